@@ -37,18 +37,37 @@
 //!
 //! - [self::ops]
 //!
+//! ファイルシステムの変更の監視は以下のモジュールにあるトレイトが行います。
+//!
+//! - [self::watch]
+//!
+//! ファイルシステムのスナップショットは以下のモジュールにあるトレイトが取ります。
+//!
+//! - [self::snapshot]
+//!
+//! `async`フィーチャーが有効な場合、これらの非同期版のトレイトが以下のモジュールにあります。
+//!
+//! - `self::async`
+//!
 //! これらは複数を合わせ持つ場合があります。
 //!
 //! ## See also
 //!
 //! - [crate::provider]
 //!
-//! # XXX: ファイルシステムの変更を伴う操作がinterior mutabilityのみを要求するだけで実現できるか不明なため、現在、exterior mutabilityを要求する
+//! # 共有
+//!
+//! ファイルシステムの変更を伴う操作を含め、すべてのトレイトメソッドは`&self`を要求します。
+//! 状態を持つ実装はinterior mutabilityによって変更を行わなければなりません。
 //!
-//! - exterior mutabilityが不要なAPIを決定する必要がある
+//! 実装が`Send + Sync`であれば、`Arc`で包むだけで`Mutex`を使わずに複数のスレッドから共有できます。
 
+#[cfg(feature = "async")]
+pub mod r#async;
 pub mod entity;
 pub mod ops;
+pub mod snapshot;
+pub mod watch;
 
 use std::path::Path;
 
@@ -0,0 +1,124 @@
+//! このモジュールには[crate::fs::FileSystem]と[crate::fs::ops]の非同期版のtraitが定義されています。
+//!
+//! `async`フィーチャーが有効な場合にのみ利用できます。
+//!
+//! 各トレイトメソッドはフューチャーを返し、ファイルのハンドルは[AsyncRead]や[AsyncWrite]を実装します。
+//! ブロックする操作をどの様に非同期化するかは実装に任されます。
+//!
+//! # See also
+//! [crate::fs]
+
+use ::{
+    std::{future::Future, path::Path},
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+
+use crate::fs::{entity, Introspect};
+
+/// [crate::fs::FileSystem]の非同期版です。
+pub trait FileSystem: Introspect {
+    type MetadataE;
+
+    /// `sub`が表す[entity::Metadata]を作ります。失敗する場合は`Err(Self::MetadataE)`を返します。
+    /// 引数`sub`はこのファイルシステムの基底パスを基準としたサブパスと見なされます。
+    fn metadata<P: AsRef<Path>>(
+        &self,
+        sub: P,
+    ) -> impl Future<Output = Result<entity::Metadata, Self::MetadataE>> + Send;
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send;
+
+    /// [crate::fs::FileSystem::is_file]の非同期版です。
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send;
+
+    /// [crate::fs::FileSystem::is_dir]の非同期版です。
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send;
+}
+
+/// [entity::Dir]の非同期版です。
+pub trait Dir {
+    type Entry: entity::DirEntry;
+    type IterE;
+    type Entries: Entries<Entry = Self::Entry, E = Self::IterE>;
+    type EntriesE;
+
+    /// ディレクトリエントリを返す`Self::Entries`。
+    fn entries(&self) -> impl Future<Output = Result<Self::Entries, Self::EntriesE>> + Send;
+}
+
+/// ディレクトリエントリを非同期に返します。[std::iter::Iterator]の非同期版です。
+pub trait Entries {
+    type Entry: entity::DirEntry;
+    type E;
+
+    /// 次のエントリを返します。エントリが無くなった場合は`Ok(None)`を返します。
+    fn next_entry(&mut self) -> impl Future<Output = Result<Option<Self::Entry>, Self::E>> + Send;
+}
+
+/// [crate::fs::ops::OpenFile]の非同期版です。
+pub trait OpenFile {
+    type File: AsyncRead + Unpin + Send;
+    type E;
+
+    /// ファイルを開きます。ファイルが存在しない場合は失敗します。
+    /// `path`が少なくとも`Readable`である必要があります。
+    fn open<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send;
+}
+
+/// [crate::fs::ops::OpenDir]の非同期版です。
+pub trait OpenDir {
+    type Dir: Dir;
+    type E;
+
+    /// ディレクトリを開きます。ディレクトリが存在しない場合は失敗します。
+    /// `path`が少なくとも`Readable`である必要があります。
+    fn open<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send;
+}
+
+/// [crate::fs::ops::CreateFile]の非同期版です。
+pub trait CreateFile {
+    type File: AsyncWrite + Unpin + Send;
+    type E;
+
+    /// 新しいファイルを作成するか、ファイルが既に存在する場合は開きます。
+    ///
+    /// `path`が少なくとも`Writable`か`Appendable`である必要があります。
+    fn create<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send;
+
+    /// 新しいファイルを作成します。ファイルが既に存在する場合は失敗します。
+    ///
+    /// `path`が少なくとも`Writable`か`Appendable`である必要がありまが、`Truncate`は無視されます。
+    ///
+    /// 不可分操作であるかは想定されません。
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send;
+}
+
+/// [crate::fs::ops::CreateDir]の非同期版です。
+pub trait CreateDir {
+    type Dir: Dir;
+    type E;
+
+    /// 新しいディレクトリを作成するか、ディレクトリが既に存在する場合は開きます。
+    /// `path`が少なくとも`Writable`である必要があります。
+    fn create<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send;
+
+    /// 新しいディレクトリを作成します。ディレクトリが既に存在する場合は失敗します。
+    /// `path`が少なくとも`Writable`である必要があります。
+    ///
+    /// 不可分操作であるかは想定されません。
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send;
+}
+
+/// [crate::fs::ops::RemoveFile]の非同期版です。
+pub trait RemoveFile {
+    type E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<(), Self::E>> + Send;
+}
+
+/// [crate::fs::ops::RemoveDir]の非同期版です。
+pub trait RemoveDir {
+    type E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<(), Self::E>> + Send;
+}
@@ -11,6 +11,56 @@ pub trait File {
     fn is_dir(&self) -> bool;
 }
 
+/// エンティティを永続化します。
+///
+/// 永続化の概念が無いファイルシステム（メモリ上のファイルシステムやアーカイブなど）では何もせずに成功します。
+pub trait Durable {
+    type E;
+
+    /// 内容とメタデータをドライブへ永続化します。
+    ///
+    /// ディレクトリの場合は、その中でのエンティティの作成や削除、リネームを永続化します。
+    fn sync_all(&self) -> Result<(), Self::E>;
+
+    /// 内容をドライブへ永続化します。メタデータは永続化されないかもしれません。
+    fn sync_data(&self) -> Result<(), Self::E> {
+        self.sync_all()
+    }
+}
+
+/// ファイルのアドバイザリロック。
+///
+/// ロックは同じファイルをロックしようとする者同士でのみ効果を持ち、ロックしない読み書きを妨げることはありません。
+/// ロックはハンドルを破棄すると解除されます。
+pub trait Lock {
+    type E;
+
+    /// 共有ロックを取得します。他のハンドルが排他ロックを持つ場合はそれが解除されるまでブロックします。
+    fn lock_shared(&self) -> Result<(), Self::E>;
+
+    /// 排他ロックを取得します。他のハンドルがロックを持つ場合はそれが解除されるまでブロックします。
+    fn lock_exclusive(&self) -> Result<(), Self::E>;
+
+    /// 共有ロックの取得を試みます。ブロックせずに、取得できなかった場合は`Ok(false)`を返します。
+    fn try_lock_shared(&self) -> Result<bool, Self::E>;
+
+    /// 排他ロックの取得を試みます。ブロックせずに、取得できなかった場合は`Ok(false)`を返します。
+    fn try_lock_exclusive(&self) -> Result<bool, Self::E>;
+
+    /// このハンドルが持つロックを解除します。
+    fn unlock(&self) -> Result<(), Self::E>;
+}
+
+/// [crate::fs::ops::ReplaceFile::replace]が返す、置き換え先に不可分に反映されるファイルです。
+pub trait AtomicFile {
+    type E;
+
+    /// 書き込んだ内容を永続化し、置き換え先のパスへ不可分に移動します。
+    ///
+    /// 失敗した場合、置き換え先は変更されません。
+    fn commit(self) -> Result<(), Self::E>;
+}
+
 /// このtraitのメソッドの呼び出しには潜在的なコストがかかる場合があります。
 pub trait Dir: File {
     type Entry: DirEntry;
@@ -29,10 +79,57 @@ pub trait Dir: File {
     fn entries(&self) -> Result<Self::Entries, Self::EntriesE>;
 }
 
+/// ディレクトリのハンドルを基準にした子エンティティの操作です。
+///
+/// 引数`name`はこのディレクトリを基準とした相対パスと見なされ、このディレクトリの外側を指すことは出来ません。
+/// ファイルシステムの基底パスからのパスを組み立てて検査し直す必要が無いので、一つのディレクトリを処理する場合に向いています。
+///
+/// 実装はディレクトリのハンドルを基準に操作するべきです（Linuxでは`openat`など）。
+/// そうすれば、操作の途中でこのディレクトリがリネームされても影響を受けません。
+pub trait DirAt: Dir + Sized {
+    type File: File;
+    type E;
+
+    /// ファイルを開きます。ファイルが存在しない場合は失敗します。
+    fn open_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E>;
+
+    /// ディレクトリを開きます。ディレクトリが存在しない場合は失敗します。
+    fn open_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E>;
+
+    /// 新しいファイルを作成するか、ファイルが既に存在する場合は開きます。
+    fn create_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E>;
+
+    /// 新しいファイルを作成します。ファイルが既に存在する場合は失敗します。
+    fn create_new_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E>;
+
+    /// 新しいディレクトリを作成するか、ディレクトリが既に存在する場合は開きます。
+    fn create_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E>;
+
+    /// ファイルを削除します。
+    fn remove_file<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E>;
+
+    /// ディレクトリをその中身ごと削除します。
+    fn remove_dir<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E>;
+
+    /// `name`が表す[Metadata]を作ります。[Metadata::path]はファイルシステムの基底パスを基準としたサブパスです。
+    fn metadata<P: AsRef<Path>>(&self, name: P) -> Result<Metadata, Self::E>;
+}
+
 /// ディレクトリ内の各エントリを表します。
 pub trait DirEntry: File {
-    /// [Dir::entries]を呼び出したディレクトリを基準とした相対パスを返します。
+    /// ファイルシステムの基底パスを基準としたサブパスを返します。
+    ///
+    /// これは[Dir::entries]を呼び出したディレクトリのサブパスに[DirEntry::file_name]を連結したものなので、
+    /// そのまま[crate::fs::FileSystem]や[crate::fs::ops]のトレイトメソッドに渡すことが出来ます。
     fn path(&self) -> std::path::PathBuf;
+
+    /// エントリのファイル名を返します。
+    fn file_name(&self) -> std::ffi::OsString;
+
+    /// エントリの種類を返します。ファイルでもディレクトリでもない場合は`None`を返します。
+    ///
+    /// ファイルシステムによってはディレクトリを読んだ時点の種類を返すので、呼び出しのコストは小さいはずです。
+    fn file_type(&self) -> Option<Type>;
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -41,15 +138,28 @@ pub enum Type {
     Dir,
 }
 
+#[derive(Debug, Clone)]
 pub struct Metadata {
     path: Box<std::path::Path>,
     r#type: Type,
     size: u64,
+    modified: Option<std::time::SystemTime>,
 }
 
 impl Metadata {
     pub fn new(path: Box<std::path::Path>, r#type: Type, size: u64) -> Self {
-        Self { path, r#type, size }
+        Self {
+            path,
+            r#type,
+            size,
+            modified: None,
+        }
+    }
+
+    /// 最終更新日時を設定します。
+    pub fn with_modified(mut self, modified: std::time::SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
     }
 
     pub fn path(&self) -> &Path {
@@ -63,4 +173,9 @@ impl Metadata {
     pub fn size(&self) -> u64 {
         self.size
     }
+
+    /// 最終更新日時です。ファイルシステムが最終更新日時を持たない場合は`None`を返します。
+    pub fn modified(&self) -> Option<std::time::SystemTime> {
+        self.modified
+    }
 }
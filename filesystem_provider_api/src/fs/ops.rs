@@ -2,6 +2,7 @@
 //!
 //! - open
 //! - create
+//! - replace
 //! - remove
 //!
 //! # See also
@@ -17,7 +18,7 @@ pub trait OpenFile {
 
     /// ファイルを開きます。ファイルが存在しない場合は失敗します。
     /// `path`が少なくとも`Readable`である必要があります。
-    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E>;
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E>;
 }
 
 pub trait OpenDir {
@@ -26,7 +27,7 @@ pub trait OpenDir {
 
     /// ディレクトリを開きます。ディレクトリが存在しない場合は失敗します。
     /// `path`が少なくとも`Readable`である必要があります。
-    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E>;
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E>;
 }
 
 pub trait CreateFile {
@@ -36,14 +37,27 @@ pub trait CreateFile {
     /// 新しいファイルを作成するか、ファイルが既に存在する場合は開きます。
     ///
     /// `path`が少なくとも`Writable`か`Appendable`である必要があります。
-    fn create<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E>;
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E>;
 
     /// 新しいファイルを作成します。ファイルが既に存在する場合は失敗します。
     ///
     /// `path`が少なくとも`Writable`か`Appendable`である必要がありまが、`Truncate`は無視されます。
     ///
     /// 不可分操作であるかは想定されません。
-    fn create_new<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E>;
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E>;
+}
+
+/// ファイルの不可分な置き換え
+pub trait ReplaceFile {
+    type File: entity::AtomicFile;
+    type E;
+
+    /// `path`を置き換えるための一時ファイルを`path`と同じディレクトリに作ります。
+    /// `path`が少なくとも`Writable`である必要があります。
+    ///
+    /// 一時ファイルに書き込んだ内容は[entity::AtomicFile::commit]を呼び出すまで`path`に反映されません。
+    /// コミットせずに`Self::File`を破棄した場合、一時ファイルは削除され`path`は変更されません。
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E>;
 }
 
 pub trait CreateDir {
@@ -52,13 +66,13 @@ pub trait CreateDir {
 
     /// 新しいファイルを作成するか、ファイルが既に存在する場合は開きます。
     /// `path`が少なくとも`Writable`である必要があります。
-    fn create<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E>;
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E>;
 
     /// 新しいファイルを作成します。ファイルが既に存在する場合は失敗します。
     /// `path`が少なくとも`Writable`である必要があります。
     ///
     /// 不可分操作であるかは想定されません。
-    fn create_new<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E>;
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E>;
 }
 
 /// ファイルの削除
@@ -0,0 +1,24 @@
+//! このモジュールにはファイルシステムのスナップショットを取るためのtraitが定義されています。
+//!
+//! スナップショットはある時点のファイルシステムの状態を読み込み専用の[crate::fs::FileSystem]として凍結したものです。
+//! スナップショットを取った後に元のファイルシステムを変更しても、スナップショットからは変更前の状態が見えます。
+//!
+//! # See also
+//! [crate::fs]
+
+use crate::fs::{ops, FileSystem};
+
+/// ファイルシステムのスナップショットを取ります。
+///
+/// スナップショットのコストはバックエンドによって異なります。
+/// 不変なツリーを共有できるバックエンドでは安価ですが、ディスクのバックエンドではツリーの複製が必要になるかもしれません。
+pub trait Snapshot {
+    /// スナップショットです。[crate::fs::Introspect]は読み込み専用であることを報告しなければなりません。
+    type Snapshot: FileSystem + ops::OpenFile + ops::OpenDir;
+    type E;
+
+    /// 現在の状態のスナップショットを取ります。
+    ///
+    /// スナップショットが保持する資源は`Self::Snapshot`を破棄すると解放されます。
+    fn snapshot(&self) -> Result<Self::Snapshot, Self::E>;
+}
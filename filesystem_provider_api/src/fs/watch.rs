@@ -0,0 +1,47 @@
+//! このモジュールにはファイルシステムの変更を監視するためのtraitが定義されています。
+//!
+//! 監視によって得られる[Event]が持つパスはすべてファイルシステムの基底パスを基準としたサブパスです。
+//! 基底パスの外側で起きた変更が報告されることはありません。
+//!
+//! # See also
+//! [crate::fs]
+
+use ::std::path::{Path, PathBuf};
+
+/// ファイルシステムの変更を表すイベント。
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Event {
+    /// エンティティが作られた。監視対象の外側から移動してきた場合も含みます。
+    Create(PathBuf),
+    /// エンティティの内容が変更された。
+    Modify(PathBuf),
+    /// エンティティが削除された。監視対象の外側へ移動した場合も含みます。
+    Remove(PathBuf),
+    /// 監視対象の内側でエンティティが移動した。
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl Event {
+    /// イベントが起きたエンティティのサブパスを返します。[Event::Rename]の場合は移動先のサブパスです。
+    pub fn path(&self) -> &Path {
+        match self {
+            Event::Create(path) | Event::Modify(path) | Event::Remove(path) => path,
+            Event::Rename { to, .. } => to,
+        }
+    }
+}
+
+/// ファイルシステムの変更を監視します。
+///
+/// バックエンドに関わらず同じ[Event]を報告しなければなりません。
+pub trait Watch {
+    type Watcher: std::iter::Iterator<Item = Result<Event, Self::IterE>>;
+    type IterE;
+    type E;
+
+    /// `path`とその下階の変更を監視する`Self::Watcher`イテレータを返します。
+    /// 引数`path`はこのファイルシステムの基底パスを基準としたサブパスと見なされます。
+    ///
+    /// イテレータは次のイベントが起きるまでブロックするかもしれません。
+    fn watch<P: AsRef<Path>>(&self, path: P) -> Result<Self::Watcher, Self::E>;
+}
@@ -4,7 +4,11 @@
 //!
 //! # ファイルシステムとプロバイダ
 //! 実際のファイルシステムは[fs]モジュールにあるtraitによって抽象化されます。プロバイダはファイルシステムを作るためのファクトリです。
+//!
+//! # パス
+//! ホストのパスの意味に依存しない相対パスは[path::PortablePath]で表します。
 pub mod fs;
+pub mod path;
 pub mod permission;
 pub mod provider;
 
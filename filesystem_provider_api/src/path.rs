@@ -0,0 +1,278 @@
+//! ホストの[Path]に依存しないパスに関するモジュール。
+//!
+//! [PortablePath]はファイルシステムの基底パスからの相対パスを`/`区切りで表し、常に正規化されています。
+//!
+//! - `.`と空のコンポーネントは取り除かれ、`..`は字句的に解決されます。
+//! - 基底パスの外を指すパスや絶対パス、ドライブなどのプレフィックスを持つパスは作れません。
+//! - コンポーネントはUTF-8で、`/`と`\`、`:`、NULを含みません。`:`はWindowsでドライブや代替データストリームを表します。
+//!
+//! [PortablePath]は`AsRef<Path>`を実装するので、そのまま[crate::fs]のトレイトメソッドに渡せます。
+//! 基底パスそのものは`.`として渡されます。
+//!
+//! 作るエンティティの名前を検査するポリシーは以下のモジュールにあります。
+//!
+//! - [self::policy]
+
+pub mod policy;
+
+use ::std::{
+    convert::TryFrom,
+    fmt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum PathError {
+    #[error("absolute path {0:?}")]
+    AbsoluteError(PathBuf),
+    #[error("out of root {0:?}")]
+    OutOfRootError(PathBuf),
+    #[error("not unicode {0:?}")]
+    NotUnicodeError(PathBuf),
+    #[error("invalid component {0:?}")]
+    InvalidComponentError(String),
+}
+
+/// 正規化された、基底パスからの相対パスです。
+///
+/// 空のパスは基底パスそのものを表します。
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PortablePath(String);
+
+impl PortablePath {
+    /// 基底パスそのものを表すパスを作ります。
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// このパスが基底パスそのものか調べます。
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `/`区切りの文字列を返します。基底パスそのものは空の文字列です。
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// コンポーネントを先頭から返します。
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.0.split('/').filter(|name| !name.is_empty())
+    }
+
+    /// 最後のコンポーネントを返します。基底パスそのものの場合は`None`です。
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().next_back()
+    }
+
+    /// 親のパスを返します。基底パスそのものの場合は`None`です。
+    pub fn parent(&self) -> Option<Self> {
+        let mut parent = self.clone();
+        match parent.pop() {
+            true => Some(parent),
+            false => None,
+        }
+    }
+
+    /// `/`区切りの相対パスを解決して連結します。
+    pub fn join<S: AsRef<str>>(&self, path: S) -> Result<Self, PathError> {
+        let mut joined = self.clone();
+        joined.push(path)?;
+        Ok(joined)
+    }
+
+    /// `/`区切りの相対パスを解決して連結します。失敗した場合、このパスは変更されません。
+    pub fn push<S: AsRef<str>>(&mut self, path: S) -> Result<(), PathError> {
+        let path = path.as_ref();
+        if path.starts_with('/') {
+            return Err(PathError::AbsoluteError(path.into()));
+        }
+        let mut pushed = self.clone();
+        for name in path.split('/') {
+            match name {
+                "" | "." => (),
+                ".." => {
+                    if !pushed.pop() {
+                        return Err(PathError::OutOfRootError(self.to_path_buf().join(path)));
+                    }
+                },
+                name => pushed.push_name(name)?,
+            }
+        }
+        *self = pushed;
+        Ok(())
+    }
+
+    /// 最後のコンポーネントを取り除きます。基底パスそのものの場合は`false`を返します。
+    pub fn pop(&mut self) -> bool {
+        match self.0.rfind('/') {
+            Some(index) => self.0.truncate(index),
+            None if self.is_root() => return false,
+            None => self.0.clear(),
+        }
+        true
+    }
+
+    /// このパスが`base`か、その下階か調べます。
+    pub fn starts_with(&self, base: &PortablePath) -> bool {
+        let mut components = self.components();
+        base.components().all(|name| components.next() == Some(name))
+    }
+
+    /// ホストのパスに変換します。基底パスそのものは`.`です。
+    pub fn to_path_buf(&self) -> PathBuf {
+        self.as_ref().to_path_buf()
+    }
+
+    /// コンポーネントを一つ連結します。ホストのパスとしても一つの通常のコンポーネントでなければなりません。
+    fn push_name(&mut self, name: &str) -> Result<(), PathError> {
+        let mut components = Path::new(name).components();
+        let single = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(component)), None) if component == name
+        );
+        if !single || name.contains(['/', '\\', ':', '\0']) {
+            return Err(PathError::InvalidComponentError(name.to_owned()));
+        }
+        if !self.is_root() {
+            self.0.push('/');
+        }
+        self.0.push_str(name);
+        Ok(())
+    }
+}
+
+impl fmt::Display for PortablePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<Path> for PortablePath {
+    fn as_ref(&self) -> &Path {
+        match self.is_root() {
+            true => Path::new("."),
+            false => Path::new(&self.0),
+        }
+    }
+}
+
+impl FromStr for PortablePath {
+    type Err = PathError;
+
+    /// `/`区切りの相対パスを解決します。
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        Self::root().join(path)
+    }
+}
+
+impl TryFrom<&Path> for PortablePath {
+    type Error = PathError;
+
+    /// ホストのパスを変換します。ルートディレクトリやプレフィックスを持つパスは[PathError::AbsoluteError]で失敗します。
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        let mut portable = Self::root();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = name
+                        .to_str()
+                        .ok_or_else(|| PathError::NotUnicodeError(path.to_owned()))?;
+                    portable.push_name(name)?;
+                },
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    if !portable.pop() {
+                        return Err(PathError::OutOfRootError(path.to_owned()));
+                    }
+                },
+                Component::RootDir | Component::Prefix(_) => return Err(PathError::AbsoluteError(path.to_owned())),
+            }
+        }
+        Ok(portable)
+    }
+}
+
+impl TryFrom<PathBuf> for PortablePath {
+    type Error = PathError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        Self::try_from(path.as_path())
+    }
+}
+
+impl From<PortablePath> for PathBuf {
+    fn from(path: PortablePath) -> Self {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod portable {
+    use ::std::{
+        convert::TryFrom,
+        path::{Path, PathBuf},
+    };
+
+    use crate::path::{PathError, PortablePath};
+
+    #[test]
+    fn normalize() -> Result<(), PathError> {
+        let path: PortablePath = "./assets//images/../logo.png/".parse()?;
+        assert_eq!(path.as_str(), "assets/logo.png");
+        assert_eq!(path.components().collect::<Vec<_>>(), ["assets", "logo.png"]);
+        assert_eq!(path.file_name(), Some("logo.png"));
+        assert_eq!(path.parent(), Some("assets".parse()?));
+        assert!(path.starts_with(&"assets".parse()?));
+        assert!(!path.starts_with(&"asset".parse()?));
+
+        let root: PortablePath = "a/..".parse()?;
+        assert!(root.is_root() && root.parent().is_none());
+        assert_eq!(root.as_ref(), Path::new("."));
+        assert_eq!(PortablePath::try_from(Path::new("a/./b/../c"))?.to_string(), "a/c");
+        Ok(())
+    }
+
+    #[test]
+    fn reject() {
+        assert!(matches!(
+            "/etc".parse::<PortablePath>(),
+            Err(PathError::AbsoluteError(_))
+        ));
+        assert!(matches!(
+            "a/../..".parse::<PortablePath>(),
+            Err(PathError::OutOfRootError(_))
+        ));
+        for path in ["a\\b", "C:x", "a/C:", "a/b:stream"] {
+            assert!(matches!(
+                path.parse::<PortablePath>(),
+                Err(PathError::InvalidComponentError(_))
+            ));
+        }
+        assert!(matches!(
+            PortablePath::try_from(PathBuf::from("/etc")),
+            Err(PathError::AbsoluteError(_))
+        ));
+        assert!(matches!(
+            PortablePath::try_from(Path::new("..")),
+            Err(PathError::OutOfRootError(_))
+        ));
+
+        let mut path: PortablePath = "a".parse().unwrap();
+        assert!(path.push("../../b").is_err());
+        assert_eq!(path.as_str(), "a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_unicode() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+        let path = Path::new(OsStr::from_bytes(b"a/\xff"));
+        assert!(matches!(
+            PortablePath::try_from(path),
+            Err(PathError::NotUnicodeError(_))
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+//! 作るエンティティのパスを検査するポリシーに関するモジュール。
+//!
+//! 基底パスの外を指すパスの拒否とは別に、他のプラットフォームで扱えない名前を拒否するために使います。
+//! 例えばLinuxでは`CON`や`a.`という名前のファイルを作れますが、Windowsではそれらを展開できません。
+//!
+//! 実装は[Policy]を実装し、エンティティを作る前に[Policy::check_new]を呼び出します。
+//! 既にあるエンティティを開いたり削除したりする場合は検査しません。
+//! 作るエンティティのパスの途中にある既にあるディレクトリや、既にあるエンティティを開き直す場合の名前も検査しません。
+//!
+//! - [Permissive] 何も拒否しません。
+//! - [Portable] 主要なプラットフォームで扱える名前だけを許します。
+
+use ::std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum PolicyError {
+    #[error("reserved name {0:?}")]
+    ReservedNameError(OsString),
+    #[error("trailing dot or space {0:?}")]
+    TrailingCharError(OsString),
+    #[error("control character {0:?}")]
+    ControlCharError(OsString),
+    #[error("special character {0:?}")]
+    SpecialCharError(OsString),
+    #[error("name too long {0:?}")]
+    NameTooLongError(OsString),
+    #[error("path too long {0:?}")]
+    PathTooLongError(PathBuf),
+}
+
+/// エンティティの名前とパスを検査します。
+///
+/// ファイルシステムが`Send + Sync`であり続けられるように、実装も`Send + Sync`でなければなりません。
+pub trait Policy: fmt::Debug + Send + Sync {
+    /// パスの一つのコンポーネントを検査します。
+    fn check_name(&self, name: &OsStr) -> Result<(), PolicyError>;
+
+    /// 基底パスを基準としたパスを検査します。すべてのコンポーネントを作る場合の[Policy::check_new]です。
+    fn check(&self, path: &Path) -> Result<(), PolicyError> {
+        self.check_new(path, 0)
+    }
+
+    /// 先頭の`existing`個の通常のコンポーネントが既にあるパスを検査します。
+    ///
+    /// 既定では残りの通常のコンポーネントをそれぞれ[Policy::check_name]で検査します。
+    fn check_new(&self, path: &Path, existing: usize) -> Result<(), PolicyError> {
+        normal_names(path)
+            .skip(existing)
+            .try_for_each(|name| self.check_name(name))
+    }
+}
+
+fn normal_names(path: &Path) -> impl Iterator<Item = &OsStr> {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    })
+}
+
+/// 何も拒否しないポリシーです。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Permissive;
+
+impl Policy for Permissive {
+    fn check_name(&self, _: &OsStr) -> Result<(), PolicyError> {
+        Ok(())
+    }
+}
+
+/// Windowsで予約されているデバイス名です。拡張子が付いていても予約されています。
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Windowsで名前に使えない、制御文字以外の文字です。
+const SPECIAL_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// 主要なプラットフォームで扱える名前だけを許すポリシーです。各検査は個別に無効にできます。
+///
+/// 長さはUTF-8のバイト数で数えます。UTF-8でない名前は置換文字に置き換えてから検査します。
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Portable {
+    /// `true`の場合、`CON`や`NUL.txt`などの予約されたデバイス名を大文字と小文字を区別せずに拒否します。
+    pub reserved_names: bool,
+    /// `true`の場合、`.`か空白で終わる名前を拒否します。
+    pub trailing_chars: bool,
+    /// `true`の場合、制御文字を含む名前を拒否します。
+    pub control_chars: bool,
+    /// `true`の場合、`<>:"\|?*`を含む名前を拒否します。
+    pub special_chars: bool,
+    /// 名前の最大の長さです。
+    pub max_name_len: Option<usize>,
+    /// パス全体の最大の長さです。
+    pub max_path_len: Option<usize>,
+}
+
+/// すべての検査を有効にし、名前を255バイト、パスを1024バイトに制限します。
+impl Default for Portable {
+    fn default() -> Self {
+        Self {
+            reserved_names: true,
+            trailing_chars: true,
+            control_chars: true,
+            special_chars: true,
+            max_name_len: Some(255),
+            max_path_len: Some(1024),
+        }
+    }
+}
+
+impl Policy for Portable {
+    fn check_name(&self, name: &OsStr) -> Result<(), PolicyError> {
+        let lossy = name.to_string_lossy();
+        if self.max_name_len.is_some_and(|max| lossy.len() > max) {
+            return Err(PolicyError::NameTooLongError(name.to_owned()));
+        }
+        if self.control_chars && lossy.chars().any(char::is_control) {
+            return Err(PolicyError::ControlCharError(name.to_owned()));
+        }
+        if self.special_chars && lossy.contains(SPECIAL_CHARS) {
+            return Err(PolicyError::SpecialCharError(name.to_owned()));
+        }
+        if self.trailing_chars && lossy.ends_with(['.', ' ']) && lossy != "." && lossy != ".." {
+            return Err(PolicyError::TrailingCharError(name.to_owned()));
+        }
+        if self.reserved_names {
+            let stem = lossy.split('.').next().unwrap_or_default().trim_end_matches(' ');
+            if RESERVED_NAMES
+                .iter()
+                .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+            {
+                return Err(PolicyError::ReservedNameError(name.to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    /// パス全体の長さは既にあるコンポーネントも含めて検査します。
+    fn check_new(&self, path: &Path, existing: usize) -> Result<(), PolicyError> {
+        let mut len = 0;
+        for (i, name) in normal_names(path).enumerate() {
+            if i >= existing {
+                self.check_name(name)?;
+            }
+            // 区切り文字の分を含める。
+            len += name.len() + usize::from(len > 0);
+        }
+        match self.max_path_len {
+            Some(max) if len > max => Err(PolicyError::PathTooLongError(path.to_owned())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod portable_names {
+    use ::std::{ffi::OsStr, path::Path};
+
+    use crate::path::policy::{Permissive, Policy as _, PolicyError, Portable};
+
+    #[test]
+    fn reject() {
+        let policy = Portable::default();
+        let check = |name: &str| policy.check_name(OsStr::new(name));
+
+        for name in ["con", "NUL", "Aux.txt", "com1.tar.gz", "LPT9 .txt"] {
+            assert!(
+                matches!(check(name), Err(PolicyError::ReservedNameError(_))),
+                "{}",
+                name
+            );
+        }
+        for name in ["a.", "a ", "a. "] {
+            assert!(
+                matches!(check(name), Err(PolicyError::TrailingCharError(_))),
+                "{}",
+                name
+            );
+        }
+        assert!(matches!(check("a\tb"), Err(PolicyError::ControlCharError(_))));
+        assert!(matches!(check("a:b"), Err(PolicyError::SpecialCharError(_))));
+        assert!(matches!(check(&"a".repeat(256)), Err(PolicyError::NameTooLongError(_))));
+
+        let long = vec!["a".repeat(200); 6].join("/");
+        assert!(matches!(
+            policy.check(Path::new(&long)),
+            Err(PolicyError::PathTooLongError(_))
+        ));
+        assert!(policy.check(Path::new("./src/CON/x")).is_err());
+        assert!(policy.check_new(Path::new("./src/CON/x"), 1).is_err());
+        assert!(matches!(
+            policy.check_new(Path::new(&long), 6),
+            Err(PolicyError::PathTooLongError(_))
+        ));
+    }
+
+    #[test]
+    fn accept() {
+        let policy = Portable::default();
+        for name in ["console", "COM10", ".gitignore", "a.b", "日本語"] {
+            assert!(policy.check_name(OsStr::new(name)).is_ok(), "{}", name);
+        }
+        assert!(policy.check(Path::new("./a/../b/c.txt")).is_ok());
+        assert!(policy.check_new(Path::new("CON/aux/a.txt"), 2).is_ok());
+
+        let relaxed = Portable {
+            reserved_names: false,
+            trailing_chars: false,
+            ..Portable::default()
+        };
+        assert!(relaxed.check(Path::new("CON/a.")).is_ok());
+        assert!(Permissive.check(Path::new("NUL/a.")).is_ok());
+        assert!(Permissive.check_new(Path::new("NUL/a."), 1).is_ok());
+    }
+}
@@ -0,0 +1,1142 @@
+//! 内容アドレスのオブジェクトストアの上に実装されたファイルシステム。
+//!
+//! ファイルの内容はオブジェクトとして保存され、同じ内容のファイルは一つのオブジェクトを共有します。
+//! ディレクトリツリーはツリーオブジェクトとして保存され、パスによるアクセスはルートのツリーから辿って解決されます。
+//! ストアのディレクトリ構成は`store`モジュールのドキュメントにあります。
+//!
+//! # 書き込み
+//!
+//! [api_ops::CreateFile]が返す[Writer]は書き込んだ内容を一時ファイルに溜め、
+//! [io::Write::flush]か[Writer::finish]を呼び出した時にオブジェクトとして保存しツリーに反映します。
+//! 反映されていない内容は破棄すると捨てられます。
+//!
+//! ツリーの更新はルートまでのツリーオブジェクトを書き直し、`HEAD`を不可分に置き換えることで行います。
+//! 更新は`lock`ファイルの排他ロックによって直列化されるので、複数のスレッドやプロセスから同じストアに書き込めます。
+//! 読み込みはロックを取らず、その時点の`HEAD`から辿ります。
+//!
+//! オブジェクトは不変なので、ファイルへの追記はサポートしません。
+
+use ::{
+    filesystem_provider_api::{
+        fs::{self as api_fs, entity as api_entity, ops as api_ops, snapshot as api_snapshot},
+        path::policy::{Permissive, Policy, PolicyError},
+    },
+    sha2::Digest as _,
+    std::{
+        collections::HashSet,
+        ffi::OsString,
+        io,
+        path::{Component, Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+pub use crate::store::ObjectId;
+use crate::store::{Entry, Store, Tree};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("out of access {0:?}")]
+    AccessError(PathBuf),
+    #[error("entity not found {0:?}")]
+    NotFoundError(PathBuf),
+    #[error("not a directory {0:?}")]
+    NotDirError(PathBuf),
+    #[error("not a file {0:?}")]
+    NotFileError(PathBuf),
+    #[error("entity already exists {0:?}")]
+    AlreadyExistsError(PathBuf),
+    #[error("corrupt object {0}")]
+    CorruptError(ObjectId),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    PolicyError(#[from]#[source]PolicyError),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    IoError(#[from]#[source]io::Error),
+}
+
+/// オブジェクトの内容を読むファイルです。オブジェクトは不変なので書き込むことは出来ません。
+#[derive(Debug)]
+pub struct File {
+    file: std::fs::File,
+    size: u64,
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl api_entity::File for File {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+/// 書き込み中の内容です。破棄すると一時ファイルは削除されます。
+#[derive(Debug)]
+struct Staging {
+    filesystem: FileSystem,
+    path: PathBuf,
+    file: std::fs::File,
+    temp: PathBuf,
+    hasher: sha2::Sha256,
+    size: u64,
+}
+
+impl Staging {
+    fn new(filesystem: &FileSystem, path: &Path) -> Result<Self, Error> {
+        let (file, temp) = filesystem.store.temp()?;
+        Ok(Self {
+            filesystem: filesystem.clone(),
+            path: path.to_path_buf(),
+            file,
+            temp,
+            hasher: sha2::Sha256::new(),
+            size: 0,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = io::Write::write(&mut self.file, buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    /// 内容をオブジェクトとして保存し、`path`のエントリをそのオブジェクトに置き換えます。
+    /// 一時ファイルはオブジェクトになるので、続けて書き込む場合は先に[Staging::reopen]を呼び出さなければなりません。
+    fn publish(&mut self) -> Result<(), Error> {
+        self.file.sync_all()?;
+        let entry = Entry {
+            r#type: api_entity::Type::File,
+            id: ObjectId::from_hasher(self.hasher.clone()),
+            size: self.size,
+        };
+
+        let filesystem = &self.filesystem;
+        filesystem.update(&self.path, false, |tree, name| {
+            if let Some(api_entity::Type::Dir) = tree.get(name).map(|entry| &entry.r#type) {
+                return Err(Error::NotFileError(self.path.clone()));
+            }
+            filesystem.store.put(&self.temp, &entry.id)?;
+            tree.insert(name.to_owned(), entry);
+            Ok(())
+        })
+    }
+
+    /// 保存したオブジェクトの内容を新しい一時ファイルに複製し、以降の書き込み先にします。
+    fn reopen(&mut self) -> Result<(), Error> {
+        let id = ObjectId::from_hasher(self.hasher.clone());
+        let (file, temp) = self.filesystem.store.temp()?;
+        // オブジェクトが既にあった場合は古い一時ファイルが残っている。
+        let _ = std::fs::remove_file(std::mem::replace(&mut self.temp, temp));
+        self.file = file;
+        io::copy(&mut self.filesystem.store.open(&id)?, &mut self.file)?;
+        Ok(())
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp);
+    }
+}
+
+/// [api_ops::CreateFile]が返すファイルです。
+///
+/// 書き込んだ内容は[io::Write::flush]か[Writer::finish]を呼び出した時にツリーに反映されます。
+/// 反映した後も続けて書き込めます。反映されていない内容は破棄すると捨てられます。
+#[derive(Debug)]
+pub struct Writer {
+    staging: Staging,
+    // 最後に反映した後に書き込んだ場合は`true`。
+    dirty: bool,
+    // 一時ファイルがオブジェクトとして保存された場合は`true`。
+    published: bool,
+}
+
+impl Writer {
+    fn new(staging: Staging) -> Self {
+        Self {
+            staging,
+            dirty: false,
+            published: false,
+        }
+    }
+
+    fn publish(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            self.staging.publish()?;
+            self.dirty = false;
+            self.published = true;
+        }
+        Ok(())
+    }
+
+    /// 書き込んだ内容をオブジェクトとして保存し、ツリーに反映します。
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.publish()
+    }
+}
+
+impl io::Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.published {
+            self.staging.reopen().map_err(into_io_error)?;
+            self.published = false;
+        }
+        let n = self.staging.write(buf)?;
+        self.dirty = true;
+        Ok(n)
+    }
+
+    /// 書き込んだ内容をオブジェクトとして保存し、ツリーに反映します。
+    fn flush(&mut self) -> io::Result<()> {
+        self.publish().map_err(into_io_error)
+    }
+}
+
+fn into_io_error(err: Error) -> io::Error {
+    match err {
+        Error::IoError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+impl api_entity::File for Writer {
+    fn size(&self) -> u64 {
+        self.staging.size
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+/// [api_ops::ReplaceFile]が返すファイルです。[api_entity::AtomicFile::commit]を呼び出すまでツリーに反映されません。
+#[derive(Debug)]
+pub struct AtomicFile(Staging);
+
+impl io::Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.file.flush()
+    }
+}
+
+impl api_entity::File for AtomicFile {
+    fn size(&self) -> u64 {
+        self.0.size
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+impl api_entity::AtomicFile for AtomicFile {
+    type E = Error;
+
+    fn commit(mut self) -> Result<(), Self::E> {
+        self.0.publish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    sub: PathBuf,
+    name: String,
+    entry: Entry,
+}
+
+impl api_entity::File for DirEntry {
+    fn size(&self) -> u64 {
+        self.entry.size
+    }
+
+    fn is_file(&self) -> bool {
+        self.entry.r#type == api_entity::Type::File
+    }
+
+    fn is_dir(&self) -> bool {
+        self.entry.r#type == api_entity::Type::Dir
+    }
+}
+
+impl api_entity::DirEntry for DirEntry {
+    fn path(&self) -> PathBuf {
+        self.sub.join(&self.name)
+    }
+
+    fn file_name(&self) -> OsString {
+        OsString::from(&self.name)
+    }
+
+    fn file_type(&self) -> Option<api_entity::Type> {
+        Some(self.entry.r#type.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct DirEntries(std::vec::IntoIter<DirEntry>);
+
+impl std::iter::Iterator for DirEntries {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+/// ディレクトリです。開いた時点のツリーを保持するので、その後の変更は反映されません。
+#[derive(Debug)]
+pub struct Dir {
+    tree: Tree,
+    sub: PathBuf,
+    size: u64,
+}
+
+impl api_entity::File for Dir {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn is_file(&self) -> bool {
+        false
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+}
+
+impl api_entity::Dir for Dir {
+    type Entries = DirEntries;
+    type EntriesE = Error;
+    type Entry = DirEntry;
+    type IterE = Error;
+
+    fn total_size(&self) -> u64 {
+        self.tree.values().map(|entry| entry.size).sum()
+    }
+
+    fn count(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn entries(&self) -> Result<Self::Entries, Self::EntriesE> {
+        let entries = self
+            .tree
+            .iter()
+            .map(|(name, entry)| DirEntry {
+                sub: self.sub.clone(),
+                name: name.clone(),
+                entry: entry.clone(),
+            })
+            .collect::<Vec<_>>();
+        Ok(DirEntries(entries.into_iter()))
+    }
+}
+
+/// パスをルートのツリーから辿る名前の列にします。ルートの外側を指すパスや、UTF-8でない名前は失敗します。
+fn components(path: &Path) -> Result<Vec<String>, Error> {
+    let access_error = || Error::AccessError(path.to_path_buf());
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                names.pop().ok_or_else(access_error)?;
+            },
+            Component::Normal(name) => names.push(name.to_str().ok_or_else(access_error)?.to_owned()),
+            Component::RootDir | Component::Prefix(_) => return Err(access_error()),
+        }
+    }
+    Ok(names)
+}
+
+/// あるルートのツリーから辿ってエンティティを読みます。
+struct View<'a> {
+    store: &'a Store,
+    root: ObjectId,
+}
+
+impl View<'_> {
+    /// `path`のエントリを探します。ルートディレクトリは大きさが0のディレクトリとして返されます。
+    fn entry(&self, path: &Path) -> Result<Entry, Error> {
+        let mut entry = Entry {
+            r#type: api_entity::Type::Dir,
+            id: self.root,
+            size: 0,
+        };
+        for name in components(path)? {
+            if entry.r#type != api_entity::Type::Dir {
+                return Err(Error::NotDirError(path.to_path_buf()));
+            }
+            entry = self
+                .store
+                .tree(&entry.id)?
+                .remove(&name)
+                .ok_or_else(|| Error::NotFoundError(path.to_path_buf()))?;
+        }
+        Ok(entry)
+    }
+
+    fn open_file(&self, path: &Path) -> Result<File, Error> {
+        let entry = self.entry(path)?;
+        if entry.r#type != api_entity::Type::File {
+            return Err(Error::NotFileError(path.to_path_buf()));
+        }
+        Ok(File {
+            file: self.store.open(&entry.id)?,
+            size: entry.size,
+        })
+    }
+
+    fn open_dir(&self, path: &Path) -> Result<Dir, Error> {
+        let entry = self.entry(path)?;
+        if entry.r#type != api_entity::Type::Dir {
+            return Err(Error::NotDirError(path.to_path_buf()));
+        }
+        Ok(Dir {
+            tree: self.store.tree(&entry.id)?,
+            sub: path.to_path_buf(),
+            size: entry.size,
+        })
+    }
+}
+
+/// 作るエンティティのパスは[Policy]で検査されます。既定のポリシーは[Permissive]です。
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    pub(crate) store: Store,
+    policy: Arc<dyn Policy>,
+}
+
+impl FileSystem {
+    pub(crate) fn new(store: Store) -> Self {
+        Self {
+            store,
+            policy: Arc::new(Permissive),
+        }
+    }
+
+    /// 作るエンティティのパスを`policy`で検査するファイルシステムを返します。
+    pub fn with_policy<P: Policy + 'static>(self, policy: P) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    pub fn policy(&self) -> &dyn Policy {
+        &*self.policy
+    }
+
+    /// 作るエンティティの`path`を[Policy]で検査します。既にあるコンポーネントの名前は検査しません。
+    fn check_new(&self, path: &Path) -> Result<(), Error> {
+        let names = components(path)?;
+        let view = self.view()?;
+        let mut prefix = PathBuf::new();
+        let existing = names
+            .iter()
+            .take_while(|name| {
+                prefix.push(name);
+                view.entry(&prefix).is_ok()
+            })
+            .count();
+        self.policy
+            .check_new(&names.iter().collect::<PathBuf>(), existing)
+            .map_err(Into::into)
+    }
+
+    /// 現在の`HEAD`から辿ります。
+    fn view(&self) -> Result<View<'_>, Error> {
+        Ok(View {
+            store: &self.store,
+            root: self.store.head()?,
+        })
+    }
+
+    fn entry(&self, path: &Path) -> Result<Entry, Error> {
+        self.view()?.entry(path)
+    }
+
+    /// `path`の親ディレクトリのツリーを`f`で変更し、ルートまでのツリーを書き直して`HEAD`を更新します。
+    /// `f`には親ディレクトリのツリーと`path`のファイル名が渡されます。
+    ///
+    /// `create_parents`が`true`の場合、存在しない親ディレクトリを作ります。
+    fn update<F>(&self, path: &Path, create_parents: bool, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Tree, &str) -> Result<(), Error>,
+    {
+        let names = components(path)?;
+        let (name, parents) = names
+            .split_last()
+            .ok_or_else(|| Error::AccessError(path.to_path_buf()))?;
+
+        let _lock = self.store.lock()?;
+        let head = self.store.head()?;
+        let root = self.update_tree(&head, parents, path, create_parents, |tree| f(tree, name))?;
+        self.store.set_head(&root.id).map_err(Into::into)
+    }
+
+    fn update_tree<F>(
+        &self,
+        id: &ObjectId,
+        parents: &[String],
+        path: &Path,
+        create_parents: bool,
+        f: F,
+    ) -> Result<Entry, Error>
+    where
+        F: FnOnce(&mut Tree) -> Result<(), Error>,
+    {
+        let mut tree = self.store.tree(id)?;
+        match parents.split_first() {
+            None => f(&mut tree)?,
+            Some((name, parents)) => {
+                let child = match tree.get(name) {
+                    Some(entry) if entry.r#type == api_entity::Type::Dir => entry.id,
+                    Some(_) => return Err(Error::NotDirError(path.to_path_buf())),
+                    None if create_parents => ObjectId::empty_tree(),
+                    None => return Err(Error::NotFoundError(path.to_path_buf())),
+                };
+                let child = self.update_tree(&child, parents, path, create_parents, f)?;
+                tree.insert(name.clone(), child);
+            },
+        }
+        self.store.put_tree(&tree).map_err(Into::into)
+    }
+
+    fn create_file_impl(&self, path: &Path, new: bool) -> Result<Writer, Error> {
+        self.check_new(path)?;
+        self.update(path, false, |tree, name| {
+            match tree.get(name).map(|entry| &entry.r#type) {
+                Some(_) if new => return Err(Error::AlreadyExistsError(path.to_path_buf())),
+                Some(api_entity::Type::Dir) => return Err(Error::NotFileError(path.to_path_buf())),
+                _ => (),
+            }
+            let empty = Entry {
+                r#type: api_entity::Type::File,
+                id: self.store.put_bytes(&[])?,
+                size: 0,
+            };
+            tree.insert(name.to_owned(), empty);
+            Ok(())
+        })?;
+        Staging::new(self, path).map(Writer::new)
+    }
+
+    fn create_dir_impl(&self, path: &Path, new: bool) -> Result<Dir, Error> {
+        self.check_new(path)?;
+        self.update(path, !new, |tree, name| {
+            match tree.get(name).map(|entry| &entry.r#type) {
+                Some(_) if new => Err(Error::AlreadyExistsError(path.to_path_buf())),
+                Some(api_entity::Type::Dir) => Ok(()),
+                Some(api_entity::Type::File) => Err(Error::NotDirError(path.to_path_buf())),
+                None => {
+                    tree.insert(name.to_owned(), self.store.put_tree(&Tree::new())?);
+                    Ok(())
+                },
+            }
+        })?;
+        self.view()?.open_dir(path)
+    }
+
+    fn remove_impl(&self, path: &Path, r#type: api_entity::Type) -> Result<(), Error> {
+        self.update(path, false, |tree, name| match tree.get(name) {
+            Some(entry) if entry.r#type == r#type => {
+                tree.remove(name);
+                Ok(())
+            },
+            Some(_) if r#type == api_entity::Type::File => Err(Error::NotFileError(path.to_path_buf())),
+            Some(_) => Err(Error::NotDirError(path.to_path_buf())),
+            None => Err(Error::NotFoundError(path.to_path_buf())),
+        })
+    }
+
+    /// `path`のファイルかディレクトリのオブジェクトのIDを返します。同じ内容のファイルは同じIDを持ちます。
+    pub fn object_id<P: AsRef<Path>>(&self, path: P) -> Result<ObjectId, Error> {
+        self.entry(path.as_ref()).map(|entry| entry.id)
+    }
+
+    /// ルートのツリーと生きている[Snapshot]のツリーから辿れないオブジェクトを削除し、削除した数を返します。
+    ///
+    /// 削除の前に開いた[Dir]や[File]が古いツリーのオブジェクトを参照している場合、それらは読めなくなるかもしれません。
+    /// 異常終了したプロセスのスナップショットは`refs/`に残り、そのオブジェクトは削除されません。
+    pub fn gc(&self) -> Result<usize, Error> {
+        fn mark(store: &Store, id: ObjectId, reachable: &mut HashSet<ObjectId>) -> Result<(), Error> {
+            reachable.insert(id);
+            for entry in store.tree(&id)?.into_values() {
+                match entry.r#type {
+                    api_entity::Type::File => {
+                        reachable.insert(entry.id);
+                    },
+                    api_entity::Type::Dir => mark(store, entry.id, reachable)?,
+                }
+            }
+            Ok(())
+        }
+
+        let _lock = self.store.lock()?;
+        let mut reachable = HashSet::new();
+        mark(&self.store, self.store.head()?, &mut reachable)?;
+        for pin in self.store.pins()? {
+            mark(&self.store, pin, &mut reachable)?;
+        }
+        self.store.sweep(&reachable).map_err(Into::into)
+    }
+}
+
+impl api_fs::Introspect for FileSystem {
+    fn is_readable(&self) -> bool {
+        true
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn is_appendable(&self) -> bool {
+        false
+    }
+
+    fn is_truncatable(&self) -> bool {
+        true
+    }
+
+    fn is_removable(&self) -> bool {
+        true
+    }
+}
+
+/// [api_snapshot::Snapshot::snapshot]が返す、ある時点のツリーを固定した読み込み専用のファイルシステムです。
+///
+/// ツリーを複製せずにルートのツリーオブジェクトのIDを保持するだけなので、スナップショットは安価です。
+/// スナップショットが生きている間、そのツリーのオブジェクトは[FileSystem::gc]で削除されません。
+#[derive(Debug)]
+pub struct Snapshot {
+    store: Store,
+    root: ObjectId,
+    pin: PathBuf,
+}
+
+impl Snapshot {
+    fn view(&self) -> Result<View<'_>, Error> {
+        Ok(View {
+            store: &self.store,
+            root: self.root,
+        })
+    }
+
+    /// このスナップショットのルートのツリーオブジェクトのIDです。
+    pub fn root(&self) -> ObjectId {
+        self.root
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.pin);
+    }
+}
+
+impl api_fs::Introspect for Snapshot {
+    fn is_readable(&self) -> bool {
+        true
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    fn is_appendable(&self) -> bool {
+        false
+    }
+
+    fn is_truncatable(&self) -> bool {
+        false
+    }
+
+    fn is_removable(&self) -> bool {
+        false
+    }
+}
+
+impl api_snapshot::Snapshot for FileSystem {
+    type E = Error;
+    type Snapshot = Snapshot;
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Self::E> {
+        let _lock = self.store.lock()?;
+        let root = self.store.head()?;
+        let pin = self.store.pin(&root)?;
+        Ok(Snapshot {
+            store: self.store.clone(),
+            root,
+            pin,
+        })
+    }
+}
+
+macro_rules! def_impl_read_traits {
+    ($type:ty) => {
+        impl api_fs::FileSystem for $type {
+            type MetadataE = Error;
+
+            fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<api_entity::Metadata, Self::MetadataE>
+            where
+                Self: Sized,
+            {
+                let sub = sub.as_ref();
+                let entry = self.view()?.entry(sub)?;
+                Ok(api_entity::Metadata::new(
+                    sub.to_path_buf().into_boxed_path(),
+                    entry.r#type,
+                    entry.size,
+                ))
+            }
+
+            fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+            where
+                Self: Sized,
+            {
+                self.view().and_then(|view| view.entry(path.as_ref())).is_ok()
+            }
+
+            fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+            where
+                Self: Sized,
+            {
+                matches!(
+                    self.view().and_then(|view| view.entry(path.as_ref())),
+                    Ok(entry) if entry.r#type == api_entity::Type::File
+                )
+            }
+
+            fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+            where
+                Self: Sized,
+            {
+                matches!(
+                    self.view().and_then(|view| view.entry(path.as_ref())),
+                    Ok(entry) if entry.r#type == api_entity::Type::Dir
+                )
+            }
+        }
+
+        impl api_ops::OpenFile for $type {
+            type E = Error;
+            type File = File;
+
+            fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+                self.view()?.open_file(path.as_ref())
+            }
+        }
+
+        impl api_ops::OpenDir for $type {
+            type Dir = Dir;
+            type E = Error;
+
+            fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+                self.view()?.open_dir(path.as_ref())
+            }
+        }
+    };
+}
+
+def_impl_read_traits!(FileSystem);
+def_impl_read_traits!(Snapshot);
+
+/// ファイルは呼び出した時点で空のファイルとしてツリーに作られますが、書き込んだ内容は
+/// [io::Write::flush]か[Writer::finish]を呼び出すまでツリーに反映されません。
+/// [Writer]をそのまま破棄すると、最後に反映した後に書き込んだ内容は捨てられます。
+impl api_ops::CreateFile for FileSystem {
+    type E = Error;
+    type File = Writer;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.create_file_impl(path.as_ref(), false)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.create_file_impl(path.as_ref(), true)
+    }
+}
+
+impl api_ops::ReplaceFile for FileSystem {
+    type E = Error;
+    type File = AtomicFile;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        self.check_new(path)?;
+        let parent = path.parent().ok_or_else(|| Error::AccessError(path.to_path_buf()))?;
+        if self.entry(parent)?.r#type != api_entity::Type::Dir {
+            return Err(Error::NotDirError(path.to_path_buf()));
+        }
+        Staging::new(self, path).map(AtomicFile)
+    }
+}
+
+impl api_ops::CreateDir for FileSystem {
+    type Dir = Dir;
+    type E = Error;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.create_dir_impl(path.as_ref(), false)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.create_dir_impl(path.as_ref(), true)
+    }
+}
+
+impl api_ops::RemoveFile for FileSystem {
+    type E = Error;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.remove_impl(path.as_ref(), api_entity::Type::File)
+    }
+}
+
+impl api_ops::RemoveDir for FileSystem {
+    type E = Error;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.remove_impl(path.as_ref(), api_entity::Type::Dir)
+    }
+}
+
+#[cfg(test)]
+mod tree {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                entity::{AtomicFile as _, Dir as _, DirEntry as _},
+                ops, FileSystem as _,
+            },
+            path::policy::{PolicyError, Portable},
+            provider::make::Make as _,
+        },
+        std::io::{Read as _, Write as _},
+    };
+
+    use crate::{fs::Error, provider::Provider};
+
+    fn read(filesystem: &crate::fs::FileSystem, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut buf = String::new();
+        ops::OpenFile::open(filesystem, path)?.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn create_and_open() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        ops::CreateDir::create(&filesystem, "a/b")?;
+        let mut file = ops::CreateFile::create(&filesystem, "a/b/c.txt")?;
+        assert!(filesystem.is_file("a/b/c.txt"));
+        file.write_all(b"hello")?;
+        file.finish()?;
+
+        assert_eq!(read(&filesystem, "a/b/c.txt")?, "hello");
+        assert_eq!(filesystem.metadata("a/b/c.txt")?.size(), 5);
+        assert!(filesystem.is_dir("a/b"));
+        assert!(!filesystem.exists("a/c.txt"));
+
+        let names = ops::OpenDir::open(&filesystem, "a")?
+            .entries()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(names, vec![std::path::Path::new("a").join("b")]);
+        Ok(())
+    }
+
+    #[test]
+    fn flush() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        file.write_all(b"hello")?;
+        assert_eq!(read(&filesystem, "a.txt")?, "");
+        file.flush()?;
+        assert_eq!(read(&filesystem, "a.txt")?, "hello");
+        file.write_all(b" world")?;
+        file.flush()?;
+        assert_eq!(read(&filesystem, "a.txt")?, "hello world");
+        file.write_all(b"!")?;
+        drop(file);
+        assert_eq!(read(&filesystem, "a.txt")?, "hello world");
+
+        ops::CreateDir::create(&filesystem, "d")?;
+        let mut file = ops::CreateFile::create(&filesystem, "d/b.txt")?;
+        file.write_all(b"lost")?;
+        ops::RemoveDir::remove(&filesystem, "d")?;
+        assert!(file.flush().is_err());
+        assert!(matches!(file.finish(), Err(Error::NotFoundError(_))));
+        assert!(temp.join("tmp").read_dir()?.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn discard_on_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        let objects = || -> std::io::Result<usize> {
+            let mut count = 0;
+            for dir in temp.join("objects").read_dir()? {
+                count += dir?.path().read_dir()?.count();
+            }
+            Ok(count)
+        };
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        let before = objects()?;
+        file.write_all(b"lost")?;
+        drop(file);
+
+        assert_eq!(read(&filesystem, "a.txt")?, "");
+        assert_eq!(filesystem.metadata("a.txt")?.size(), 0);
+        assert_eq!(objects()?, before);
+        assert!(temp.join("tmp").read_dir()?.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn create_new() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut file = ops::CreateFile::create_new(&filesystem, "a.txt")?;
+        file.write_all(b"first")?;
+        file.finish()?;
+        assert!(matches!(
+            ops::CreateFile::create_new(&filesystem, "a.txt"),
+            Err(Error::AlreadyExistsError(_))
+        ));
+        assert!(matches!(
+            ops::CreateFile::create(&filesystem, "missing/a.txt"),
+            Err(Error::NotFoundError(_))
+        ));
+        assert_eq!(read(&filesystem, "a.txt")?, "first");
+        Ok(())
+    }
+
+    #[test]
+    fn replace_and_remove() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        file.write_all(b"old")?;
+        file.finish()?;
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "a.txt")?;
+        file.write_all(b"discarded")?;
+        drop(file);
+        assert_eq!(read(&filesystem, "a.txt")?, "old");
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "a.txt")?;
+        file.write_all(b"new")?;
+        file.commit()?;
+        assert_eq!(read(&filesystem, "a.txt")?, "new");
+
+        ops::CreateDir::create(&filesystem, "d/e")?;
+        assert!(matches!(
+            ops::RemoveFile::remove(&filesystem, "d"),
+            Err(Error::NotFileError(_))
+        ));
+        ops::RemoveDir::remove(&filesystem, "d")?;
+        ops::RemoveFile::remove(&filesystem, "a.txt")?;
+        assert_eq!(ops::OpenDir::open(&filesystem, ".")?.count(), 0);
+        assert!(temp.join("tmp").read_dir()?.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_root() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        assert!(matches!(
+            ops::OpenDir::open(&filesystem, ".."),
+            Err(Error::AccessError(_))
+        ));
+        assert!(matches!(
+            ops::CreateFile::create(&filesystem, "a/../../b"),
+            Err(Error::AccessError(_))
+        ));
+        assert!(matches!(
+            ops::OpenFile::open(&filesystem, "/etc"),
+            Err(Error::AccessError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn name_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf()).with_policy(Portable::default());
+
+        for path in ["con", "a/b.", "./x/PRN.txt"] {
+            assert!(matches!(
+                ops::CreateDir::create(&filesystem, path),
+                Err(Error::PolicyError(_))
+            ));
+        }
+        assert!(matches!(
+            ops::ReplaceFile::replace(&filesystem, "a|b"),
+            Err(Error::PolicyError(PolicyError::SpecialCharError(_)))
+        ));
+        assert_eq!(ops::OpenDir::open(&filesystem, ".")?.count(), 0);
+
+        ops::CreateDir::create(&filesystem, "x/../console")?;
+        assert!(filesystem.is_dir("console"));
+
+        // 既にあるディレクトリの名前は検査しない。
+        ops::CreateDir::create(&Provider::make(temp.to_path_buf()), "aux")?;
+        ops::CreateDir::create(&filesystem, "aux")?;
+        ops::CreateDir::create(&filesystem, "aux/a")?;
+        assert!(matches!(
+            ops::CreateDir::create(&filesystem, "aux/nul"),
+            Err(Error::PolicyError(_))
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod objects {
+    use ::{
+        filesystem_provider_api::{fs::ops, provider::make::Make as _},
+        std::io::Write as _,
+    };
+
+    use crate::provider::Provider;
+
+    #[test]
+    fn dedup() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        ops::CreateDir::create(&filesystem, "x")?;
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        file.write_all(b"same")?;
+        file.finish()?;
+        let mut file = ops::CreateFile::create(&filesystem, "x/b.txt")?;
+        file.write_all(b"same")?;
+        file.finish()?;
+
+        let id = filesystem.object_id("a.txt")?;
+        assert_eq!(id, filesystem.object_id("x/b.txt")?);
+        assert_eq!(
+            id.to_string(),
+            "0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"
+        );
+        let hex = id.to_string();
+        assert!(temp.join("objects").join(&hex[..2]).join(&hex[2..]).is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn gc() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        file.write_all(b"first")?;
+        file.finish()?;
+        let first = filesystem.object_id("a.txt")?;
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        file.write_all(b"second")?;
+        file.finish()?;
+
+        assert!(filesystem.gc()? > 0);
+        let hex = first.to_string();
+        assert!(!temp.join("objects").join(&hex[..2]).join(&hex[2..]).exists());
+        assert!(ops::OpenFile::open(&filesystem, "a.txt").is_ok());
+        assert_eq!(filesystem.gc()?, 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod snapshot {
+    use ::{
+        filesystem_provider_api::{
+            fs::{ops, snapshot::Snapshot as _, FileSystem as _, Introspect as _},
+            provider::make::Make as _,
+        },
+        std::io::{Read as _, Write as _},
+    };
+
+    use crate::provider::Provider;
+
+    #[test]
+    fn frozen() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+
+        file.write_all(b"before")?;
+
+        file.finish()?;
+        let snapshot = filesystem.snapshot()?;
+        assert!(!snapshot.is_writable());
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+
+        file.write_all(b"after")?;
+
+        file.finish()?;
+        let mut file = ops::CreateFile::create(&filesystem, "b.txt")?;
+        file.write_all(b"new")?;
+        file.finish()?;
+        assert!(filesystem.gc()? > 0);
+
+        let mut buf = String::new();
+        ops::OpenFile::open(&snapshot, "a.txt")?.read_to_string(&mut buf)?;
+        assert_eq!(buf, "before");
+        assert!(!snapshot.exists("b.txt"));
+        assert!(filesystem.exists("b.txt"));
+
+        drop(snapshot);
+        assert!(temp.join("refs").read_dir()?.next().is_none());
+        assert!(filesystem.gc()? > 0);
+        Ok(())
+    }
+}
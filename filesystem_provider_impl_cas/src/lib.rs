@@ -0,0 +1,33 @@
+//! 内容アドレスのオブジェクトストアを使ったファイルシステムの実装。
+//!
+//! ファイルの内容はそのハッシュを名前とするオブジェクトとしてディスクに保存されるので、同じ内容のファイルは重複しません。
+//! ディレクトリツリーもオブジェクトとして保存され、パスによるアクセスはツリーを辿って解決されます。
+//!
+//! [filesystem_provider_api::fs::ops::CreateFile]で書き込んだ内容は、`flush`か[fs::Writer::finish]を呼び出すまで保存されません。
+//! 呼び出さずに破棄したファイルの内容は捨てられます。
+//!
+//! ```
+//! use ::{
+//!     filesystem_provider_api::{
+//!         fs::{ops, FileSystem as _},
+//!         provider::make::Make as _,
+//!     },
+//!     filesystem_provider_impl_cas::provider::Provider,
+//!     std::io::Write as _,
+//! };
+//!
+//! fn foo() -> Result<(), Box<dyn std::error::Error>> {
+//!     let filesystem = Provider::make(std::path::PathBuf::from("store"));
+//!
+//!     let mut file = ops::CreateFile::create(&filesystem, "test.txt")?;
+//!     file.write_all(b"hello")?;
+//!     file.finish()?;
+//!
+//!     assert!(filesystem.is_file("test.txt"));
+//!     Ok(())
+//! }
+//! ```
+
+pub mod fs;
+pub mod provider;
+mod store;
@@ -0,0 +1,110 @@
+use ::std::path::PathBuf;
+use filesystem_provider_api::provider::make as api_make;
+
+#[derive(Debug)]
+pub struct Provider;
+
+/// `root`はオブジェクトストアのディレクトリです。存在しない場合は最初に書き込む時に作られます。
+///
+/// ```
+/// use ::{
+///     filesystem_provider_api::provider::make::Make as _,
+///     filesystem_provider_impl_cas::provider::Provider,
+/// };
+///
+/// let root = std::path::PathBuf::from("store");
+/// let filesystem = Provider::make(root);
+/// ```
+impl api_make::Make for Provider {
+    type FS = crate::fs::FileSystem;
+
+    fn make(root: PathBuf) -> Self::FS {
+        let store = crate::store::Store::new(root);
+        crate::fs::FileSystem::new(store)
+    }
+}
+
+/// `crate::provider::Provider`は以下のcapabilitiesを備えたファイルシステムを作ることができる。
+///
+/// - `Readable`
+/// - `Writable`
+/// - `Truncatable`
+/// - `Removable`
+///
+/// オブジェクトは不変なので`Appendable`ではない。
+///
+#[cfg(test)]
+mod test_capabilities {
+    use ::filesystem_provider_api::fs;
+
+    fn inspect<F: fs::Introspect>(_: &F) {}
+    fn filesystem<F: fs::FileSystem<MetadataE = crate::fs::Error>>(_: &F) {}
+
+    #[test]
+    fn it_works() -> Result<(), Box<dyn std::error::Error>> {
+        use filesystem_provider_api::provider::make::Make;
+
+        let filesystem = crate::provider::Provider::make(std::path::PathBuf::from("store"));
+
+        inspect(&filesystem);
+
+        assert!(fs::Introspect::is_readable(&filesystem));
+        assert!(fs::Introspect::is_writable(&filesystem));
+        assert!(!fs::Introspect::is_appendable(&filesystem));
+        assert!(fs::Introspect::is_truncatable(&filesystem));
+        assert!(fs::Introspect::is_removable(&filesystem));
+
+        self::filesystem(&filesystem);
+
+        Ok(())
+    }
+}
+
+/// `crate::provider::Provider`は以下の操作を備えたファイルシステムを作ることができる。
+///
+/// - open::{File, Dir}
+/// - create::{File, Dir}
+/// - replace::File
+/// - remove::{File, Dir}
+///
+/// `crate::provider::Provider`が作るファイルシステムは`Send + Sync`であり、スレッド間で共有できる。
+///
+#[cfg(test)]
+mod test_operations {
+    use ::filesystem_provider_api::fs;
+
+    fn open_file<F: fs::ops::OpenFile<E = crate::fs::Error, File = crate::fs::File>>(_: &F) {}
+    fn open_dir<F: fs::ops::OpenDir<E = crate::fs::Error, Dir = crate::fs::Dir>>(_: &F) {}
+
+    fn create_file<F: fs::ops::CreateFile<E = crate::fs::Error, File = crate::fs::Writer>>(_: &F) {}
+    fn create_dir<F: fs::ops::CreateDir<E = crate::fs::Error, Dir = crate::fs::Dir>>(_: &F) {}
+
+    fn replace_file<F: fs::ops::ReplaceFile<E = crate::fs::Error, File = crate::fs::AtomicFile>>(_: &F) {}
+
+    fn remove_file<F: fs::ops::RemoveFile<E = crate::fs::Error>>(_: &F) {}
+    fn remove_dir<F: fs::ops::RemoveDir<E = crate::fs::Error>>(_: &F) {}
+
+    fn send_sync<F: Send + Sync>(_: &F) {}
+
+    #[test]
+    fn it_works() -> Result<(), Box<dyn std::error::Error>> {
+        use filesystem_provider_api::provider::make::Make;
+
+        let filesystem = crate::provider::Provider::make(std::path::PathBuf::from("store"));
+
+        open_file(&filesystem);
+        open_dir(&filesystem);
+
+        create_file(&filesystem);
+        create_dir(&filesystem);
+
+        replace_file(&filesystem);
+
+        remove_file(&filesystem);
+        remove_dir(&filesystem);
+
+        send_sync(&filesystem);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! 内容アドレスのオブジェクトストア。
+//!
+//! ストアは基底パスの下に以下を持ちます。
+//!
+//! - `objects/` 内容のSHA-256の16進数を名前とするオブジェクト。先頭の2文字はサブディレクトリになります。
+//! - `tmp/` 書き込み中のオブジェクト
+//! - `HEAD` ルートディレクトリのツリーオブジェクトのID
+//! - `lock` ツリーを更新する間、排他ロックされるファイル
+//! - `refs/` 生きているスナップショットのルートのツリーオブジェクトのID
+//!
+//! オブジェクトは不変なので、同じ内容のファイルは一つのオブジェクトを共有します。
+//! ディレクトリはその子エンティティの名前、種類、大きさ、IDを並べたツリーオブジェクトとして保存されます。
+
+use ::{
+    filesystem_provider_api::fs::entity::Type,
+    sha2::Digest as _,
+    std::{
+        collections::{BTreeMap, HashSet},
+        convert::TryInto,
+        fmt,
+        io::{self, Read as _, Write as _},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+};
+
+use crate::fs::Error;
+
+/// オブジェクトのIDです。内容のSHA-256で、[fmt::Display]は小文字の16進数です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ObjectId([u8; 32]);
+
+impl ObjectId {
+    pub(crate) fn of(bytes: &[u8]) -> Self {
+        Self(sha2::Sha256::digest(bytes).into())
+    }
+
+    pub(crate) fn from_hasher(hasher: sha2::Sha256) -> Self {
+        Self(hasher.finalize().into())
+    }
+
+    /// 16進数の文字列からIDを作ります。
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut id = [0; 32];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self(id))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// 空のディレクトリを表すツリーオブジェクトのIDです。
+    pub(crate) fn empty_tree() -> Self {
+        Self::of(&encode_tree(&Tree::new()))
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// ツリーオブジェクトの各エントリです。`size`はファイルの場合は内容の、ディレクトリの場合はツリーオブジェクトの大きさです。
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) r#type: Type,
+    pub(crate) id: ObjectId,
+    pub(crate) size: u64,
+}
+
+pub(crate) type Tree = BTreeMap<String, Entry>;
+
+/// ツリーをバイト列にします。各エントリは名前の順に、種類(`f`か`d`)、大きさ、ID、名前の長さ、名前の順に並びます。
+/// 数値はすべてリトルエンディアンの`u64`です。
+fn encode_tree(tree: &Tree) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (name, entry) in tree {
+        bytes.push(match entry.r#type {
+            Type::File => b'f',
+            Type::Dir => b'd',
+        });
+        bytes.extend_from_slice(&entry.size.to_le_bytes());
+        bytes.extend_from_slice(entry.id.as_bytes());
+        bytes.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+    }
+    bytes
+}
+
+fn decode_tree(mut bytes: &[u8]) -> Option<Tree> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = bytes.split_at(n);
+        *bytes = tail;
+        Some(head)
+    }
+    fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+        take(bytes, 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    let mut tree = Tree::new();
+    while !bytes.is_empty() {
+        let r#type = match take(&mut bytes, 1)?[0] {
+            b'f' => Type::File,
+            b'd' => Type::Dir,
+            _ => return None,
+        };
+        let size = take_u64(&mut bytes)?;
+        let id = ObjectId(take(&mut bytes, 32)?.try_into().unwrap());
+        let len = take_u64(&mut bytes)?.try_into().ok()?;
+        let name = std::str::from_utf8(take(&mut bytes, len)?).ok()?;
+        tree.insert(name.to_owned(), Entry { r#type, id, size });
+    }
+    Some(tree)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Store {
+    root: Arc<Path>,
+}
+
+impl Store {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn object_path(&self, id: &ObjectId) -> PathBuf {
+        let hex = id.to_string();
+        self.root.join("objects").join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// `tmp/`に新しい一時ファイルを作ります。
+    pub(crate) fn temp(&self) -> io::Result<(std::fs::File, PathBuf)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = self.root.join("tmp");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = std::fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok((file, path))
+    }
+
+    /// 永続化済みの一時ファイル`temp`をオブジェクト`id`として保存します。
+    /// 同じオブジェクトが既にある場合は何もしないので、一時ファイルは呼び出し側が削除する必要があります。
+    pub(crate) fn put(&self, temp: &Path, id: &ObjectId) -> io::Result<()> {
+        let path = self.object_path(id);
+        if path.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::rename(temp, path)
+    }
+
+    pub(crate) fn put_bytes(&self, bytes: &[u8]) -> io::Result<ObjectId> {
+        let id = ObjectId::of(bytes);
+        if self.object_path(&id).exists() {
+            return Ok(id);
+        }
+        let (mut file, temp) = self.temp()?;
+        let result = file
+            .write_all(bytes)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| self.put(&temp, &id));
+        let _ = std::fs::remove_file(&temp);
+        result.map(|_| id)
+    }
+
+    pub(crate) fn open(&self, id: &ObjectId) -> io::Result<std::fs::File> {
+        std::fs::File::open(self.object_path(id))
+    }
+
+    /// ツリーオブジェクトを読みます。内容がIDと一致しない場合は失敗します。
+    pub(crate) fn tree(&self, id: &ObjectId) -> Result<Tree, Error> {
+        let mut bytes = Vec::new();
+        match self.open(id) {
+            Ok(mut file) => file.read_to_end(&mut bytes).map(|_| ())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && *id == ObjectId::empty_tree() => (),
+            Err(err) => return Err(err.into()),
+        }
+        if ObjectId::of(&bytes) != *id {
+            return Err(Error::CorruptError(*id));
+        }
+        decode_tree(&bytes).ok_or(Error::CorruptError(*id))
+    }
+
+    pub(crate) fn put_tree(&self, tree: &Tree) -> io::Result<Entry> {
+        let bytes = encode_tree(tree);
+        self.put_bytes(&bytes).map(|id| Entry {
+            r#type: Type::Dir,
+            id,
+            size: bytes.len() as u64,
+        })
+    }
+
+    /// ルートディレクトリのツリーオブジェクトのIDです。まだ何も書き込まれていない場合は空のツリーです。
+    pub(crate) fn head(&self) -> Result<ObjectId, Error> {
+        match std::fs::read_to_string(self.root.join("HEAD")) {
+            Ok(hex) => ObjectId::from_hex(hex.trim())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HEAD").into()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(ObjectId::empty_tree()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// `HEAD`を不可分に置き換えます。
+    pub(crate) fn set_head(&self, id: &ObjectId) -> io::Result<()> {
+        let (mut file, temp) = self.temp()?;
+        let result = writeln!(file, "{}", id)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| std::fs::rename(&temp, self.root.join("HEAD")));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// `id`をスナップショットのルートとして`refs/`に記録し、そのファイルのパスを返します。
+    pub(crate) fn pin(&self, id: &ObjectId) -> io::Result<PathBuf> {
+        let (mut file, temp) = self.temp()?;
+        let dir = self.root.join("refs");
+        let path = dir.join(temp.file_name().unwrap());
+        let result = writeln!(file, "{}", id)
+            .and_then(|_| std::fs::create_dir_all(&dir))
+            .and_then(|_| std::fs::rename(&temp, &path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result.map(|_| path)
+    }
+
+    /// `refs/`に記録されているスナップショットのルートです。
+    pub(crate) fn pins(&self) -> io::Result<Vec<ObjectId>> {
+        let dir = match std::fs::read_dir(self.root.join("refs")) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut pins = Vec::new();
+        for entry in dir {
+            let hex = std::fs::read_to_string(entry?.path())?;
+            pins.extend(ObjectId::from_hex(hex.trim()));
+        }
+        Ok(pins)
+    }
+
+    /// ツリーを更新するための排他ロックを取得します。返されたファイルを破棄するとロックは解除されます。
+    pub(crate) fn lock(&self) -> io::Result<std::fs::File> {
+        std::fs::create_dir_all(&self.root)?;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root.join("lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// `reachable`に含まれないオブジェクトを削除し、削除した数を返します。
+    pub(crate) fn sweep(&self, reachable: &HashSet<ObjectId>) -> io::Result<usize> {
+        let objects = self.root.join("objects");
+        if !objects.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for dir in std::fs::read_dir(objects)? {
+            let dir = dir?;
+            for object in std::fs::read_dir(dir.path())? {
+                let object = object?;
+                let hex = format!(
+                    "{}{}",
+                    dir.file_name().to_string_lossy(),
+                    object.file_name().to_string_lossy()
+                );
+                match ObjectId::from_hex(&hex) {
+                    Some(id) if reachable.contains(&id) => (),
+                    _ => {
+                        std::fs::remove_file(object.path())?;
+                        removed += 1;
+                    },
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
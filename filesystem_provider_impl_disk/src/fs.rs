@@ -1,14 +1,32 @@
+#[cfg(feature = "async")]
+pub mod r#async;
+#[cfg(not(unix))]
+mod fallback;
+pub mod snapshot;
+#[cfg(unix)]
+mod sys;
+pub mod transaction;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
 use ::{
     filesystem_provider_api::{
         fs as api_fs,
         fs::{entity as api_entity, ops as api_ops},
+        path::policy::{Permissive, Policy, PolicyError},
     },
     std::{
+        ffi::OsString,
         io,
         path::{Path, PathBuf},
+        sync::Arc,
+        time::SystemTime,
     },
 };
 
+#[cfg(not(unix))]
+use self::fallback as sys;
+
 #[derive(Debug)]
 pub struct File(std::fs::File);
 
@@ -48,26 +66,133 @@ impl api_entity::File for File {
     }
 }
 
+impl api_entity::Durable for File {
+    type E = io::Error;
+
+    fn sync_all(&self) -> Result<(), Self::E> {
+        self.0.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<(), Self::E> {
+        self.0.sync_data()
+    }
+}
+
+/// UNIXでは`flock(2)`によるロックです。ロックは開いたファイル記述ごとに管理されます。
+impl api_entity::Lock for File {
+    type E = io::Error;
+
+    fn lock_shared(&self) -> Result<(), Self::E> {
+        self.0.lock_shared()
+    }
+
+    fn lock_exclusive(&self) -> Result<(), Self::E> {
+        self.0.lock()
+    }
+
+    fn try_lock_shared(&self) -> Result<bool, Self::E> {
+        try_lock(self.0.try_lock_shared())
+    }
+
+    fn try_lock_exclusive(&self) -> Result<bool, Self::E> {
+        try_lock(self.0.try_lock())
+    }
+
+    fn unlock(&self) -> Result<(), Self::E> {
+        self.0.unlock()
+    }
+}
+
+fn try_lock(result: Result<(), std::fs::TryLockError>) -> io::Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(false),
+        Err(std::fs::TryLockError::Error(err)) => Err(err),
+    }
+}
+
+/// ファイルを開く際のモードです。
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum OpenMode {
+    Read,
+    Truncate,
+    CreateNew,
+}
+
+/// statの結果のうちこのファイルシステムが使うものです。
+#[derive(Debug)]
+pub(crate) struct Stat {
+    file_type: Option<api_entity::Type>,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// ディレクトリを読んだ時点で分かるエントリの種類です。
+#[derive(Debug)]
+pub(crate) enum EntryType {
+    Known(Option<api_entity::Type>),
+    // シンボリックリンクか種類が分からない場合。
+    Unknown,
+}
+
 #[derive(Debug)]
-pub struct DirEntry(std::fs::DirEntry);
+pub struct DirEntry {
+    // エントリを含むディレクトリのハンドル。
+    parent: Arc<sys::Handle>,
+    file_name: OsString,
+    // Dir::entriesを呼び出したディレクトリのサブパス。
+    sub: PathBuf,
+    // イテレーションの度にstatしないように、ディレクトリを読んだ時点の種類を保持する。
+    file_type: Option<api_entity::Type>,
+}
+
+impl DirEntry {
+    fn new(parent: &Arc<sys::Handle>, file_name: OsString, entry_type: EntryType, sub: &Path) -> io::Result<Self> {
+        let file_type = match entry_type {
+            EntryType::Known(file_type) => file_type,
+            // シンボリックリンクか種類が分からない場合のみstatする。リンク切れの場合はどちらでもないものとして扱う。
+            EntryType::Unknown => match sys::stat_at(parent, Path::new(&file_name), true) {
+                Ok(stat) => stat.file_type,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            },
+        };
+        Ok(Self {
+            parent: parent.clone(),
+            file_name,
+            sub: sub.to_path_buf(),
+            file_type,
+        })
+    }
+}
 
 impl api_entity::File for DirEntry {
     fn size(&self) -> u64 {
-        self.0.metadata().unwrap().len()
+        sys::stat_at(&self.parent, Path::new(&self.file_name), false)
+            .unwrap()
+            .size
     }
 
     fn is_file(&self) -> bool {
-        self.0.metadata().unwrap().is_file()
+        self.file_type == Some(api_entity::Type::File)
     }
 
     fn is_dir(&self) -> bool {
-        self.0.metadata().unwrap().is_dir()
+        self.file_type == Some(api_entity::Type::Dir)
     }
 }
 
 impl api_entity::DirEntry for DirEntry {
     fn path(&self) -> PathBuf {
-        self.0.path()
+        self.sub.join(&self.file_name)
+    }
+
+    fn file_name(&self) -> OsString {
+        self.file_name.clone()
+    }
+
+    fn file_type(&self) -> Option<api_entity::Type> {
+        self.file_type.clone()
     }
 }
 
@@ -77,14 +202,31 @@ pub enum OpenEntityError {
     AccessError(PathBuf),
     #[error("entity not readable")]
     ReadError,
+    #[error("entity not found {0:?}")]
+    NotFoundError(PathBuf),
+    #[error("not a directory {0:?}")]
+    NotDirError(PathBuf),
     #[error("{0:?}")]
     #[rustfmt::skip]
     IoError(#[from]#[source]io::Error),
 }
 
+impl OpenEntityError {
+    /// ディレクトリを開く際のエラーを、存在しない場合とディレクトリでない場合について区別します。
+    fn from_open_dir(err: io::Error, path: PathBuf) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => OpenEntityError::NotFoundError(path),
+            io::ErrorKind::NotADirectory => OpenEntityError::NotDirError(path),
+            _ => OpenEntityError::IoError(err),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct DirEntries {
-    read_dir: std::fs::ReadDir,
+    parent: Arc<sys::Handle>,
+    read_dir: sys::ReadDir,
+    sub: PathBuf,
 }
 
 impl std::iter::Iterator for DirEntries {
@@ -92,27 +234,49 @@ impl std::iter::Iterator for DirEntries {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.read_dir.next() {
-            Some(Ok(entry)) => Some(Ok(DirEntry(entry))),
+            Some(Ok((file_name, _))) if is_hidden(&self.sub.join(&file_name)) => self.next(),
+            Some(Ok((file_name, entry_type))) => {
+                Some(DirEntry::new(&self.parent, file_name, entry_type, &self.sub).map_err(Into::into))
+            },
             Some(Err(err)) => Some(Err(err.into())),
             None => None,
         }
     }
 }
 
+/// ディレクトリのハンドル。
+///
+/// UNIXではディレクトリを開いたファイル記述子を保持するので、開いた後にディレクトリがリネームされても同じディレクトリを指し続けます。
+/// それ以外のプラットフォームでは開いた時点のパスを保持します。
 #[derive(Debug)]
-pub struct Dir(PathBuf);
+pub struct Dir {
+    handle: Arc<sys::Handle>,
+    // 基底パスを基準としたサブパス。
+    sub: PathBuf,
+    policy: Arc<dyn Policy>,
+}
+
+impl Dir {
+    fn open(path: &Path, sub: &Path, policy: &Arc<dyn Policy>) -> io::Result<Self> {
+        Ok(Self {
+            handle: Arc::new(sys::open_dir_at(None, path)?),
+            sub: normalize(sub),
+            policy: policy.clone(),
+        })
+    }
+}
 
 impl api_entity::File for Dir {
     fn size(&self) -> u64 {
-        self.0.metadata().unwrap().len()
+        sys::metadata(&self.handle).unwrap().len()
     }
 
     fn is_file(&self) -> bool {
-        self.0.metadata().unwrap().is_file()
+        sys::metadata(&self.handle).unwrap().is_file()
     }
 
     fn is_dir(&self) -> bool {
-        self.0.metadata().unwrap().is_dir()
+        sys::metadata(&self.handle).unwrap().is_dir()
     }
 }
 
@@ -123,34 +287,233 @@ impl api_entity::Dir for Dir {
     type IterE = OpenEntityError;
 
     fn total_size(&self) -> u64 {
-        self.0
-            .read_dir()
+        self.entries()
             .unwrap()
-            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .map(|entry| api_entity::File::size(&entry.unwrap()))
             .sum::<u64>()
     }
 
     fn count(&self) -> usize {
-        std::fs::read_dir(self.0.as_path()).unwrap().count()
+        sys::ReadDir::new(&self.handle)
+            .unwrap()
+            .filter(|entry| !matches!(entry, Ok((name, _)) if is_hidden(&self.sub.join(name))))
+            .count()
     }
 
     fn entries(&self) -> Result<Self::Entries, Self::EntriesE> {
-        let read_dir = self.0.read_dir()?;
-        Ok(DirEntries { read_dir })
+        Ok(DirEntries {
+            parent: self.handle.clone(),
+            read_dir: sys::ReadDir::new(&self.handle)?,
+            sub: self.sub.clone(),
+        })
+    }
+}
+
+/// ディレクトリを開いてfsyncします。ディレクトリ内でのエンティティの作成や削除、リネームが永続化されます。
+fn sync_dir(path: &Path) -> io::Result<()> {
+    sys::sync_dir(&sys::open_dir_at(None, path)?)
+}
+
+impl api_entity::Durable for Dir {
+    type E = io::Error;
+
+    fn sync_all(&self) -> Result<(), Self::E> {
+        sys::sync_dir(&self.handle)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChildEntityError {
+    #[error("out of access {0:?}")]
+    AccessError(PathBuf),
+    #[error("entity not found {0:?}")]
+    NotFoundError(PathBuf),
+    #[error("not a directory {0:?}")]
+    NotDirError(PathBuf),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    PolicyError(#[from]#[source]PolicyError),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    IoError(#[from]#[source]io::Error),
+}
+
+impl Dir {
+    /// `name`を検査し、このディレクトリを基準とした正規化されたパスを返します。
+    /// `allow_self`が`false`の場合、このディレクトリ自身を指すパスもエラーとします。
+    fn child<P: AsRef<Path>>(&self, name: P, allow_self: bool) -> Result<PathBuf, ChildEntityError> {
+        let name = name.as_ref();
+        check_path(|_| ChildEntityError::AccessError(self.sub.join(name)), name)?;
+
+        if is_hidden(&normalize(&self.sub.join(name))) {
+            return Err(ChildEntityError::AccessError(self.sub.join(name)));
+        }
+        match normalize(name) {
+            normalized if !normalized.as_os_str().is_empty() => Ok(normalized),
+            _ if allow_self => Ok(PathBuf::from(".")),
+            _ => Err(ChildEntityError::AccessError(self.sub.join(name))),
+        }
+    }
+
+    /// 作る子エンティティの`name`を検査し、このディレクトリを基準とした正規化されたパスを返します。
+    /// [Policy]はこのディレクトリのサブパスと連結したパスを検査します。既にあるコンポーネントの名前は検査しません。
+    fn new_child<P: AsRef<Path>>(&self, name: P) -> Result<PathBuf, ChildEntityError> {
+        let name = self.child(name, false)?;
+        let existing = existing_components(&name, |prefix| sys::stat_at(&self.handle, prefix, false).is_ok());
+        self.policy
+            .check_new(&self.sub.join(&name), self.sub.components().count() + existing)?;
+        Ok(name)
+    }
+
+    /// 子エンティティの操作に失敗した場合のエラーを、存在しない場合とディレクトリでない場合について区別します。
+    fn child_error(&self, name: &Path) -> impl FnOnce(io::Error) -> ChildEntityError + '_ {
+        let path = normalize(&self.sub.join(name));
+        move |err| match err.kind() {
+            io::ErrorKind::NotFound => ChildEntityError::NotFoundError(path),
+            io::ErrorKind::NotADirectory => ChildEntityError::NotDirError(path),
+            _ => ChildEntityError::IoError(err),
+        }
+    }
+
+    fn open_file_at(&self, name: &Path, mode: OpenMode) -> Result<File, ChildEntityError> {
+        sys::open_file_at(&self.handle, name, mode)
+            .map(File)
+            .map_err(self.child_error(name))
+    }
+}
+
+/// UNIXでは子エンティティの操作は`openat`などのシステムコールによってこのディレクトリのハンドルを基準に行われます。
+impl api_entity::DirAt for Dir {
+    type E = ChildEntityError;
+    type File = File;
+
+    fn open_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        let name = self.child(name, false)?;
+        self.open_file_at(&name, OpenMode::Read)
+    }
+
+    fn open_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E> {
+        let name = self.child(name, true)?;
+        let handle = sys::open_dir_at(Some(&self.handle), &name).map_err(self.child_error(&name))?;
+        Ok(Dir {
+            handle: Arc::new(handle),
+            sub: normalize(&self.sub.join(&name)),
+            policy: self.policy.clone(),
+        })
+    }
+
+    fn create_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        let name = self.new_child(name)?;
+        self.open_file_at(&name, OpenMode::Truncate)
+    }
+
+    fn create_new_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        let name = self.new_child(name)?;
+        self.open_file_at(&name, OpenMode::CreateNew)
+    }
+
+    fn create_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E> {
+        let name = self.new_child(name)?;
+
+        let mut prefix = PathBuf::new();
+        for comp in name.components() {
+            prefix.push(comp);
+            match sys::mkdir_at(&self.handle, &prefix) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+                result => result.map_err(self.child_error(&prefix))?,
+            }
+        }
+        self.open_dir(name)
+    }
+
+    fn remove_file<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E> {
+        let name = self.child(name, false)?;
+        sys::unlink_at(&self.handle, &name).map_err(self.child_error(&name))
+    }
+
+    fn remove_dir<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E> {
+        let name = self.child(name, false)?;
+        sys::remove_dir_all_at(&self.handle, &name).map_err(self.child_error(&name))
+    }
+
+    fn metadata<P: AsRef<Path>>(&self, name: P) -> Result<api_entity::Metadata, Self::E> {
+        let name = self.child(name, true)?;
+        let stat = sys::stat_at(&self.handle, &name, true).map_err(self.child_error(&name))?;
+        let r#type = stat
+            .file_type
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "neither a file nor a directory"))?;
+        Ok(
+            api_entity::Metadata::new(normalize(&self.sub.join(&name)).into_boxed_path(), r#type, stat.size)
+                .with_modified(stat.modified),
+        )
     }
 }
 
 /// このファイルシステムのサブパスはカレントディレクトリか通常のコンポーネントで開始し基底パス下階のみを指さなければならない。
-#[derive(Debug)]
+///
+/// 作るエンティティのパスはさらに[Policy]で検査されます。既定のポリシーは[Permissive]です。
+#[derive(Debug, Clone)]
 pub struct FileSystem {
     // 基底パスが変更されないようにPathBufではなくPathを利用する。
     // 所有権を持つのでBox化する。
     pub(crate) root: Box<Path>,
+    pub(crate) policy: Arc<dyn Policy>,
 }
 
 impl FileSystem {
+    pub(crate) fn new(root: Box<Path>) -> Self {
+        Self {
+            root,
+            policy: Arc::new(Permissive),
+        }
+    }
+
+    /// 作るエンティティのパスを`policy`で検査するファイルシステムを返します。
+    ///
+    /// ```
+    /// use ::{
+    ///     filesystem_provider_api::{path::policy::Portable, provider::make::Make as _},
+    ///     filesystem_provider_impl_disk::provider::Provider,
+    /// };
+    ///
+    /// let filesystem = Provider::make(".".into()).with_policy(Portable::default());
+    /// ```
+    pub fn with_policy<P: Policy + 'static>(self, policy: P) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    pub fn policy(&self) -> &dyn Policy {
+        &*self.policy
+    }
+
     fn current<P: AsRef<Path>>(&self, sub: &P) -> PathBuf {
-        self.root.join(&sub)
+        self.root.join(sub)
+    }
+
+    /// サブパスを[check_path]で検査し、基底パスと連結したパスを返します。
+    /// 検査に失敗した場合やサブパスが[is_hidden]の場合は基底パスと連結したパスで`ctor`を呼び出します。
+    fn resolve<R, F: FnOnce(PathBuf) -> R, P: AsRef<Path>>(&self, ctor: F, sub: &P) -> Result<PathBuf, R> {
+        let path = self.current(sub);
+        match check_path(|_| (), sub.as_ref()) {
+            Ok(()) if !is_hidden(&normalize(sub.as_ref())) => Ok(path),
+            _ => Err(ctor(path)),
+        }
+    }
+
+    /// 作るエンティティのサブパスを[check_path]と[FileSystem::check_policy]で検査し、基底パスと連結したパスを返します。
+    fn resolve_new<P: AsRef<Path>>(&self, sub: &P) -> Result<PathBuf, CreateEntityError> {
+        let path = self.resolve(CreateEntityError::AccessError, sub)?;
+        self.check_policy(&normalize(sub.as_ref()))?;
+        Ok(path)
+    }
+
+    /// 作るエンティティの正規化されたサブパスを[Policy]で検査します。既にあるコンポーネントの名前は検査しません。
+    pub(crate) fn check_policy(&self, normalized: &Path) -> Result<(), PolicyError> {
+        let existing = existing_components(normalized, |prefix| self.current(&prefix).symlink_metadata().is_ok());
+        self.policy.check_new(normalized, existing)
     }
 }
 
@@ -184,38 +547,47 @@ impl api_fs::FileSystem for FileSystem {
         Self: Sized,
     {
         let sub = sub.as_ref();
-        let metadata = sub.metadata()?;
-        Ok(api_entity::Metadata::new(
-            sub.to_path_buf().into_boxed_path(),
-            if metadata.is_file() {
-                api_entity::Type::File
-            } else {
-                assert!(metadata.is_dir());
-                api_entity::Type::Dir
-            },
-            metadata.len(),
-        ))
+        let path = self.resolve(
+            |path| io::Error::new(io::ErrorKind::PermissionDenied, format!("out of access {:?}", path)),
+            &sub,
+        )?;
+        let metadata = path.metadata()?;
+        let r#type = if metadata.is_file() {
+            api_entity::Type::File
+        } else if metadata.is_dir() {
+            api_entity::Type::Dir
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "neither a file nor a directory",
+            ));
+        };
+        let entity = api_entity::Metadata::new(sub.to_path_buf().into_boxed_path(), r#type, metadata.len());
+        Ok(match metadata.modified() {
+            Ok(modified) => entity.with_modified(modified),
+            Err(_) => entity,
+        })
     }
 
     fn exists<P: AsRef<Path>>(&self, path: P) -> bool
     where
         Self: Sized,
     {
-        self.current(&path).exists()
+        self.resolve(|_| (), &path).is_ok_and(|path| path.exists())
     }
 
     fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
     where
         Self: Sized,
     {
-        self.current(&path).is_file()
+        self.resolve(|_| (), &path).is_ok_and(|path| path.is_file())
     }
 
     fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
     where
         Self: Sized,
     {
-        self.current(&path).is_dir()
+        self.resolve(|_| (), &path).is_ok_and(|path| path.is_dir())
     }
 }
 
@@ -233,7 +605,7 @@ pub enum RemoveEntityError {
 /// パスはカレントディレクトリか通常のコンポーネントで始まり、カレント・親ディレクトリか通常のコンポーネントが続かなければならない。
 /// そうでなければエラーとする。
 /// さらにパスがファイルシステムの基底パス（ルートパス）の下階以外を表しているときも同様とする。
-fn check_path<R, F: FnOnce(PathBuf) -> R>(ctor: F, path: &Path) -> Result<(), R> {
+pub(crate) fn check_path<R, F: FnOnce(PathBuf) -> R>(ctor: F, path: &Path) -> Result<(), R> {
     use std::path::Component;
 
     let mut components = path.components();
@@ -251,13 +623,68 @@ fn check_path<R, F: FnOnce(PathBuf) -> R>(ctor: F, path: &Path) -> Result<(), R>
             Component::Normal(_) => level += 1,
             _ => return Err(ctor(path.to_owned())),
         }
+        // 途中で基底パスの上階を経由する場合も基底パスの外側を指しているとみなす。
+        if level < 0 {
+            return Err(ctor(path.to_owned()));
+        }
     }
 
-    if level < 0 {
-        return Err(ctor(path.to_owned()));
-    } else {
-        Ok(())
+    Ok(())
+}
+
+/// スナップショットなど、このクレートが基底パスの下に置くものをまとめるディレクトリの名前です。
+///
+/// 利用者のファイルと衝突しにくい名前にしています。ファイルシステムからは見えず、操作も出来ません。
+pub const RESERVED_DIR: &str = ".filesystem_provider";
+
+/// 正規化されたサブパスが[RESERVED_DIR]かその下階を指すか調べます。
+pub(crate) fn is_hidden(normalized: &Path) -> bool {
+    normalized.components().next() == Some(std::path::Component::Normal(RESERVED_DIR.as_ref()))
+}
+
+/// [RESERVED_DIR]の下のディレクトリ`dir`を、空であれば削除します。[RESERVED_DIR]も空になれば削除します。
+///
+/// 他のスナップショットやトランザクションが使っていて空でない場合は何もしません。
+fn remove_empty_reserved(dir: &Path) -> io::Result<()> {
+    for dir in dir.ancestors().take(2) {
+        match std::fs::remove_dir(dir) {
+            Err(err) if err.kind() == io::ErrorKind::DirectoryNotEmpty => break,
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// 正規化されたパスの先頭から、`exists`が`true`を返す間のコンポーネントの数を返します。
+fn existing_components(normalized: &Path, exists: impl Fn(&Path) -> bool) -> usize {
+    let mut prefix = PathBuf::new();
+    normalized
+        .components()
+        .take_while(|component| {
+            prefix.push(component);
+            exists(&prefix)
+        })
+        .count()
+}
+
+/// パスからカレントディレクトリを取り除き、親ディレクトリを字句的に解決します。
+///
+/// [check_path]を通過したパスに対して使うことを想定しています。
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+
+    let mut normalized = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            comp => normalized.push(comp),
+        }
     }
+    normalized
 }
 
 macro_rules! def_impl_ops_trait_for_filesystem {
@@ -266,9 +693,8 @@ macro_rules! def_impl_ops_trait_for_filesystem {
             type E = crate::fs::RemoveEntityError;
 
             fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
-                let path = self.current(&path);
-                let _ = check_path(RemoveEntityError::AccessError, &path)?;
-                $fn_name(self.current(&path)).map_err(RemoveEntityError::IoError)
+                let path = self.resolve(RemoveEntityError::AccessError, &path)?;
+                $fn_name(path).map_err(RemoveEntityError::IoError)
             }
         }
     };
@@ -285,6 +711,9 @@ pub enum CreateEntityError {
     WriteError,
     #[error("{0:?}")]
     #[rustfmt::skip]
+    PolicyError(#[from]#[source]PolicyError),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
     IoError(#[from]#[source]io::Error),
 }
 
@@ -292,20 +721,19 @@ impl api_ops::CreateFile for FileSystem {
     type E = CreateEntityError;
     type File = File;
 
-    fn create<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(CreateEntityError::AccessError, &path)?;
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = self.resolve_new(&path)?;
 
         std::fs::File::create(path)
             .map(File)
             .map_err(CreateEntityError::IoError)
     }
 
-    fn create_new<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(CreateEntityError::AccessError, &path)?;
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = self.resolve_new(&path)?;
 
         std::fs::OpenOptions::new()
+            .write(true)
             .create_new(true)
             .open(path)
             .map(File)
@@ -313,11 +741,124 @@ impl api_ops::CreateFile for FileSystem {
     }
 }
 
+/// [api_ops::ReplaceFile::replace]が返す一時ファイル。
+///
+/// 一時ファイルは置き換え先と同じディレクトリに作られるので、コミット時のリネームは不可分です。
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: std::fs::File,
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    fn create(target: PathBuf) -> io::Result<Self> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let file_name = target
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "replace target has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+
+        loop {
+            let temp = target.with_file_name(format!(
+                ".{}.{}.{}.tmp",
+                file_name,
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&temp) {
+                Ok(file) => {
+                    let atomic = Self {
+                        file,
+                        temp,
+                        target,
+                        committed: false,
+                    };
+                    // 置き換えた後もパーミッションが変わらないように、既にあるファイルのパーミッションを引き継ぐ。
+                    if let Ok(metadata) = std::fs::metadata(&atomic.target) {
+                        atomic.file.set_permissions(metadata.permissions())?;
+                    }
+                    return Ok(atomic);
+                },
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl io::Seek for AtomicFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl io::Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl api_entity::AtomicFile for AtomicFile {
+    type E = io::Error;
+
+    fn commit(mut self) -> Result<(), Self::E> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.temp, &self.target)?;
+        self.committed = true;
+
+        // リネームを永続化する。
+        match self.target.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent),
+            _ => sync_dir(Path::new(".")),
+        }
+    }
+}
+
+impl api_entity::Durable for AtomicFile {
+    type E = io::Error;
+
+    fn sync_all(&self) -> Result<(), Self::E> {
+        self.file.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<(), Self::E> {
+        self.file.sync_data()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+impl api_ops::ReplaceFile for FileSystem {
+    type E = CreateEntityError;
+    type File = AtomicFile;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = self.resolve_new(&path)?;
+
+        AtomicFile::create(path).map_err(CreateEntityError::IoError)
+    }
+}
+
 impl FileSystem {
-    fn create_new_dir_impl(&self, path: &Path) -> Result<Dir, CreateEntityError> {
-        std::fs::create_dir_all(path)
-            .map(|_| Dir(path.to_path_buf()))
-            .map_err(Into::into)
+    fn create_new_dir_impl(&self, path: PathBuf, sub: &Path) -> Result<Dir, CreateEntityError> {
+        std::fs::create_dir_all(&path)?;
+        Dir::open(&path, sub, &self.policy).map_err(Into::into)
     }
 }
 
@@ -325,22 +866,22 @@ impl api_ops::CreateDir for FileSystem {
     type Dir = Dir;
     type E = CreateEntityError;
 
-    fn create<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(CreateEntityError::AccessError, &path)?;
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let sub = path.as_ref();
+        let path = self.resolve_new(&sub)?;
 
         if path.exists() {
-            Ok(Dir(path))
+            Dir::open(&path, sub, &self.policy).map_err(Into::into)
         } else {
-            self.create_new_dir_impl(path.as_ref())
+            self.create_new_dir_impl(path, sub)
         }
     }
 
-    fn create_new<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(CreateEntityError::AccessError, &path)?;
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let sub = path.as_ref();
+        let path = self.resolve_new(&sub)?;
 
-        self.create_new_dir_impl(path.as_ref())
+        self.create_new_dir_impl(path, sub)
     }
 }
 
@@ -348,9 +889,8 @@ impl api_ops::OpenFile for FileSystem {
     type E = OpenEntityError;
     type File = File;
 
-    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::File, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(OpenEntityError::AccessError, &path)?;
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = self.resolve(OpenEntityError::AccessError, &path)?;
 
         std::fs::OpenOptions::new()
             .read(true)
@@ -364,11 +904,12 @@ impl api_ops::OpenDir for FileSystem {
     type Dir = Dir;
     type E = OpenEntityError;
 
-    /// XXX: 現在、この呼出しではディレクトリを開かない
-    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Dir, Self::E> {
-        let path = self.current(&path);
-        let _ = check_path(OpenEntityError::AccessError, &path)?;
-        Ok(Dir(path))
+    /// ディレクトリが存在しない場合は[OpenEntityError::NotFoundError]、
+    /// ディレクトリでない場合は[OpenEntityError::NotDirError]で失敗します。
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let sub = path.as_ref();
+        let path = self.resolve(OpenEntityError::AccessError, &sub)?;
+        Dir::open(&path, sub, &self.policy).map_err(|err| OpenEntityError::from_open_dir(err, path))
     }
 }
 
@@ -381,12 +922,11 @@ mod check_path {
     #[test]
     fn llegal_subpath_start_with_current() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new(".");
-        match ops::OpenFile::open(&mut filesystem, sub) {
-            Err(OpenEntityError::AccessError(_)) => unreachable!(),
-            _ => (),
+        if let Err(OpenEntityError::AccessError(_)) = ops::OpenFile::open(&filesystem, sub) {
+            unreachable!()
         }
         Ok(())
     }
@@ -394,12 +934,11 @@ mod check_path {
     #[test]
     fn llegal_subpath_start_with_normal() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new("src");
-        match ops::OpenFile::open(&mut filesystem, sub) {
-            Err(OpenEntityError::AccessError(_)) => unreachable!(),
-            _ => (),
+        if let Err(OpenEntityError::AccessError(_)) = ops::OpenFile::open(&filesystem, sub) {
+            unreachable!()
         }
         Ok(())
     }
@@ -407,10 +946,10 @@ mod check_path {
     #[test]
     fn illegal_subpath() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new("..");
-        match ops::OpenFile::open(&mut filesystem, sub).err().unwrap() {
+        match ops::OpenFile::open(&filesystem, sub).err().unwrap() {
             OpenEntityError::AccessError(path) => assert_eq!(path, Path::new(".").join("..")),
             _ => unreachable!(),
         }
@@ -420,33 +959,283 @@ mod check_path {
     #[test]
     fn illegal_subpath2() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new(".").join(".").join("..");
-        match ops::OpenFile::open(&mut filesystem, sub).err().unwrap() {
+        match ops::OpenFile::open(&filesystem, sub).err().unwrap() {
             OpenEntityError::AccessError(path) => assert_eq!(path, Path::new(".").join(".").join("..")),
             _ => unreachable!(),
         }
         Ok(())
     }
 
+    #[test]
+    fn illegal_subpath3() -> Result<(), Box<dyn std::error::Error>> {
+        let root = Box::from(Path::new("."));
+        let filesystem = fs::FileSystem::new(root);
+
+        let sub = Path::new("src").join("..").join("..").join("src");
+        match ops::OpenFile::open(&filesystem, sub).err().unwrap() {
+            OpenEntityError::AccessError(path) => assert_eq!(path, Path::new(".").join("src/../../src")),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
     #[test]
     fn open_file() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new(".").join("src").join("fs.rs");
-        assert!(ops::OpenFile::open(&mut filesystem, sub).is_ok());
+        assert!(ops::OpenFile::open(&filesystem, sub).is_ok());
         Ok(())
     }
 
     #[test]
     fn open_dir() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let mut filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let sub = Path::new(".").join("src");
-        assert!(ops::OpenDir::open(&mut filesystem, sub).is_ok());
+        assert!(ops::OpenDir::open(&filesystem, sub).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn open_dir_not_found() -> Result<(), Box<dyn std::error::Error>> {
+        let root = Box::from(Path::new("."));
+        let filesystem = fs::FileSystem::new(root);
+
+        let sub = Path::new(".").join("no such dir");
+        match ops::OpenDir::open(&filesystem, sub).err().unwrap() {
+            OpenEntityError::NotFoundError(path) => assert_eq!(path, Path::new(".").join(".").join("no such dir")),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn open_dir_not_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let root = Box::from(Path::new("."));
+        let filesystem = fs::FileSystem::new(root);
+
+        let sub = Path::new(".").join("src").join("fs.rs");
+        match ops::OpenDir::open(&filesystem, sub).err().unwrap() {
+            OpenEntityError::NotDirError(path) => assert_eq!(path, Path::new(".").join(".").join("src").join("fs.rs")),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod replace_file {
+    use ::{
+        filesystem_provider_api::fs::{entity::AtomicFile as _, ops},
+        std::io::Write as _,
+    };
+
+    use crate::fs::{self};
+
+    #[test]
+    fn commit() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+        std::fs::write(temp.join("config"), b"old")?;
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "config")?;
+        file.write_all(b"new")?;
+        assert_eq!(std::fs::read(temp.join("config"))?, b"old");
+
+        file.commit()?;
+        assert_eq!(std::fs::read(temp.join("config"))?, b"new");
+        assert_eq!(std::fs::read_dir(&*temp)?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn discard_on_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+        std::fs::write(temp.join("config"), b"old")?;
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "config")?;
+        file.write_all(b"new")?;
+        drop(file);
+
+        assert_eq!(std::fs::read(temp.join("config"))?, b"old");
+        assert_eq!(std::fs::read_dir(&*temp)?.count(), 1);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_permissions() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+        std::fs::write(temp.join("config"), b"old")?;
+        std::fs::set_permissions(temp.join("config"), std::fs::Permissions::from_mode(0o600))?;
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "config")?;
+        file.write_all(b"new")?;
+        file.commit()?;
+
+        let mode = std::fs::metadata(temp.join("config"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod durable {
+    use ::{
+        filesystem_provider_api::fs::{entity::Durable as _, ops},
+        std::{io::Write as _, path::Path},
+    };
+
+    use crate::fs::{self};
+
+    #[test]
+    fn sync_file_and_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+
+        let dir = ops::CreateDir::create(&filesystem, "wal")?;
+        let mut file = ops::CreateFile::create_new(&filesystem, Path::new("wal").join("0001.log"))?;
+        file.write_all(b"record")?;
+
+        file.sync_data()?;
+        file.sync_all()?;
+        dir.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod lock {
+    use filesystem_provider_api::fs::{entity::Lock as _, ops};
+
+    use crate::fs::{self};
+
+    #[test]
+    fn exclusive_excludes_shared() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+
+        let writer = ops::CreateFile::create(&filesystem, "lock")?;
+        let reader = ops::OpenFile::open(&filesystem, "lock")?;
+
+        writer.lock_exclusive()?;
+        assert!(!reader.try_lock_shared()?);
+
+        writer.unlock()?;
+        assert!(reader.try_lock_shared()?);
+        assert!(!writer.try_lock_exclusive()?);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_with_shared() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = fs::FileSystem::new(Box::from(temp.as_path()));
+
+        let first = ops::CreateFile::create(&filesystem, "lock")?;
+        let second = ops::OpenFile::open(&filesystem, "lock")?;
+
+        first.lock_shared()?;
+        assert!(second.try_lock_shared()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod dir_at {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                entity::{DirAt as _, Type},
+                ops,
+            },
+            provider::make::Make as _,
+        },
+        std::{
+            io::{Read as _, Write as _},
+            path::Path,
+        },
+    };
+
+    use crate::{fs::ChildEntityError, provider::Provider};
+
+    #[test]
+    fn create_open_remove() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        let dir = ops::OpenDir::open(&filesystem, ".")?;
+
+        let sub = dir.create_dir("a/b")?;
+        sub.create_new_file("c.txt")?.write_all(b"hello")?;
+        assert!(sub.create_new_file("c.txt").is_err());
+
+        let metadata = dir.metadata("a/b/c.txt")?;
+        assert_eq!(metadata.path(), Path::new("a").join("b").join("c.txt"));
+        assert_eq!(metadata.r#type(), &Type::File);
+        assert_eq!(metadata.size(), 5);
+        assert_eq!(
+            metadata.modified(),
+            Some(std::fs::metadata(temp.join("a").join("b").join("c.txt"))?.modified()?)
+        );
+
+        let mut buf = String::new();
+        dir.open_dir("a")?.open_file("b/c.txt")?.read_to_string(&mut buf)?;
+        assert_eq!(buf, "hello");
+
+        sub.remove_file("c.txt")?;
+        assert!(matches!(
+            sub.open_file("c.txt"),
+            Err(ChildEntityError::NotFoundError(_))
+        ));
+
+        dir.create_dir("a/b/d")?;
+        dir.remove_dir("a")?;
+        assert!(!temp.join("a").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_renamed_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        let dir = ops::OpenDir::open(&filesystem, "d")?;
+
+        std::fs::rename(temp.join("d"), temp.join("renamed"))?;
+        dir.create_file("a.txt")?;
+
+        assert!(temp.join("renamed").join("a.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_name() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        let dir = ops::OpenDir::open(&filesystem, "d")?;
+
+        assert!(matches!(dir.open_file(".."), Err(ChildEntityError::AccessError(_))));
+        assert!(matches!(
+            dir.create_file("x/../../y"),
+            Err(ChildEntityError::AccessError(_))
+        ));
+        assert!(matches!(dir.remove_dir("."), Err(ChildEntityError::AccessError(_))));
+        assert!(matches!(
+            dir.open_dir(temp.as_path()),
+            Err(ChildEntityError::AccessError(_))
+        ));
         Ok(())
     }
 }
@@ -462,7 +1251,7 @@ mod dir_entries {
     #[test]
     fn it_works() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(std::path::Path::new("."));
-        let mut filesystem = crate::fs::FileSystem { root };
+        let filesystem = crate::fs::FileSystem::new(root);
 
         let dir = filesystem.open("src")?;
         let entries = dir.entries()?;
@@ -471,6 +1260,57 @@ mod dir_entries {
         }
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn immune_to_rename() -> Result<(), Box<dyn std::error::Error>> {
+        use filesystem_provider_api::provider::make::Make as _;
+
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        std::fs::write(temp.join("d").join("a.txt"), b"a")?;
+        let filesystem = crate::provider::Provider::make(temp.to_path_buf());
+
+        let dir = filesystem.open("d")?;
+        std::fs::rename(temp.join("d"), temp.join("renamed"))?;
+        std::fs::create_dir(temp.join("d"))?;
+
+        let entries = dir.entries()?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_name(), "a.txt");
+        assert_eq!(filesystem_provider_api::fs::entity::Dir::count(&dir), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn root_relative_path() -> Result<(), Box<dyn std::error::Error>> {
+        use filesystem_provider_api::{
+            fs::entity::{File as _, Type},
+            provider::make::Make as _,
+        };
+
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        std::fs::write(temp.join("d").join("a.txt"), b"a")?;
+        std::fs::create_dir(temp.join("d").join("e"))?;
+        let filesystem = crate::provider::Provider::make(temp.to_path_buf());
+
+        let dir = filesystem.open("./d")?;
+        let mut entries = dir.entries()?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        assert_eq!(entries[0].path(), std::path::Path::new("d").join("a.txt"));
+        assert_eq!(entries[0].file_name(), "a.txt");
+        assert_eq!(entries[0].file_type(), Some(Type::File));
+        assert!(entries[0].is_file());
+        assert!(filesystem.exists(entries[0].path()));
+        assert_eq!(filesystem.metadata(entries[0].path())?.size(), 1);
+
+        assert_eq!(entries[1].path(), std::path::Path::new("d").join("e"));
+        assert_eq!(entries[1].file_type(), Some(Type::Dir));
+        assert!(entries[1].is_dir());
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -485,19 +1325,20 @@ mod filesystem {
     #[test]
     fn metadata() -> Result<(), Box<dyn std::error::Error>> {
         let root = Box::from(Path::new("."));
-        let filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         let metadata = filesystem.metadata(".")?;
 
         assert_eq!(metadata.r#type(), &Type::Dir);
         assert_eq!(metadata.path(), Path::new("."));
+        assert_eq!(metadata.modified(), Some(std::fs::metadata(".")?.modified()?));
         Ok(())
     }
 
     #[test]
     fn exists() {
         let root = Box::from(Path::new("."));
-        let filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         assert!(filesystem.exists("."));
     }
@@ -505,7 +1346,7 @@ mod filesystem {
     #[test]
     fn is_file() {
         let root = Box::from(Path::new("."));
-        let filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         assert!(!filesystem.is_file("."));
     }
@@ -513,8 +1354,97 @@ mod filesystem {
     #[test]
     fn is_dir() {
         let root = Box::from(Path::new("."));
-        let filesystem = fs::FileSystem { root };
+        let filesystem = fs::FileSystem::new(root);
 
         assert!(filesystem.is_dir("."));
     }
+
+    #[test]
+    fn out_of_root() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("root"))?;
+        std::fs::write(temp.join("x"), b"x")?;
+        let filesystem = fs::FileSystem::new(temp.join("root").into_boxed_path());
+
+        let err = filesystem.metadata("../x").err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(filesystem.metadata(temp.join("x")).is_err());
+        assert!(!filesystem.exists("../x"));
+        assert!(!filesystem.is_file("../x"));
+        assert!(!filesystem.is_dir(".."));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn neither_file_nor_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let fifo = std::ffi::CString::new(temp.join("fifo").into_os_string().into_encoded_bytes())?;
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let filesystem = fs::FileSystem::new(temp.to_path_buf().into_boxed_path());
+
+        let err = filesystem.metadata("fifo").err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod name_policy {
+    use ::filesystem_provider_api::{
+        fs::{entity::DirAt as _, ops, FileSystem as _},
+        path::policy::{PolicyError, Portable},
+        provider::make::Make as _,
+    };
+
+    use crate::{
+        fs::{ChildEntityError, CreateEntityError},
+        provider::Provider,
+    };
+
+    #[test]
+    fn portable() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("aux"), b"")?;
+        let filesystem = Provider::make(temp.to_path_buf()).with_policy(Portable::default());
+
+        assert!(matches!(
+            ops::CreateFile::create(&filesystem, "con.txt"),
+            Err(CreateEntityError::PolicyError(PolicyError::ReservedNameError(_)))
+        ));
+        assert!(matches!(
+            ops::CreateDir::create_new(&filesystem, "a/b."),
+            Err(CreateEntityError::PolicyError(PolicyError::TrailingCharError(_)))
+        ));
+        assert!(matches!(
+            ops::ReplaceFile::replace(&filesystem, "a\nb"),
+            Err(CreateEntityError::PolicyError(PolicyError::ControlCharError(_)))
+        ));
+        assert!(!temp.join("a").exists());
+
+        let dir = ops::CreateDir::create(&filesystem, "a")?;
+        assert!(matches!(
+            dir.create_new_file("nul"),
+            Err(ChildEntityError::PolicyError(_))
+        ));
+        dir.create_new_file("null")?;
+
+        // 既にあるエンティティは検査しない。
+        assert!(filesystem.is_file("aux"));
+        ops::OpenFile::open(&filesystem, "aux")?;
+        ops::CreateFile::create(&filesystem, "aux")?;
+        ops::RemoveFile::remove(&filesystem, "aux")?;
+
+        // 既にあるディレクトリの下には作れるが、作るコンポーネントは検査する。
+        std::fs::create_dir(temp.join("con"))?;
+        ops::CreateFile::create(&filesystem, "con/a.txt")?;
+        let con = ops::OpenDir::open(&filesystem, "con")?;
+        con.create_new_file("b.txt")?;
+        assert!(matches!(con.create_dir("nul"), Err(ChildEntityError::PolicyError(_))));
+        assert!(matches!(
+            ops::CreateDir::create(&filesystem, "con/prn"),
+            Err(CreateEntityError::PolicyError(_))
+        ));
+        Ok(())
+    }
 }
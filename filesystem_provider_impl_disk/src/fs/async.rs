@@ -0,0 +1,301 @@
+//! [api_async]の実装。
+//!
+//! ブロックする操作はすべて同期版の実装をtokioのブロッキングスレッドプールで呼び出すことで行います。
+//! したがって、パスの検査などの振る舞いは同期版と同じです。
+
+use ::{
+    filesystem_provider_api::fs::{self as api_fs, entity as api_entity, ops as api_ops, r#async as api_async},
+    std::{
+        future::Future,
+        io,
+        path::{Path, PathBuf},
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf},
+};
+
+use crate::fs::{CreateEntityError, DirEntry, FileSystem, OpenEntityError, RemoveEntityError};
+
+/// `f`をブロッキングスレッドプールで実行します。
+async fn blocking<T, E, F>(f: F) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<io::Error> + Send + 'static,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(io::Error::other)?
+}
+
+#[derive(Debug)]
+pub struct File(tokio::fs::File);
+
+impl From<crate::fs::File> for File {
+    fn from(file: crate::fs::File) -> Self {
+        Self(tokio::fs::File::from_std(file.0))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.get_mut().0).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.get_mut().0).poll_complete(cx)
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug)]
+pub struct DirEntries(Option<crate::fs::DirEntries>);
+
+impl api_async::Entries for DirEntries {
+    type E = OpenEntityError;
+    type Entry = DirEntry;
+
+    async fn next_entry(&mut self) -> Result<Option<Self::Entry>, Self::E> {
+        let mut entries = match self.0.take() {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        let (entries, entry) = tokio::task::spawn_blocking(move || {
+            let entry = entries.next();
+            (entries, entry)
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        self.0 = Some(entries);
+        entry.transpose()
+    }
+}
+
+#[derive(Debug)]
+pub struct Dir(Arc<crate::fs::Dir>);
+
+impl From<crate::fs::Dir> for Dir {
+    fn from(dir: crate::fs::Dir) -> Self {
+        Self(Arc::new(dir))
+    }
+}
+
+impl api_async::Dir for Dir {
+    type Entries = DirEntries;
+    type EntriesE = io::Error;
+    type Entry = DirEntry;
+    type IterE = OpenEntityError;
+
+    fn entries(&self) -> impl Future<Output = Result<Self::Entries, Self::EntriesE>> + Send {
+        let dir = self.0.clone();
+        async move {
+            blocking(move || api_entity::Dir::entries(&*dir))
+                .await
+                .map(|entries| DirEntries(Some(entries)))
+        }
+    }
+}
+
+impl FileSystem {
+    /// 同期版の実装をブロッキングスレッドプールで呼び出すための準備をします。
+    fn to_blocking<P: AsRef<Path>>(&self, path: P) -> (FileSystem, PathBuf) {
+        (self.clone(), path.as_ref().to_path_buf())
+    }
+}
+
+impl api_async::FileSystem for FileSystem {
+    type MetadataE = io::Error;
+
+    fn metadata<P: AsRef<Path>>(
+        &self,
+        sub: P,
+    ) -> impl Future<Output = Result<api_entity::Metadata, Self::MetadataE>> + Send {
+        let (filesystem, sub) = self.to_blocking(sub);
+        blocking(move || api_fs::FileSystem::metadata(&filesystem, sub))
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || Ok::<_, io::Error>(api_fs::FileSystem::exists(&filesystem, path)))
+                .await
+                .unwrap_or(false)
+        }
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || Ok::<_, io::Error>(api_fs::FileSystem::is_file(&filesystem, path)))
+                .await
+                .unwrap_or(false)
+        }
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = bool> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || Ok::<_, io::Error>(api_fs::FileSystem::is_dir(&filesystem, path)))
+                .await
+                .unwrap_or(false)
+        }
+    }
+}
+
+impl api_async::OpenFile for FileSystem {
+    type E = OpenEntityError;
+    type File = File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::OpenFile::open(&filesystem, path))
+                .await
+                .map(File::from)
+        }
+    }
+}
+
+impl api_async::OpenDir for FileSystem {
+    type Dir = Dir;
+    type E = OpenEntityError;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::OpenDir::open(&filesystem, path))
+                .await
+                .map(Dir::from)
+        }
+    }
+}
+
+impl api_async::CreateFile for FileSystem {
+    type E = CreateEntityError;
+    type File = File;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::CreateFile::create(&filesystem, path))
+                .await
+                .map(File::from)
+        }
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::File, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::CreateFile::create_new(&filesystem, path))
+                .await
+                .map(File::from)
+        }
+    }
+}
+
+impl api_async::CreateDir for FileSystem {
+    type Dir = Dir;
+    type E = CreateEntityError;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::CreateDir::create(&filesystem, path))
+                .await
+                .map(Dir::from)
+        }
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Self::Dir, Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        async move {
+            blocking(move || api_ops::CreateDir::create_new(&filesystem, path))
+                .await
+                .map(Dir::from)
+        }
+    }
+}
+
+impl api_async::RemoveFile for FileSystem {
+    type E = RemoveEntityError;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<(), Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        blocking(move || api_ops::RemoveFile::remove(&filesystem, path))
+    }
+}
+
+impl api_async::RemoveDir for FileSystem {
+    type E = RemoveEntityError;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<(), Self::E>> + Send {
+        let (filesystem, path) = self.to_blocking(path);
+        blocking(move || api_ops::RemoveDir::remove(&filesystem, path))
+    }
+}
+
+#[cfg(test)]
+mod asynchronous {
+    use ::{
+        filesystem_provider_api::fs::r#async::{self as ops, CreateFile as _, Dir as _, Entries as _, FileSystem as _},
+        std::path::Path,
+        tokio::io::{AsyncReadExt as _, AsyncWriteExt as _},
+    };
+
+    #[tokio::test]
+    async fn create_and_open_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = crate::fs::FileSystem::new(Box::from(temp.as_path()));
+        let sub = Path::new("a.txt");
+
+        let mut file = filesystem.create(sub).await?;
+        file.write_all(b"hello").await?;
+        file.flush().await?;
+        drop(file);
+
+        assert!(filesystem.is_file(sub).await);
+
+        let mut buf = String::new();
+        ops::OpenFile::open(&filesystem, sub)
+            .await?
+            .read_to_string(&mut buf)
+            .await?;
+        assert_eq!(buf, "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn entries() -> Result<(), Box<dyn std::error::Error>> {
+        let root = Box::from(Path::new("."));
+        let filesystem = crate::fs::FileSystem::new(root);
+
+        let dir = ops::OpenDir::open(&filesystem, "src").await?;
+        let mut entries = dir.entries().await?;
+        let mut count = 0;
+        while entries.next_entry().await?.is_some() {
+            count += 1;
+        }
+        assert!(count > 0);
+        Ok(())
+    }
+}
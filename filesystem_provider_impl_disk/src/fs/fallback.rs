@@ -0,0 +1,112 @@
+//! UNIX以外のプラットフォームで[super::sys]の代わりに使う、パスによる実装。
+//!
+//! ディレクトリのハンドルは開いた時点のパスを保持するだけなので、開いた後にディレクトリがリネームされると追従できません。
+
+use ::{
+    filesystem_provider_api::fs::entity as api_entity,
+    std::{
+        ffi::OsString,
+        io,
+        path::{Path, PathBuf},
+    },
+};
+
+use crate::fs::{EntryType, OpenMode, Stat};
+
+/// ディレクトリのハンドルです。ディレクトリのパスを保持します。
+pub(crate) type Handle = PathBuf;
+
+/// `dir`を基準に`path`のディレクトリを開きます。`dir`が`None`の場合はカレントディレクトリを基準とします。
+pub(crate) fn open_dir_at(dir: Option<&PathBuf>, path: &Path) -> io::Result<PathBuf> {
+    let path = dir.map_or_else(|| path.to_path_buf(), |dir| dir.join(path));
+    if !std::fs::metadata(&path)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory, "not a directory"));
+    }
+    Ok(path)
+}
+
+/// `dir`を基準に`path`のファイルを`mode`で開きます。
+pub(crate) fn open_file_at(dir: &Path, path: &Path, mode: OpenMode) -> io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    match mode {
+        OpenMode::Read => options.read(true),
+        OpenMode::Truncate => options.write(true).create(true).truncate(true),
+        OpenMode::CreateNew => options.write(true).create_new(true),
+    };
+    options.open(dir.join(path))
+}
+
+/// ディレクトリをfsyncできないプラットフォームがあるので何もしません。
+pub(crate) fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// ディレクトリのハンドルのメタデータを返します。
+pub(crate) fn metadata(dir: &Path) -> io::Result<std::fs::Metadata> {
+    std::fs::metadata(dir)
+}
+
+/// `dir`を基準に`path`をstatします。`follow`が`false`の場合はシンボリックリンクを辿りません。
+pub(crate) fn stat_at(dir: &Path, path: &Path, follow: bool) -> io::Result<Stat> {
+    let metadata = match follow {
+        true => std::fs::metadata(dir.join(path))?,
+        false => std::fs::symlink_metadata(dir.join(path))?,
+    };
+    Ok(Stat {
+        file_type: file_type(&metadata.file_type()),
+        size: metadata.len(),
+        modified: metadata.modified()?,
+    })
+}
+
+/// `dir`を基準に`path`のディレクトリを作ります。
+pub(crate) fn mkdir_at(dir: &Path, path: &Path) -> io::Result<()> {
+    std::fs::create_dir(dir.join(path))
+}
+
+/// `dir`を基準に`path`のファイルを削除します。
+pub(crate) fn unlink_at(dir: &Path, path: &Path) -> io::Result<()> {
+    std::fs::remove_file(dir.join(path))
+}
+
+/// `dir`を基準に`path`のディレクトリをその中身ごと削除します。シンボリックリンクは辿りません。
+pub(crate) fn remove_dir_all_at(dir: &Path, path: &Path) -> io::Result<()> {
+    std::fs::remove_dir_all(dir.join(path))
+}
+
+fn file_type(file_type: &std::fs::FileType) -> Option<api_entity::Type> {
+    if file_type.is_file() {
+        Some(api_entity::Type::File)
+    } else if file_type.is_dir() {
+        Some(api_entity::Type::Dir)
+    } else {
+        None
+    }
+}
+
+/// ディレクトリのエントリの名前と種類を読みます。
+#[derive(Debug)]
+pub(crate) struct ReadDir(std::fs::ReadDir);
+
+impl ReadDir {
+    pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::read_dir(dir).map(Self)
+    }
+}
+
+impl std::iter::Iterator for ReadDir {
+    type Item = io::Result<(OsString, EntryType)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match self.0.next()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        let entry_type = match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => EntryType::Unknown,
+            Ok(file_type) => EntryType::Known(self::file_type(&file_type)),
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Ok((entry.file_name(), entry_type)))
+    }
+}
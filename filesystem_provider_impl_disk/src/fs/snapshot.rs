@@ -0,0 +1,283 @@
+//! [api_snapshot]の実装。
+//!
+//! スナップショットは基底パスの下の[RESERVED_DIR]の[SNAPSHOT_DIR]に作られるツリーの複製です。
+//! [RESERVED_DIR]は元のファイルシステムからは見えず、操作することも出来ません。
+//! 同じファイルシステム上に置くので、Linuxでreflinkをサポートするファイルシステム（Btrfs、XFSなど）では
+//! ファイルの内容を共有して安価に複製できます。reflinkが使えない場合は内容をコピーします。
+//!
+//! ハードリンクは使いません。[crate::fs::File]は既存のファイルをその場で書き換えるので、
+//! ハードリンクではスナップショットの内容まで変わってしまうからです。
+
+use ::{
+    filesystem_provider_api::fs::{self as api_fs, entity as api_entity, ops as api_ops, snapshot as api_snapshot},
+    std::{
+        io,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::fs::{remove_empty_reserved, Dir, File, FileSystem, OpenEntityError, RESERVED_DIR};
+
+/// スナップショットを置く、[RESERVED_DIR]の下のディレクトリの名前です。
+pub const SNAPSHOT_DIR: &str = "snapshot";
+
+/// ファイルの内容を複製します。可能であればreflinkを使います。
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mut src = std::fs::File::open(from)?;
+    let mut dst = std::fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+
+    #[cfg(target_os = "linux")]
+    let copied = crate::fs::sys::reflink(&src, &dst).is_ok();
+    #[cfg(not(target_os = "linux"))]
+    let copied = false;
+
+    if !copied {
+        io::copy(&mut src, &mut dst)?;
+    }
+    dst.set_permissions(src.metadata()?.permissions())
+}
+
+/// `from`のディレクトリツリーを`to`に複製します。シンボリックリンクはリンクのまま複製します。
+/// `skip`のディレクトリは複製しません。
+fn copy_tree(from: &Path, to: &Path, skip: &Path) -> io::Result<()> {
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let (from, to) = (entry.path(), to.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if from == skip {
+            continue;
+        } else if file_type.is_dir() {
+            copy_tree(&from, &to, skip)?;
+        } else if file_type.is_file() {
+            copy_file(&from, &to)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(&from)?, &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// [api_snapshot::Snapshot::snapshot]が返す読み込み専用のファイルシステムです。破棄すると複製したツリーは削除されます。
+#[derive(Debug)]
+pub struct Snapshot {
+    filesystem: FileSystem,
+}
+
+impl Snapshot {
+    /// 複製したツリーのパスです。
+    pub fn root(&self) -> &Path {
+        &self.filesystem.root
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.filesystem.root);
+        if let Some(parent) = self.filesystem.root.parent() {
+            let _ = remove_empty_reserved(parent);
+        }
+    }
+}
+
+impl api_snapshot::Snapshot for FileSystem {
+    type E = io::Error;
+    type Snapshot = Snapshot;
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Self::E> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let root = std::fs::canonicalize(&self.root)?;
+        let reserved = root.join(RESERVED_DIR);
+        let snapshots = reserved.join(SNAPSHOT_DIR);
+        std::fs::create_dir_all(&snapshots)?;
+        let dir = snapshots.join(format!(
+            "{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // 途中で失敗しても複製したツリーを削除できるように、先にSnapshotを作る。
+        let snapshot = Snapshot {
+            filesystem: FileSystem {
+                root: PathBuf::from(&dir).into_boxed_path(),
+                policy: self.policy.clone(),
+            },
+        };
+        copy_tree(&root, &dir, &reserved)?;
+        Ok(snapshot)
+    }
+}
+
+impl api_fs::Introspect for Snapshot {
+    fn is_readable(&self) -> bool {
+        true
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    fn is_appendable(&self) -> bool {
+        false
+    }
+
+    fn is_truncatable(&self) -> bool {
+        false
+    }
+
+    fn is_removable(&self) -> bool {
+        false
+    }
+}
+
+impl api_fs::FileSystem for Snapshot {
+    type MetadataE = io::Error;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<api_entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.filesystem.metadata(sub)
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.filesystem.exists(path)
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.filesystem.is_file(path)
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.filesystem.is_dir(path)
+    }
+}
+
+impl api_ops::OpenFile for Snapshot {
+    type E = OpenEntityError;
+    type File = File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        api_ops::OpenFile::open(&self.filesystem, path)
+    }
+}
+
+impl api_ops::OpenDir for Snapshot {
+    type Dir = Dir;
+    type E = OpenEntityError;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        api_ops::OpenDir::open(&self.filesystem, path)
+    }
+}
+
+#[cfg(test)]
+mod frozen {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                entity::{Dir as _, DirAt as _, DirEntry as _},
+                ops,
+                snapshot::Snapshot as _,
+                FileSystem as _, Introspect as _,
+            },
+            provider::make::Make as _,
+        },
+        std::io::{Read as _, Write as _},
+    };
+
+    use crate::{fs::RESERVED_DIR, provider::Provider};
+
+    #[test]
+    fn it_works() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::create_dir(temp.join("d"))?;
+        std::fs::write(temp.join("d").join("b.txt"), b"b")?;
+        std::fs::write(temp.join("a.txt"), b"before")?;
+
+        let snapshot = filesystem.snapshot()?;
+        assert!(!snapshot.is_writable());
+
+        ops::CreateFile::create(&filesystem, "a.txt")?.write_all(b"after")?;
+        ops::CreateFile::create(&filesystem, "c.txt")?;
+        ops::RemoveDir::remove(&filesystem, "d")?;
+
+        let mut buf = String::new();
+        ops::OpenFile::open(&snapshot, "a.txt")?.read_to_string(&mut buf)?;
+        assert_eq!(buf, "before");
+        assert!(snapshot.is_file("d/b.txt"));
+        assert!(!snapshot.exists("c.txt"));
+
+        let root = snapshot.root().to_path_buf();
+        assert!(root.starts_with(std::fs::canonicalize(&temp)?));
+        assert!(root.is_dir());
+        drop(snapshot);
+        assert!(!root.exists());
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_from_live_view() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::write(temp.join("a.txt"), b"a")?;
+
+        let first = filesystem.snapshot()?;
+        let second = filesystem.snapshot()?;
+        assert!(temp.join(RESERVED_DIR).is_dir());
+        assert!(!second.exists(RESERVED_DIR));
+
+        let root = ops::OpenDir::open(&filesystem, ".")?;
+        assert_eq!(root.count(), 1);
+        let names = root
+            .entries()?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(names, vec!["a.txt"]);
+
+        assert!(!filesystem.exists(RESERVED_DIR));
+        assert!(ops::OpenDir::open(&filesystem, format!("x/../{}", RESERVED_DIR)).is_err());
+        assert!(ops::RemoveDir::remove(&filesystem, RESERVED_DIR).is_err());
+        assert!(ops::CreateFile::create(&filesystem, format!("{}/b.txt", RESERVED_DIR)).is_err());
+        assert!(root.open_dir(RESERVED_DIR).is_err());
+        assert!(filesystem.transaction()?.remove_dir(RESERVED_DIR).is_err());
+
+        drop(first);
+        assert!(second.is_file("a.txt"));
+        drop(second);
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn keep_user_snapshot_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::create_dir(temp.join(".snapshot"))?;
+        std::fs::write(temp.join(".snapshot").join("a.txt"), b"a")?;
+
+        let snapshot = filesystem.snapshot()?;
+        assert!(filesystem.is_file(".snapshot/a.txt"));
+        assert!(snapshot.is_file(".snapshot/a.txt"));
+        assert_eq!(ops::OpenDir::open(&filesystem, ".")?.count(), 1);
+
+        drop(snapshot);
+        assert!(temp.join(".snapshot").join("a.txt").is_file());
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+}
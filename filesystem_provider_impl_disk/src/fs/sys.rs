@@ -0,0 +1,211 @@
+//! ディレクトリのファイル記述子を扱うためのUNIXのシステムコールの薄いラッパー。
+//!
+//! UNIX以外では同じ名前の関数をパスで実装した[super::fallback]が使われます。
+
+use ::{
+    filesystem_provider_api::fs::entity as api_entity,
+    std::{
+        ffi::{CStr, CString, OsStr, OsString},
+        io,
+        os::unix::{
+            ffi::OsStrExt,
+            io::{AsRawFd, FromRawFd, IntoRawFd},
+        },
+        path::Path,
+        ptr::NonNull,
+    },
+};
+
+use crate::fs::{EntryType, OpenMode, Stat};
+
+/// ディレクトリのハンドルです。ディレクトリを開いたファイル記述子を保持します。
+pub(crate) type Handle = std::fs::File;
+
+fn cstring<P: AsRef<OsStr>>(path: P) -> io::Result<CString> {
+    CString::new(path.as_ref().as_bytes()).map_err(Into::into)
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// `dir`を基準に`path`を`flags`で開きます。`dir`が`None`の場合はカレントディレクトリを基準とします。
+fn open_at(dir: Option<&std::fs::File>, path: &Path, flags: libc::c_int) -> io::Result<std::fs::File> {
+    let path = cstring(path)?;
+    let dirfd = dir.map_or(libc::AT_FDCWD, AsRawFd::as_raw_fd);
+    let fd = cvt(unsafe { libc::openat(dirfd, path.as_ptr(), flags | libc::O_CLOEXEC, 0o666) })?;
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+/// `dir`を基準に`path`のディレクトリを開きます。
+pub(crate) fn open_dir_at(dir: Option<&std::fs::File>, path: &Path) -> io::Result<std::fs::File> {
+    open_at(dir, path, libc::O_RDONLY | libc::O_DIRECTORY)
+}
+
+/// `dir`を基準に`path`のファイルを`mode`で開きます。
+pub(crate) fn open_file_at(dir: &std::fs::File, path: &Path, mode: OpenMode) -> io::Result<std::fs::File> {
+    let flags = match mode {
+        OpenMode::Read => libc::O_RDONLY,
+        OpenMode::Truncate => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+        OpenMode::CreateNew => libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+    };
+    open_at(Some(dir), path, flags)
+}
+
+/// ディレクトリのハンドルをfsyncします。
+pub(crate) fn sync_dir(dir: &std::fs::File) -> io::Result<()> {
+    dir.sync_all()
+}
+
+/// ディレクトリのハンドルのメタデータを返します。
+pub(crate) fn metadata(dir: &std::fs::File) -> io::Result<std::fs::Metadata> {
+    dir.metadata()
+}
+
+/// `dir`を基準に`path`をstatします。`follow`が`false`の場合はシンボリックリンクを辿りません。
+pub(crate) fn stat_at(dir: &std::fs::File, path: &Path, follow: bool) -> io::Result<Stat> {
+    let stat = raw_stat_at(dir, path, follow)?;
+    Ok(Stat {
+        file_type: file_type(&stat),
+        size: stat.st_size as u64,
+        modified: modified(&stat),
+    })
+}
+
+fn raw_stat_at(dir: &std::fs::File, path: &Path, follow: bool) -> io::Result<libc::stat> {
+    let path = cstring(path)?;
+    let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    cvt(unsafe { libc::fstatat(dir.as_raw_fd(), path.as_ptr(), stat.as_mut_ptr(), flags) })?;
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// `dir`を基準に`path`のディレクトリを作ります。
+pub(crate) fn mkdir_at(dir: &std::fs::File, path: &Path) -> io::Result<()> {
+    let path = cstring(path)?;
+    cvt(unsafe { libc::mkdirat(dir.as_raw_fd(), path.as_ptr(), 0o777) }).map(|_| ())
+}
+
+/// `dir`を基準に`path`のファイルを削除します。
+pub(crate) fn unlink_at(dir: &std::fs::File, path: &Path) -> io::Result<()> {
+    remove_at(dir, path, 0)
+}
+
+/// `dir`を基準に`path`を削除します。ディレクトリを削除する場合は`flags`に`AT_REMOVEDIR`を指定します。
+fn remove_at(dir: &std::fs::File, path: &Path, flags: libc::c_int) -> io::Result<()> {
+    let path = cstring(path)?;
+    cvt(unsafe { libc::unlinkat(dir.as_raw_fd(), path.as_ptr(), flags) }).map(|_| ())
+}
+
+/// `dir`を基準に`path`のディレクトリをその中身ごと削除します。シンボリックリンクは辿りません。
+pub(crate) fn remove_dir_all_at(dir: &std::fs::File, path: &Path) -> io::Result<()> {
+    let child = open_at(Some(dir), path, libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW)?;
+    for entry in ReadDir::new(&child)? {
+        let (name, entry_type) = entry?;
+        let name = Path::new(&name);
+        let is_dir = match entry_type {
+            EntryType::Known(file_type) => file_type == Some(api_entity::Type::Dir),
+            EntryType::Unknown => file_type(&raw_stat_at(&child, name, false)?) == Some(api_entity::Type::Dir),
+        };
+        if is_dir {
+            remove_dir_all_at(&child, name)?;
+        } else {
+            remove_at(&child, name, 0)?;
+        }
+    }
+    remove_at(dir, path, libc::AT_REMOVEDIR)
+}
+
+/// statの結果からエンティティの種類を返します。
+fn file_type(stat: &libc::stat) -> Option<api_entity::Type> {
+    match stat.st_mode & libc::S_IFMT {
+        libc::S_IFREG => Some(api_entity::Type::File),
+        libc::S_IFDIR => Some(api_entity::Type::Dir),
+        _ => None,
+    }
+}
+
+/// statの結果から最終更新日時を返します。
+fn modified(stat: &libc::stat) -> std::time::SystemTime {
+    let since_epoch = |secs: i64, nanos: i64| std::time::Duration::new(secs.unsigned_abs(), nanos as u32);
+    // time_tとc_longの幅はプラットフォームによって異なる。
+    #[allow(clippy::unnecessary_cast)]
+    let (secs, nanos) = (stat.st_mtime as i64, stat.st_mtime_nsec as i64);
+    if secs >= 0 {
+        std::time::UNIX_EPOCH + since_epoch(secs, nanos)
+    } else {
+        std::time::UNIX_EPOCH - since_epoch(secs, 0) + since_epoch(0, nanos)
+    }
+}
+
+/// ディレクトリのファイル記述子からエントリの名前と種類を読みます。`.`と`..`は含みません。
+#[derive(Debug)]
+pub(crate) struct ReadDir(NonNull<libc::DIR>);
+
+// DIRストリームはこの構造体だけが所有するので、スレッド間で移動しても問題ない。
+unsafe impl Send for ReadDir {}
+
+impl ReadDir {
+    /// `dir`とは読み込み位置を共有しないように、`dir`を改めて開いてから読みます。
+    pub(crate) fn new(dir: &std::fs::File) -> io::Result<Self> {
+        let fd = open_dir_at(Some(dir), Path::new("."))?.into_raw_fd();
+        match NonNull::new(unsafe { libc::fdopendir(fd) }) {
+            Some(stream) => Ok(Self(stream)),
+            None => {
+                let err = io::Error::last_os_error();
+                unsafe { libc::close(fd) };
+                Err(err)
+            },
+        }
+    }
+}
+
+impl std::iter::Iterator for ReadDir {
+    type Item = io::Result<(OsString, EntryType)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // readdirは終端ではerrnoを変更せず、エラーの場合のみerrnoを設定する。
+            // errnoを書き換える移植可能な方法はないので、呼び出し前の値から変わったかで区別する。
+            let errno = io::Error::last_os_error().raw_os_error();
+            let entry = unsafe { libc::readdir(self.0.as_ptr()) };
+            if entry.is_null() {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(0) | None => None,
+                    code if code == errno => None,
+                    Some(_) => Some(Err(err)),
+                };
+            }
+
+            let (name, d_type) = unsafe { (CStr::from_ptr((*entry).d_name.as_ptr()), (*entry).d_type) };
+            let name = name.to_bytes();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let entry_type = match d_type {
+                libc::DT_REG => EntryType::Known(Some(api_entity::Type::File)),
+                libc::DT_DIR => EntryType::Known(Some(api_entity::Type::Dir)),
+                libc::DT_LNK | libc::DT_UNKNOWN => EntryType::Unknown,
+                _ => EntryType::Known(None),
+            };
+            return Some(Ok((OsStr::from_bytes(name).to_os_string(), entry_type)));
+        }
+    }
+}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0.as_ptr()) };
+    }
+}
+
+/// `dst`を`src`と内容を共有するreflinkにします。ファイルシステムがサポートしない場合は失敗します。
+#[cfg(target_os = "linux")]
+pub(crate) fn reflink(src: &std::fs::File, dst: &std::fs::File) -> io::Result<()> {
+    cvt(unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) }).map(|_| ())
+}
@@ -0,0 +1,648 @@
+//! 複数の操作をすべて適用するか、まったく適用しないかのどちらかにするトランザクション。
+//!
+//! 操作は[Transaction]に積まれ、[Transaction::commit]で順に適用されます。
+//! 適用の途中で失敗した場合は、既に適用した操作をジャーナルを使って元に戻します。
+//!
+//! ジャーナルは基底パスの下の[RESERVED_DIR]の[JOURNAL_DIR]にトランザクションごとに作られます。
+//! [RESERVED_DIR]はファイルシステムからは見えず、トランザクションからも操作できません。
+//! 各操作は適用する前にジャーナルに記録されるので、コミットの途中でプロセスが異常終了しても
+//! [FileSystem::recover]で元に戻すことができます。
+//! すべての操作を適用して永続化した後にはコミットの記録が書かれ、[FileSystem::recover]はその後に中断したトランザクションを元に戻しません。
+//! 書き込むファイルの内容や、上書きまたは削除されるエンティティもこのディレクトリに退避されます。
+//! 同じファイルシステム上にあるので、退避と復元はリネームで済みます。
+//!
+//! トランザクションは他のトランザクションやトランザクションを使わない操作からの分離を保証しません。
+
+use ::{
+    filesystem_provider_api::path::policy::PolicyError,
+    std::{
+        collections::BTreeSet,
+        ffi::OsStr,
+        io::{self, Read as _, Write as _},
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::fs::{check_path, is_hidden, normalize, remove_empty_reserved, sync_dir, FileSystem, RESERVED_DIR};
+
+/// ジャーナルを置く、[RESERVED_DIR]の下のディレクトリの名前です。
+pub const JOURNAL_DIR: &str = "transaction";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError {
+    #[error("out of access {0:?}")]
+    AccessError(PathBuf),
+    #[error("entity not found {0:?}")]
+    NotFoundError(PathBuf),
+    #[error("not a directory {0:?}")]
+    NotDirError(PathBuf),
+    #[error("not a file {0:?}")]
+    NotFileError(PathBuf),
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    PolicyError(#[from]#[source]PolicyError),
+    #[error("rollback failed after {cause}: {rollback:?}")]
+    RollbackError {
+        cause: Box<TransactionError>,
+        #[source]
+        rollback: io::Error,
+    },
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    IoError(#[from]#[source]io::Error),
+}
+
+#[derive(Debug)]
+enum Step {
+    CreateDir(PathBuf),
+    /// 書き込むファイルと、その内容を退避した一時ファイルの番号です。
+    WriteFile(PathBuf, u64),
+    RemoveFile(PathBuf),
+    RemoveDir(PathBuf),
+    Rename(PathBuf, PathBuf),
+}
+
+/// ジャーナルの記録です。パスは基底パスを基準としたサブパスです。
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Record {
+    /// エンティティを作った。元に戻す時は削除する。
+    Created(PathBuf),
+    /// エンティティを退避した。元に戻す時は退避したエンティティを戻す。
+    Replaced(PathBuf, u64),
+    /// エンティティを移動した。元に戻す時は移動元に戻す。
+    Renamed(PathBuf, PathBuf),
+    /// すべての操作を適用して永続化した。これ以前の記録は元に戻さない。
+    Committed,
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        fn path(bytes: &mut Vec<u8>, path: &Path) {
+            let path = path.as_os_str().as_encoded_bytes();
+            bytes.extend_from_slice(&(path.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(path);
+        }
+
+        let mut bytes = Vec::new();
+        match self {
+            Record::Created(target) => {
+                bytes.push(b'c');
+                path(&mut bytes, target);
+            },
+            Record::Replaced(target, backup) => {
+                bytes.push(b'r');
+                path(&mut bytes, target);
+                bytes.extend_from_slice(&backup.to_le_bytes());
+            },
+            Record::Renamed(from, to) => {
+                bytes.push(b'm');
+                path(&mut bytes, from);
+                path(&mut bytes, to);
+            },
+            Record::Committed => bytes.push(b'd'),
+        }
+        bytes
+    }
+
+    /// ジャーナルを読みます。末尾の不完全な記録は、その操作が適用される前に中断したものなので無視します。
+    fn decode_all(mut bytes: &[u8]) -> Vec<Record> {
+        fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            if bytes.len() < n {
+                return None;
+            }
+            let (head, tail) = bytes.split_at(n);
+            *bytes = tail;
+            Some(head)
+        }
+        fn number(bytes: &mut &[u8]) -> Option<u64> {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(take(bytes, 8)?);
+            Some(u64::from_le_bytes(buf))
+        }
+        fn path(bytes: &mut &[u8]) -> Option<PathBuf> {
+            let len = number(bytes)? as usize;
+            // このジャーナルは同じプラットフォームのas_encoded_bytesで書かれたものである。
+            let path = unsafe { OsStr::from_encoded_bytes_unchecked(take(bytes, len)?) };
+            Some(PathBuf::from(path))
+        }
+        fn record(bytes: &mut &[u8]) -> Option<Record> {
+            match take(bytes, 1)?[0] {
+                b'c' => Some(Record::Created(path(bytes)?)),
+                b'r' => Some(Record::Replaced(path(bytes)?, number(bytes)?)),
+                b'm' => Some(Record::Renamed(path(bytes)?, path(bytes)?)),
+                b'd' => Some(Record::Committed),
+                _ => None,
+            }
+        }
+
+        let mut records = Vec::new();
+        while let Some(record) = record(&mut bytes) {
+            records.push(record);
+        }
+        records
+    }
+}
+
+/// 退避したエンティティや書き込む内容を置くパスです。
+fn backup_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{}.bak", number))
+}
+
+/// エンティティがあれば削除します。ディレクトリの場合は中身ごと削除します。
+fn remove_entity(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// 記録を元に戻します。記録した操作が途中までしか適用されていなくても失敗しません。
+fn undo(root: &Path, dir: &Path, record: &Record) -> io::Result<()> {
+    match record {
+        Record::Created(path) => remove_entity(&root.join(path)),
+        Record::Replaced(path, number) => {
+            let backup = backup_path(dir, *number);
+            if std::fs::symlink_metadata(&backup).is_ok() {
+                remove_entity(&root.join(path))?;
+                std::fs::rename(backup, root.join(path))?;
+            }
+            Ok(())
+        },
+        Record::Renamed(from, to) => {
+            let (from, to) = (root.join(from), root.join(to));
+            if std::fs::symlink_metadata(&from).is_err() && std::fs::symlink_metadata(&to).is_ok() {
+                std::fs::rename(to, from)?;
+            }
+            Ok(())
+        },
+        Record::Committed => Ok(()),
+    }
+}
+
+/// 記録した操作で変更された可能性のあるディレクトリです。
+fn changed_dirs(root: &Path, records: &[Record]) -> BTreeSet<PathBuf> {
+    let parent = |path: &Path| root.join(path).parent().map(Path::to_path_buf);
+    records
+        .iter()
+        .flat_map(|record| match record {
+            Record::Created(path) | Record::Replaced(path, _) => vec![parent(path)],
+            Record::Renamed(from, to) => vec![parent(from), parent(to)],
+            Record::Committed => vec![],
+        })
+        .flatten()
+        .collect()
+}
+
+/// [FileSystem::transaction]が返すトランザクションです。
+///
+/// [Transaction::commit]を呼び出さずに破棄した場合、積んだ操作は適用されません。
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    filesystem: &'a FileSystem,
+    dir: PathBuf,
+    journal: std::fs::File,
+    steps: Vec<Step>,
+    next: u64,
+    /// ロールバックに失敗した場合、[FileSystem::recover]のためにジャーナルを残す。
+    keep: bool,
+}
+
+impl Transaction<'_> {
+    /// サブパスを検査して正規化します。基底パス自身や[RESERVED_DIR]を指すことは出来ません。
+    fn check<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf, TransactionError> {
+        let path = path.as_ref();
+        check_path(TransactionError::AccessError, path)?;
+        let normalized = normalize(path);
+        if normalized.as_os_str().is_empty() || is_hidden(&normalized) {
+            return Err(TransactionError::AccessError(path.to_path_buf()));
+        }
+        Ok(normalized)
+    }
+
+    /// 作るエンティティのサブパスを[Transaction::check]に加えてファイルシステムの[crate::fs::FileSystem::policy]で検査します。
+    /// 既にあるコンポーネントの名前は検査しません。
+    fn check_new<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf, TransactionError> {
+        let path = self.check(path)?;
+        self.filesystem.check_policy(&path)?;
+        Ok(path)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+
+    /// ディレクトリを作る操作を積みます。存在しない親ディレクトリも作られます。
+    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), TransactionError> {
+        let path = self.check_new(path)?;
+        self.steps.push(Step::CreateDir(path));
+        Ok(())
+    }
+
+    /// ファイルを`contents`で作るか置き換える操作を積みます。`contents`はこの時点でジャーナルに退避されます。
+    pub fn write_file<P: AsRef<Path>>(&mut self, path: P, contents: &[u8]) -> Result<(), TransactionError> {
+        let path = self.check_new(path)?;
+        let number = self.next();
+
+        let mut staged = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(backup_path(&self.dir, number))?;
+        staged.write_all(contents)?;
+        staged.sync_all()?;
+
+        self.steps.push(Step::WriteFile(path, number));
+        Ok(())
+    }
+
+    /// ファイルを削除する操作を積みます。
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), TransactionError> {
+        let path = self.check(path)?;
+        self.steps.push(Step::RemoveFile(path));
+        Ok(())
+    }
+
+    /// ディレクトリをその中身ごと削除する操作を積みます。
+    pub fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), TransactionError> {
+        let path = self.check(path)?;
+        self.steps.push(Step::RemoveDir(path));
+        Ok(())
+    }
+
+    /// エンティティを移動する操作を積みます。移動先にエンティティがある場合は置き換えます。
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> Result<(), TransactionError> {
+        let (from, to) = (self.check(from)?, self.check_new(to)?);
+        self.steps.push(Step::Rename(from, to));
+        Ok(())
+    }
+
+    /// 積んだ操作を順に適用します。
+    ///
+    /// 失敗した場合は既に適用した操作を元に戻して、失敗の原因を返します。
+    /// 元に戻すことにも失敗した場合は[TransactionError::RollbackError]を返し、ジャーナルは残されます。
+    pub fn commit(mut self) -> Result<(), TransactionError> {
+        let mut applied = Vec::new();
+        let steps = std::mem::take(&mut self.steps);
+        let result = steps.into_iter().try_for_each(|step| self.apply(step, &mut applied));
+
+        let err = match result.and_then(|_| self.finish(&applied)) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let root = &self.filesystem.root;
+        match applied
+            .iter()
+            .rev()
+            .try_for_each(|record| undo(root, &self.dir, record))
+        {
+            Ok(()) => Err(err),
+            Err(rollback) => {
+                self.keep = true;
+                Err(TransactionError::RollbackError {
+                    cause: Box::new(err),
+                    rollback,
+                })
+            },
+        }
+    }
+
+    /// 適用した操作を永続化してから、コミットの記録をジャーナルに永続化します。
+    fn finish(&mut self, applied: &[Record]) -> Result<(), TransactionError> {
+        for dir in changed_dirs(&self.filesystem.root, applied) {
+            sync_dir(&dir)?;
+        }
+        sync_dir(&self.dir)?;
+        self.journal.write_all(&Record::Committed.encode())?;
+        self.journal.sync_data()?;
+        Ok(())
+    }
+
+    /// 記録をジャーナルに永続化してから`applied`に加えます。
+    fn record(&mut self, record: Record, applied: &mut Vec<Record>) -> io::Result<()> {
+        self.journal.write_all(&record.encode())?;
+        self.journal.sync_data()?;
+        applied.push(record);
+        Ok(())
+    }
+
+    /// `path`のエンティティをジャーナルに退避します。退避は移動元と移動先のディレクトリを同期して永続化します。
+    fn backup(&mut self, path: &Path, applied: &mut Vec<Record>) -> io::Result<()> {
+        let number = self.next();
+        self.record(Record::Replaced(path.to_path_buf(), number), applied)?;
+        let path = self.filesystem.root.join(path);
+        std::fs::rename(&path, backup_path(&self.dir, number))?;
+        if let Some(parent) = path.parent() {
+            sync_dir(parent)?;
+        }
+        sync_dir(&self.dir)
+    }
+
+    fn apply(&mut self, step: Step, applied: &mut Vec<Record>) -> Result<(), TransactionError> {
+        let root = self.filesystem.root.to_path_buf();
+        let metadata = |path: &Path| match std::fs::symlink_metadata(root.join(path)) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(TransactionError::IoError(err)),
+        };
+
+        match step {
+            Step::CreateDir(path) => {
+                let mut current = PathBuf::new();
+                for component in path.components() {
+                    current.push(component);
+                    match metadata(&current)? {
+                        Some(metadata) if metadata.is_dir() => (),
+                        Some(_) => return Err(TransactionError::NotDirError(current)),
+                        None => {
+                            self.record(Record::Created(current.clone()), applied)?;
+                            std::fs::create_dir(root.join(&current))?;
+                        },
+                    }
+                }
+            },
+            Step::WriteFile(path, number) => {
+                match metadata(&path)? {
+                    Some(metadata) if metadata.is_dir() => return Err(TransactionError::NotFileError(path)),
+                    Some(_) => self.backup(&path, applied)?,
+                    None => (),
+                }
+                self.record(Record::Created(path.clone()), applied)?;
+                std::fs::rename(backup_path(&self.dir, number), root.join(&path)).map_err(|err| match err.kind() {
+                    io::ErrorKind::NotFound => TransactionError::NotFoundError(path),
+                    _ => err.into(),
+                })?;
+            },
+            Step::RemoveFile(path) => match metadata(&path)? {
+                Some(metadata) if metadata.is_dir() => return Err(TransactionError::NotFileError(path)),
+                Some(_) => self.backup(&path, applied)?,
+                None => return Err(TransactionError::NotFoundError(path)),
+            },
+            Step::RemoveDir(path) => match metadata(&path)? {
+                Some(metadata) if metadata.is_dir() => self.backup(&path, applied)?,
+                Some(_) => return Err(TransactionError::NotDirError(path)),
+                None => return Err(TransactionError::NotFoundError(path)),
+            },
+            Step::Rename(from, to) => {
+                if metadata(&from)?.is_none() {
+                    return Err(TransactionError::NotFoundError(from));
+                }
+                if metadata(&to)?.is_some() {
+                    self.backup(&to, applied)?;
+                }
+                self.record(Record::Renamed(from.clone(), to.clone()), applied)?;
+                std::fs::rename(root.join(from), root.join(to))?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.dir);
+            if let Some(journals) = self.dir.parent() {
+                let _ = remove_empty_reserved(journals);
+            }
+        }
+    }
+}
+
+impl FileSystem {
+    /// 新しいトランザクションを始めます。ジャーナルのディレクトリが作られます。
+    pub fn transaction(&self) -> Result<Transaction<'_>, TransactionError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let reserved = self.root.join(RESERVED_DIR);
+        let journals = reserved.join(JOURNAL_DIR);
+        let dir = journals.join(format!(
+            "{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let journal = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join("journal"))?;
+        // 実行中のトランザクションがrecoverで元に戻されないようにロックする。
+        journal.lock()?;
+        // 異常終了した後にrecoverがジャーナルを見つけられるように、作ったディレクトリとファイルを永続化する。
+        for dir in [dir.as_path(), &journals, &reserved, &self.root] {
+            sync_dir(dir)?;
+        }
+
+        Ok(Transaction {
+            filesystem: self,
+            dir,
+            journal,
+            steps: Vec::new(),
+            next: 0,
+            keep: false,
+        })
+    }
+
+    /// 異常終了などで残されたジャーナルを使って、途中まで適用されたトランザクションを元に戻します。
+    /// 元に戻したトランザクションの数を返します。実行中のトランザクションには影響しません。
+    /// コミットの記録があるトランザクションは元に戻さず、ジャーナルだけを削除します。
+    pub fn recover(&self) -> Result<usize, TransactionError> {
+        let path = self.root.join(RESERVED_DIR).join(JOURNAL_DIR);
+        let journals = match std::fs::read_dir(&path) {
+            Ok(journals) => journals,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut recovered = 0;
+        for dir in journals {
+            let dir = dir?.path();
+            let mut journal = match std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(dir.join("journal"))
+            {
+                Ok(journal) => journal,
+                // ジャーナルを作る前に中断した。
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    std::fs::remove_dir_all(&dir)?;
+                    continue;
+                },
+                Err(err) => return Err(err.into()),
+            };
+            match journal.try_lock() {
+                Ok(()) => (),
+                Err(std::fs::TryLockError::WouldBlock) => continue,
+                Err(std::fs::TryLockError::Error(err)) => return Err(err.into()),
+            }
+
+            let mut bytes = Vec::new();
+            journal.read_to_end(&mut bytes)?;
+            let records = Record::decode_all(&bytes);
+            let committed = records.contains(&Record::Committed);
+            if !committed {
+                for record in records.iter().rev() {
+                    undo(&self.root, &dir, record)?;
+                }
+            }
+            drop(journal);
+            std::fs::remove_dir_all(&dir)?;
+            recovered += usize::from(!committed);
+        }
+        remove_empty_reserved(&path)?;
+        Ok(recovered)
+    }
+}
+
+#[cfg(test)]
+mod journal {
+    use ::{
+        filesystem_provider_api::{
+            fs::{entity::Dir as _, ops},
+            provider::make::Make as _,
+        },
+        std::path::Path,
+    };
+
+    use crate::{
+        fs::{
+            transaction::{TransactionError, JOURNAL_DIR},
+            RESERVED_DIR,
+        },
+        provider::Provider,
+    };
+
+    #[test]
+    fn commit() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::write(temp.join("old.txt"), b"old")?;
+        std::fs::write(temp.join("x.txt"), b"x")?;
+
+        let mut transaction = filesystem.transaction()?;
+        transaction.create_dir("a/b")?;
+        transaction.write_file("a/b/c.txt", b"c")?;
+        transaction.remove_file("old.txt")?;
+        transaction.rename("x.txt", "a/y.txt")?;
+        transaction.commit()?;
+
+        assert_eq!(std::fs::read(temp.join("a").join("b").join("c.txt"))?, b"c");
+        assert_eq!(std::fs::read(temp.join("a").join("y.txt"))?, b"x");
+        assert!(!temp.join("old.txt").exists());
+        assert!(!temp.join("x.txt").exists());
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn rollback() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::create_dir(temp.join("plugin"))?;
+        std::fs::write(temp.join("plugin").join("keep.txt"), b"old")?;
+
+        let mut transaction = filesystem.transaction()?;
+        transaction.write_file("plugin/keep.txt", b"new")?;
+        transaction.create_dir("plugin/lib")?;
+        transaction.remove_dir("plugin")?;
+        transaction.remove_file("missing.txt")?;
+        assert!(matches!(transaction.commit(), Err(TransactionError::NotFoundError(_))));
+
+        assert_eq!(std::fs::read(temp.join("plugin").join("keep.txt"))?, b"old");
+        assert!(!temp.join("plugin").join("lib").exists());
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn recover() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::write(temp.join("keep.txt"), b"old")?;
+
+        // コミットの途中で中断したトランザクションを再現する。
+        let mut transaction = filesystem.transaction()?;
+        transaction.write_file("keep.txt", b"new")?;
+        transaction.create_dir("d")?;
+        let mut applied = Vec::new();
+        for step in std::mem::take(&mut transaction.steps) {
+            transaction.apply(step, &mut applied)?;
+        }
+        let running = filesystem.transaction()?;
+        transaction.keep = true;
+        drop(transaction);
+        assert_eq!(std::fs::read(temp.join("keep.txt"))?, b"new");
+
+        assert_eq!(filesystem.recover()?, 1);
+        assert_eq!(std::fs::read(temp.join("keep.txt"))?, b"old");
+        assert!(!temp.join("d").exists());
+
+        drop(running);
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn recover_committed() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        std::fs::write(temp.join("keep.txt"), b"old")?;
+
+        // コミットの記録を書いた後、ジャーナルを削除する前に中断したトランザクションを再現する。
+        let mut transaction = filesystem.transaction()?;
+        transaction.write_file("keep.txt", b"new")?;
+        transaction.create_dir("d")?;
+        let mut applied = Vec::new();
+        for step in std::mem::take(&mut transaction.steps) {
+            transaction.apply(step, &mut applied)?;
+        }
+        transaction.finish(&applied)?;
+        transaction.keep = true;
+        drop(transaction);
+
+        assert_eq!(filesystem.recover()?, 0);
+        assert_eq!(std::fs::read(temp.join("keep.txt"))?, b"new");
+        assert!(temp.join("d").is_dir());
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_is_out_of_access() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut transaction = filesystem.transaction()?;
+        assert!(matches!(
+            transaction.remove_dir(Path::new(RESERVED_DIR).join(JOURNAL_DIR)),
+            Err(TransactionError::AccessError(_))
+        ));
+        assert!(matches!(
+            transaction.write_file("../escape.txt", b""),
+            Err(TransactionError::AccessError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn journal_is_hidden() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+        let journals = Path::new(RESERVED_DIR).join(JOURNAL_DIR);
+
+        let transaction = filesystem.transaction()?;
+        assert!(temp.join(&journals).is_dir());
+        assert_eq!(ops::OpenDir::open(&filesystem, ".")?.count(), 0);
+        assert!(ops::OpenDir::open(&filesystem, ".")?.entries()?.next().is_none());
+        assert!(ops::OpenDir::open(&filesystem, &journals).is_err());
+        assert!(ops::RemoveDir::remove(&filesystem, &journals).is_err());
+        assert!(ops::RemoveDir::remove(&filesystem, RESERVED_DIR).is_err());
+        assert!(temp.join(&journals).is_dir());
+
+        drop(transaction);
+        assert!(!temp.join(RESERVED_DIR).exists());
+        Ok(())
+    }
+}
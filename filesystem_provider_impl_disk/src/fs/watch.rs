@@ -0,0 +1,472 @@
+//! Linuxのinotifyによる[api_watch::Watch]の実装。
+//!
+//! ディレクトリを監視する場合は下階のディレクトリも再帰的に監視します。
+//! 監視下のディレクトリが基底パスの外側へ移動した場合はその監視を解除するので、基底パスの外側で起きた変更が報告されることはありません。
+//! 監視を始めたエンティティ自身が削除されたり移動したりした場合も、[api_watch::Event::Remove]を報告してすべての監視を解除します。
+//! ただし基底パス自身を監視していた場合は報告するサブパスが無いので、[WatchError::RootRemovedError]を返してイテレータを終えます。
+//! [crate::fs::RESERVED_DIR]の下は監視しません。
+
+use ::{
+    filesystem_provider_api::fs::watch as api_watch,
+    std::{
+        collections::{HashMap, VecDeque},
+        ffi::{CString, OsStr, OsString},
+        io,
+        os::unix::{
+            ffi::{OsStrExt, OsStringExt},
+            io::{AsRawFd, FromRawFd, OwnedFd},
+        },
+        path::{Path, PathBuf},
+    },
+};
+
+use crate::fs::{check_path, is_hidden, normalize, FileSystem};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WatchError {
+    #[error("out of access {0:?}")]
+    AccessError(PathBuf),
+    #[error("event queue overflowed")]
+    OverflowError,
+    #[error("root directory removed")]
+    RootRemovedError,
+    #[error("{0:?}")]
+    #[rustfmt::skip]
+    IoError(#[from]#[source]io::Error),
+}
+
+const DIR_MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_DONT_FOLLOW
+    | libc::IN_ONLYDIR;
+
+const FILE_MASK: u32 = libc::IN_MODIFY | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_DONT_FOLLOW;
+
+const HEADER_LEN: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// [api_watch::Event]を返すイテレータ。イベントが起きるまでブロックします。
+#[derive(Debug)]
+pub struct Watcher {
+    fd: OwnedFd,
+    root: Box<Path>,
+    // 監視を始めたエンティティのサブパス。
+    base: PathBuf,
+    // watch descriptorから監視しているエンティティのサブパスへの対応。
+    watches: HashMap<i32, PathBuf>,
+    events: VecDeque<api_watch::Event>,
+    buf: Vec<u8>,
+    // 基底パス自身が削除されたり移動したりしたかどうか。
+    root_removed: bool,
+    // [WatchError::RootRemovedError]を返し終えたかどうか。
+    finished: bool,
+}
+
+impl Watcher {
+    fn new(root: &Path, sub: PathBuf) -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut watcher = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            root: Box::from(root),
+            base: sub.clone(),
+            watches: HashMap::new(),
+            events: VecDeque::new(),
+            buf: vec![0; 64 * (HEADER_LEN + libc::FILENAME_MAX as usize + 1)],
+            root_removed: false,
+            finished: false,
+        };
+
+        if root.join(&sub).symlink_metadata()?.is_dir() {
+            watcher.add_dir(sub)?;
+        } else {
+            watcher.add(&sub, FILE_MASK)?;
+        }
+        Ok(watcher)
+    }
+
+    fn add(&mut self, sub: &Path, mask: u32) -> io::Result<()> {
+        let path = CString::new(self.root.join(sub).into_os_string().into_vec())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(wd, sub.to_path_buf());
+        Ok(())
+    }
+
+    /// ディレクトリとその下階のディレクトリを監視します。シンボリックリンクは辿りません。
+    fn add_dir(&mut self, sub: PathBuf) -> io::Result<()> {
+        if is_hidden(&sub) {
+            return Ok(());
+        }
+        self.add(&sub, DIR_MASK)?;
+        for entry in self.root.join(&sub).read_dir()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                self.add_dir(sub.join(entry.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 監視を始める前にディレクトリが消えている場合は単に無視します。
+    fn add_dir_if_exists(&mut self, sub: PathBuf) -> io::Result<()> {
+        match self.add_dir(sub) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) if err.raw_os_error() == Some(libc::ENOTDIR) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// `sub`とその下階の監視を解除します。
+    fn remove_dir(&mut self, sub: &Path) {
+        let fd = self.fd.as_raw_fd();
+        self.watches.retain(|wd, path| {
+            let keep = !path.starts_with(sub);
+            if !keep {
+                unsafe { libc::inotify_rm_watch(fd, *wd) };
+            }
+            keep
+        });
+    }
+
+    fn rename_dir(&mut self, from: &Path, to: &Path) {
+        for path in self.watches.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = to.join(rest);
+            }
+        }
+    }
+
+    fn read_events(&mut self) -> Result<(), WatchError> {
+        let len = loop {
+            let len = unsafe { libc::read(self.fd.as_raw_fd(), self.buf.as_mut_ptr().cast(), self.buf.len()) };
+            if len >= 0 {
+                break len as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        };
+
+        let mut raw_events = Vec::new();
+        let mut offset = 0;
+        while offset + HEADER_LEN <= len {
+            let event = unsafe { std::ptr::read_unaligned(self.buf[offset..].as_ptr().cast::<libc::inotify_event>()) };
+            let name = &self.buf[offset + HEADER_LEN..offset + HEADER_LEN + event.len as usize];
+            let name = match name.iter().position(|&b| b == 0) {
+                Some(nul) => &name[..nul],
+                None => name,
+            };
+            raw_events.push((event, OsStr::from_bytes(name).to_os_string()));
+            offset += HEADER_LEN + event.len as usize;
+        }
+
+        // IN_MOVED_FROMと対になるIN_MOVED_TOは同じ読み込みで続けて届くことを前提とする。
+        let mut moved_from = None;
+        for (event, name) in raw_events {
+            self.dispatch(event, name, &mut moved_from)?;
+        }
+        self.flush_moved_from(&mut moved_from);
+        Ok(())
+    }
+
+    /// 対になるIN_MOVED_TOが無かったIN_MOVED_FROMは基底パスの外側への移動と見なします。
+    fn flush_moved_from(&mut self, moved_from: &mut Option<(u32, PathBuf, bool)>) {
+        if let Some((_, from, is_dir)) = moved_from.take() {
+            if is_dir {
+                self.remove_dir(&from);
+            }
+            self.events.push_back(api_watch::Event::Remove(from));
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        event: libc::inotify_event,
+        name: OsString,
+        moved_from: &mut Option<(u32, PathBuf, bool)>,
+    ) -> Result<(), WatchError> {
+        use api_watch::Event;
+
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            return Err(WatchError::OverflowError);
+        }
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.watches.remove(&event.wd);
+            return Ok(());
+        }
+
+        let path = match self.watches.get(&event.wd) {
+            Some(sub) if name.is_empty() => sub.clone(),
+            Some(sub) => sub.join(name),
+            None => return Ok(()),
+        };
+        if is_hidden(&path) {
+            return Ok(());
+        }
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+
+        if event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+            // 下階のエンティティの削除や移動は親ディレクトリのイベントで報告される。
+            if path == self.base {
+                self.flush_moved_from(moved_from);
+                self.remove_dir(&path);
+                if path.as_os_str().is_empty() {
+                    self.root_removed = true;
+                } else {
+                    self.events.push_back(Event::Remove(path));
+                }
+            }
+            return Ok(());
+        }
+
+        if event.mask & libc::IN_MOVED_TO != 0 {
+            match moved_from.take() {
+                Some((cookie, from, _)) if cookie == event.cookie => {
+                    if is_dir {
+                        self.rename_dir(&from, &path);
+                    }
+                    self.events.push_back(Event::Rename { from, to: path });
+                },
+                pending => {
+                    *moved_from = pending;
+                    self.flush_moved_from(moved_from);
+                    if is_dir {
+                        self.add_dir_if_exists(path.clone())?;
+                    }
+                    self.events.push_back(Event::Create(path));
+                },
+            }
+            return Ok(());
+        }
+
+        self.flush_moved_from(moved_from);
+
+        if event.mask & libc::IN_MOVED_FROM != 0 {
+            *moved_from = Some((event.cookie, path, is_dir));
+        } else if event.mask & libc::IN_CREATE != 0 {
+            if is_dir {
+                self.add_dir_if_exists(path.clone())?;
+            }
+            self.events.push_back(Event::Create(path));
+        } else if event.mask & libc::IN_MODIFY != 0 {
+            self.events.push_back(Event::Modify(path));
+        } else if event.mask & libc::IN_DELETE != 0 {
+            self.events.push_back(Event::Remove(path));
+        }
+        Ok(())
+    }
+}
+
+impl std::iter::Iterator for Watcher {
+    type Item = Result<api_watch::Event, WatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.finished {
+                return None;
+            }
+            if self.root_removed {
+                self.finished = true;
+                return Some(Err(WatchError::RootRemovedError));
+            }
+            if let Err(err) = self.read_events() {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+impl api_watch::Watch for FileSystem {
+    type E = WatchError;
+    type IterE = WatchError;
+    type Watcher = Watcher;
+
+    fn watch<P: AsRef<Path>>(&self, path: P) -> Result<Self::Watcher, Self::E> {
+        let path = path.as_ref();
+        check_path(WatchError::AccessError, path)?;
+        if is_hidden(&normalize(path)) {
+            return Err(WatchError::AccessError(path.to_path_buf()));
+        }
+
+        Watcher::new(&self.root, normalize(path)).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod watcher {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                snapshot::Snapshot as _,
+                watch::{Event, Watch as _},
+            },
+            provider::make::Make as _,
+        },
+        std::path::PathBuf,
+    };
+
+    use crate::{fs::RESERVED_DIR, provider::Provider};
+
+    #[test]
+    fn create() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::write(temp.join("a.txt"), b"a")?;
+
+        assert_eq!(watcher.next().unwrap()?, Event::Create(PathBuf::from("a.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_snapshots() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        std::fs::write(temp.join("d").join("a.txt"), b"a")?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch(".")?;
+        let snapshot = filesystem.snapshot()?;
+        drop(snapshot);
+        std::fs::write(temp.join("b.txt"), b"b")?;
+
+        assert_eq!(watcher.next().unwrap()?, Event::Create(PathBuf::from("b.txt")));
+        assert!(filesystem.watch(RESERVED_DIR).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rename_in_subdir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        std::fs::write(temp.join("d").join("a.txt"), b"a")?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::rename(temp.join("d").join("a.txt"), temp.join("b.txt"))?;
+
+        assert_eq!(
+            watcher.next().unwrap()?,
+            Event::Rename {
+                from: PathBuf::from("d").join("a.txt"),
+                to: PathBuf::from("b.txt"),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn never_report_outside_root() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let outside = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::rename(temp.join("d"), outside.join("d"))?;
+        assert_eq!(watcher.next().unwrap()?, Event::Remove(PathBuf::from("d")));
+
+        std::fs::write(outside.join("d").join("a.txt"), b"a")?;
+        std::fs::write(temp.join("b.txt"), b"b")?;
+        assert_eq!(watcher.next().unwrap()?, Event::Create(PathBuf::from("b.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn watched_dir_moved_outside_root() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let outside = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(temp.join("d").join("e"))?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch("d")?;
+        std::fs::rename(temp.join("d"), outside.join("d"))?;
+        assert_eq!(watcher.next().unwrap()?, Event::Remove(PathBuf::from("d")));
+        assert!(watcher.watches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn root_moved() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(temp.join("root").join("d"))?;
+        let filesystem = Provider::make(temp.join("root"));
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::rename(temp.join("root"), temp.join("moved"))?;
+        assert!(matches!(
+            watcher.next(),
+            Some(Err(crate::fs::watch::WatchError::RootRemovedError))
+        ));
+        assert!(watcher.next().is_none());
+        assert!(watcher.watches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn root_removed() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("root"))?;
+        let filesystem = Provider::make(temp.join("root"));
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::remove_dir(temp.join("root"))?;
+        assert!(matches!(
+            watcher.next(),
+            Some(Err(crate::fs::watch::WatchError::RootRemovedError))
+        ));
+        assert!(watcher.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn subdir_renamed_inside_root() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("d"))?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let mut watcher = filesystem.watch(".")?;
+        std::fs::rename(temp.join("d"), temp.join("e"))?;
+        std::fs::write(temp.join("e").join("a.txt"), b"a")?;
+
+        assert_eq!(
+            watcher.next().unwrap()?,
+            Event::Rename {
+                from: PathBuf::from("d"),
+                to: PathBuf::from("e"),
+            }
+        );
+        assert_eq!(
+            watcher.next().unwrap()?,
+            Event::Create(PathBuf::from("e").join("a.txt"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_subpath() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        assert!(matches!(
+            filesystem.watch(".."),
+            Err(crate::fs::watch::WatchError::AccessError(_))
+        ));
+        Ok(())
+    }
+}
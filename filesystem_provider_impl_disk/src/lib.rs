@@ -15,9 +15,9 @@
 //! fn foo() -> Result<(), Box<dyn std::error::Error>> {
 //!     let root = std::path::PathBuf::from(".");
 //!     let sub = root.join("test.txt");
-//!     let mut filesystem = Provider::make(root);
+//!     let filesystem = Provider::make(root);
 //!
-//!     let file = ops::OpenFile::open(&mut filesystem, sub)?;
+//!     let file = ops::OpenFile::open(&filesystem, sub)?;
 //!     assert!(file.is_file());
 //!     Ok(())
 //! }
@@ -11,14 +11,14 @@ pub struct Provider;
 /// };
 ///
 /// let root = std::path::PathBuf::from(".");
-/// let mut filesystem = Provider::make(root);
+/// let filesystem = Provider::make(root);
 /// ```
 impl api_make::Make for Provider {
     type FS = crate::fs::FileSystem;
 
     fn make(root: PathBuf) -> Self::FS {
         let root = root.into_boxed_path();
-        crate::fs::FileSystem { root }
+        crate::fs::FileSystem::new(root)
     }
 }
 
@@ -61,6 +61,7 @@ mod test_capabilities {
 ///
 /// - open::{File, Dir}
 /// - create::{File, Dir}
+/// - replace::File
 /// - remove::{File, Dir}
 ///
 #[cfg(test)]
@@ -73,8 +74,10 @@ mod test_operations {
     fn create_file<F: fs::ops::CreateFile<E = crate::fs::CreateEntityError, File = crate::fs::File>>(_: &F) {}
     fn create_dir<F: fs::ops::CreateDir<E = crate::fs::CreateEntityError, Dir = crate::fs::Dir>>(_: &F) {}
 
-    fn remove_file<'a, F: fs::ops::RemoveFile<E = crate::fs::RemoveEntityError>>(_: &F) {}
-    fn remove_dir<'a, F: fs::ops::RemoveDir<E = crate::fs::RemoveEntityError>>(_: &F) {}
+    fn replace_file<F: fs::ops::ReplaceFile<E = crate::fs::CreateEntityError, File = crate::fs::AtomicFile>>(_: &F) {}
+
+    fn remove_file<F: fs::ops::RemoveFile<E = crate::fs::RemoveEntityError>>(_: &F) {}
+    fn remove_dir<F: fs::ops::RemoveDir<E = crate::fs::RemoveEntityError>>(_: &F) {}
 
     #[test]
     fn it_works() -> Result<(), Box<dyn std::error::Error>> {
@@ -88,9 +91,41 @@ mod test_operations {
         create_file(&filesystem);
         create_dir(&filesystem);
 
+        replace_file(&filesystem);
+
         remove_file(&filesystem);
         remove_dir(&filesystem);
 
         Ok(())
     }
 }
+
+/// `crate::provider::Provider`が作るファイルシステムは`Send + Sync`であり、スレッド間で共有できる。
+#[cfg(test)]
+mod test_share {
+    use ::{
+        filesystem_provider_api::{fs::ops, provider::make::Make},
+        std::sync::Arc,
+    };
+
+    fn send_sync<F: Send + Sync>(_: &F) {}
+
+    #[test]
+    fn it_works() -> Result<(), Box<dyn std::error::Error>> {
+        let filesystem = Arc::new(crate::provider::Provider::make(std::path::PathBuf::from(".")));
+
+        send_sync(&filesystem);
+
+        let workers = (0..4)
+            .map(|_| {
+                let filesystem = filesystem.clone();
+                std::thread::spawn(move || ops::OpenFile::open(&*filesystem, "Cargo.toml").is_ok())
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            assert!(worker.join().unwrap());
+        }
+
+        Ok(())
+    }
+}
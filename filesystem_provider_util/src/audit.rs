@@ -0,0 +1,480 @@
+//! ファイルシステムへの操作を記録するラッパー。
+//!
+//! [AuditFileSystem]は包んだファイルシステムへの呼び出しごとに、操作、基底パスからの相対パス、結果、所要時間を[Log]に渡します。
+//! 返されたファイルやディレクトリへの読み書きは記録しません。
+//! 返されるディレクトリは[entity::DirAt]を実装しないので、記録されない変更はできません。
+//!
+//! [Log]の実装として、JSON Linesを書き出す[JsonLines]と、`tracing`フィーチャーが有効な場合は[tracing]のイベントを発行する`Tracing`があります。
+
+use ::{
+    filesystem_provider_api::fs::{entity, ops, FileSystem, Introspect},
+    std::{
+        fmt, io,
+        path::Path,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex, PoisonError,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+use crate::{normalize, PlainDir};
+
+/// 記録される操作です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Operation {
+    Metadata,
+    Exists,
+    IsFile,
+    IsDir,
+    OpenFile,
+    OpenDir,
+    CreateFile,
+    CreateNewFile,
+    ReplaceFile,
+    CreateDir,
+    CreateNewDir,
+    RemoveFile,
+    RemoveDir,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Metadata => "metadata",
+            Operation::Exists => "exists",
+            Operation::IsFile => "is_file",
+            Operation::IsDir => "is_dir",
+            Operation::OpenFile => "open_file",
+            Operation::OpenDir => "open_dir",
+            Operation::CreateFile => "create_file",
+            Operation::CreateNewFile => "create_new_file",
+            Operation::ReplaceFile => "replace_file",
+            Operation::CreateDir => "create_dir",
+            Operation::CreateNewDir => "create_new_dir",
+            Operation::RemoveFile => "remove_file",
+            Operation::RemoveDir => "remove_dir",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 操作の結果です。
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Outcome {
+    Ok,
+    /// [FileSystem::exists]などの問い合わせの答えです。
+    Answer(bool),
+    /// 失敗した操作のエラーメッセージです。
+    Err(String),
+}
+
+impl Outcome {
+    pub fn is_ok(&self) -> bool {
+        !matches!(self, Outcome::Err(_))
+    }
+}
+
+/// 一つの操作の記録です。
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Record<'a> {
+    pub operation: Operation,
+    /// 基底パスからの相対パスです。`.`は取り除かれています。
+    pub path: &'a Path,
+    pub outcome: Outcome,
+    pub duration: Duration,
+}
+
+/// 記録の書き出し先です。複数のスレッドから呼び出される場合があります。
+pub trait Log {
+    fn record(&self, record: &Record<'_>);
+}
+
+impl<L: Log + ?Sized> Log for &L {
+    fn record(&self, record: &Record<'_>) {
+        (**self).record(record)
+    }
+}
+
+impl<L: Log + ?Sized> Log for Box<L> {
+    fn record(&self, record: &Record<'_>) {
+        (**self).record(record)
+    }
+}
+
+impl<L: Log + ?Sized> Log for Arc<L> {
+    fn record(&self, record: &Record<'_>) {
+        (**self).record(record)
+    }
+}
+
+/// 記録を一行に一つのJSONオブジェクトとして書き出します。
+///
+/// オブジェクトは`operation`、`path`、`outcome`（`"ok"`、`"true"`、`"false"`、`"error"`のいずれか）、
+/// 失敗した場合の`error`、マイクロ秒単位の`duration_us`を持ちます。
+/// UTF-8でないパスは置換文字で置き換えられます。
+/// 書き出しに失敗した記録は捨てられ、その数を[JsonLines::dropped]で調べられます。
+#[derive(Debug, Default)]
+pub struct JsonLines<W> {
+    writer: Mutex<W>,
+    dropped: AtomicU64,
+}
+
+impl<W: io::Write> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// 書き出しに失敗して捨てられた記録の数です。
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// JSONの文字列リテラルを書き出します。
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl<W: io::Write> Log for JsonLines<W> {
+    fn record(&self, record: &Record<'_>) {
+        let mut line = String::from("{\"operation\":");
+        write_json_str(&mut line, record.operation.as_str());
+        line.push_str(",\"path\":");
+        write_json_str(&mut line, &record.path.to_string_lossy());
+        line.push_str(",\"outcome\":");
+        match &record.outcome {
+            Outcome::Ok => line.push_str("\"ok\""),
+            Outcome::Answer(answer) => write_json_str(&mut line, &answer.to_string()),
+            Outcome::Err(err) => {
+                line.push_str("\"error\",\"error\":");
+                write_json_str(&mut line, err);
+            },
+        }
+        line.push_str(&format!(",\"duration_us\":{}}}\n", record.duration.as_micros()));
+
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if writer.write_all(line.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 記録を[tracing]の`INFO`レベルのイベントとして発行します。失敗した操作は`WARN`レベルです。
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Log for Tracing {
+    fn record(&self, record: &Record<'_>) {
+        let operation = record.operation.as_str();
+        let path = record.path.display();
+        let duration_us = record.duration.as_micros() as u64;
+        match &record.outcome {
+            Outcome::Ok => tracing::info!(operation, %path, duration_us),
+            Outcome::Answer(answer) => tracing::info!(operation, %path, answer, duration_us),
+            Outcome::Err(error) => tracing::warn!(operation, %path, error = error.as_str(), duration_us),
+        }
+    }
+}
+
+/// ファイルシステムを包んで操作を記録します。
+#[derive(Debug)]
+pub struct AuditFileSystem<F, L> {
+    inner: F,
+    log: L,
+}
+
+impl<F, L: Log> AuditFileSystem<F, L> {
+    pub fn new(inner: F, log: L) -> Self {
+        Self { inner, log }
+    }
+
+    forward!(inner: F);
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn into_inner(self) -> (F, L) {
+        (self.inner, self.log)
+    }
+
+    fn audit<T, E: fmt::Display>(
+        &self,
+        operation: Operation,
+        path: &Path,
+        f: impl FnOnce(&F, &Path) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let result = f(&self.inner, path);
+        let outcome = match &result {
+            Ok(_) => Outcome::Ok,
+            Err(err) => Outcome::Err(err.to_string()),
+        };
+        self.emit(operation, path, outcome, start);
+        result
+    }
+
+    fn query(&self, operation: Operation, path: &Path, f: impl FnOnce(&F, &Path) -> bool) -> bool {
+        let start = Instant::now();
+        let answer = f(&self.inner, path);
+        self.emit(operation, path, Outcome::Answer(answer), start);
+        answer
+    }
+
+    fn emit(&self, operation: Operation, path: &Path, outcome: Outcome, start: Instant) {
+        let duration = start.elapsed();
+        self.log.record(&Record {
+            operation,
+            path: &normalize(path),
+            outcome,
+            duration,
+        });
+    }
+}
+
+impl<F: Introspect, L> Introspect for AuditFileSystem<F, L> {
+    forward!(introspect);
+}
+
+impl<F, L> FileSystem for AuditFileSystem<F, L>
+where
+    F: FileSystem<MetadataE: fmt::Display>,
+    L: Log,
+{
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.audit(Operation::Metadata, sub.as_ref(), |inner, sub| inner.metadata(sub))
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(Operation::Exists, path.as_ref(), |inner, path| inner.exists(path))
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(Operation::IsFile, path.as_ref(), |inner, path| inner.is_file(path))
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(Operation::IsDir, path.as_ref(), |inner, path| inner.is_dir(path))
+    }
+}
+
+impl<F: ops::OpenFile<E: fmt::Display>, L: Log> ops::OpenFile for AuditFileSystem<F, L> {
+    type E = F::E;
+    type File = F::File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.audit(Operation::OpenFile, path.as_ref(), |inner, path| inner.open(path))
+    }
+}
+
+impl<F: ops::OpenDir<E: fmt::Display>, L: Log> ops::OpenDir for AuditFileSystem<F, L> {
+    type Dir = PlainDir<F::Dir>;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.audit(Operation::OpenDir, path.as_ref(), |inner, path| inner.open(path))
+            .map(PlainDir::new)
+    }
+}
+
+impl<F: ops::CreateFile<E: fmt::Display>, L: Log> ops::CreateFile for AuditFileSystem<F, L> {
+    type E = F::E;
+    type File = F::File;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.audit(Operation::CreateFile, path.as_ref(), |inner, path| inner.create(path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.audit(Operation::CreateNewFile, path.as_ref(), |inner, path| {
+            inner.create_new(path)
+        })
+    }
+}
+
+impl<F: ops::ReplaceFile<E: fmt::Display>, L: Log> ops::ReplaceFile for AuditFileSystem<F, L> {
+    type E = F::E;
+    type File = F::File;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.audit(Operation::ReplaceFile, path.as_ref(), |inner, path| inner.replace(path))
+    }
+}
+
+impl<F: ops::CreateDir<E: fmt::Display>, L: Log> ops::CreateDir for AuditFileSystem<F, L> {
+    type Dir = PlainDir<F::Dir>;
+    type E = F::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.audit(Operation::CreateDir, path.as_ref(), |inner, path| inner.create(path))
+            .map(PlainDir::new)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.audit(Operation::CreateNewDir, path.as_ref(), |inner, path| {
+            inner.create_new(path)
+        })
+        .map(PlainDir::new)
+    }
+}
+
+impl<F: ops::RemoveFile<E: fmt::Display>, L: Log> ops::RemoveFile for AuditFileSystem<F, L> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.audit(Operation::RemoveFile, path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+impl<F: ops::RemoveDir<E: fmt::Display>, L: Log> ops::RemoveDir for AuditFileSystem<F, L> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.audit(Operation::RemoveDir, path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+#[cfg(test)]
+mod trail {
+    use ::{
+        filesystem_provider_api::{
+            fs::{ops, FileSystem as _},
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::provider::Provider,
+        std::{
+            io,
+            path::PathBuf,
+            sync::{Arc, Mutex},
+        },
+    };
+
+    use crate::audit::{AuditFileSystem, JsonLines, Log, Operation, Outcome, Record};
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<(Operation, PathBuf, Outcome)>>);
+
+    impl Log for Collect {
+        fn record(&self, record: &Record<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.operation, record.path.to_path_buf(), record.outcome.clone()));
+        }
+    }
+
+    #[test]
+    fn records() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let log = Arc::new(Collect::default());
+        let filesystem = AuditFileSystem::new(Provider::make(temp.to_path_buf()), log.clone());
+
+        ops::CreateDir::create(&filesystem, "./d")?;
+        ops::CreateFile::create_new(&filesystem, "d/a.txt")?;
+        assert!(filesystem.is_file("d/a.txt"));
+        assert!(ops::OpenFile::open(&filesystem, "d/missing.txt").is_err());
+        ops::RemoveDir::remove(&filesystem, "d")?;
+
+        let records = log.0.lock().unwrap();
+        let summary = records
+            .iter()
+            .map(|(operation, path, outcome)| (*operation, path.to_str().unwrap(), outcome.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (Operation::CreateDir, "d", true),
+                (Operation::CreateNewFile, "d/a.txt", true),
+                (Operation::IsFile, "d/a.txt", true),
+                (Operation::OpenFile, "d/missing.txt", false),
+                (Operation::RemoveDir, "d", true),
+            ]
+        );
+        assert_eq!(records[2].2, Outcome::Answer(true));
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = AuditFileSystem::new(Provider::make(temp.to_path_buf()), JsonLines::new(Vec::new()));
+
+        assert!(!filesystem.exists("say \"hi\""));
+        assert!(filesystem.metadata("missing").is_err());
+
+        let (_, log) = filesystem.into_inner();
+        let text = String::from_utf8(log.into_inner())?;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"operation":"exists","path":"say \"hi\"","outcome":"false","duration_us":"#));
+        assert!(lines[1].starts_with(r#"{"operation":"metadata","path":"missing","outcome":"error","error":""#));
+        assert!(lines.iter().all(|line| line.ends_with('}')));
+        Ok(())
+    }
+
+    /// 書き込みに失敗するライターです。
+    struct Broken;
+
+    impl io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dropped() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = AuditFileSystem::new(Provider::make(temp.to_path_buf()), JsonLines::new(Broken));
+
+        assert!(!filesystem.exists("a.txt"));
+        assert!(!filesystem.is_dir("d"));
+        assert_eq!(filesystem.log().dropped(), 2);
+        Ok(())
+    }
+}
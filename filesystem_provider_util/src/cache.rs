@@ -0,0 +1,651 @@
+//! 読み込みの結果をキャッシュするファイルシステムのラッパー。
+//!
+//! [CachedFileSystem]は[api_fs::FileSystem]のメソッドの結果とファイルの内容をキャッシュします。
+//! ファイルの内容は合計の大きさが[Options::capacity]を超えないように、最も長く使われていないものから捨てられます。
+//! 容量より大きいファイルはキャッシュせずに包んだファイルシステムから直接読みます。
+//! ディレクトリの内容はキャッシュしません。
+//!
+//! ラッパーを通した書き込みは、書き込んだパスとその上階および下階のキャッシュを無効にします。パスの`..`は字句的に解決します。
+//! ファイルへの書き込みは、返されたファイルを破棄した時にも無効にします。
+//! 返されるディレクトリは[api_entity::DirAt]を実装しないので、ディレクトリの中の変更もラッパーのメソッドで行います。
+//! ラッパーを通さない変更は[CachedFileSystem::invalidate]で無効にするか、[Options::ttl]で期限を設定してください。
+//!
+//! 包んだファイルシステムから読んでいる間にキャッシュが無効にされた場合、読んだ結果は古いかもしれないのでキャッシュしません。
+
+use ::{
+    filesystem_provider_api::fs::{self as api_fs, entity as api_entity, ops as api_ops},
+    std::{
+        collections::{BTreeMap, HashMap},
+        io::{self, Read as _},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, MutexGuard, PoisonError},
+        time::{Duration, Instant},
+    },
+};
+
+use crate::{normalize, resolve, PlainDir};
+
+/// キャッシュの設定です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Options {
+    /// キャッシュするファイルの内容の合計の大きさの上限（バイト）です。
+    pub capacity: usize,
+    /// キャッシュの有効期限です。`None`の場合は無効にされるまで有効です。
+    pub ttl: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            capacity: 64 * 1024 * 1024,
+            ttl: None,
+        }
+    }
+}
+
+/// `path`のキャッシュのキーです。`..`を字句的に解決するので、`x/../a.txt`と`a.txt`は同じキーになります。
+///
+/// 基底パスの外を指すパスは包んだファイルシステムが拒否するので、解決せずにそのままキーにします。
+fn key(path: &Path) -> PathBuf {
+    resolve(path).unwrap_or_else(|| normalize(path))
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum Query {
+    Exists,
+    IsFile,
+    IsDir,
+}
+
+#[derive(Debug)]
+struct Content {
+    data: Arc<[u8]>,
+    cached_at: Instant,
+    tick: u64,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    metadata: HashMap<PathBuf, (api_entity::Metadata, Instant)>,
+    queries: HashMap<(PathBuf, Query), (bool, Instant)>,
+    contents: HashMap<PathBuf, Content>,
+    /// 最後に使われた順に並んだ`contents`のキーです。
+    lru: BTreeMap<u64, PathBuf>,
+    size: usize,
+    tick: u64,
+    /// 無効にする度に増える世代です。読む前と後で世代が異なる場合は結果をキャッシュしない。
+    generation: u64,
+}
+
+impl Cache {
+    fn content(&mut self, key: &Path, fresh: impl Fn(Instant) -> bool) -> Option<Arc<[u8]>> {
+        let content = self.contents.get_mut(key)?;
+        if !fresh(content.cached_at) {
+            self.remove_content(key);
+            return None;
+        }
+        self.tick += 1;
+        self.lru.remove(&content.tick);
+        content.tick = self.tick;
+        self.lru.insert(self.tick, key.to_path_buf());
+        Some(content.data.clone())
+    }
+
+    fn insert_content(&mut self, key: PathBuf, data: Arc<[u8]>, capacity: usize) {
+        self.remove_content(&key);
+        while self.size + data.len() > capacity {
+            match self.lru.keys().next().copied() {
+                Some(tick) => {
+                    let oldest = self.lru[&tick].clone();
+                    self.remove_content(&oldest);
+                },
+                None => return,
+            }
+        }
+        self.tick += 1;
+        self.size += data.len();
+        self.lru.insert(self.tick, key.clone());
+        self.contents.insert(
+            key,
+            Content {
+                data,
+                cached_at: Instant::now(),
+                tick: self.tick,
+            },
+        );
+    }
+
+    fn remove_content(&mut self, key: &Path) {
+        if let Some(content) = self.contents.remove(key) {
+            self.lru.remove(&content.tick);
+            self.size -= content.data.len();
+        }
+    }
+
+    /// `key`とその上階および下階のキャッシュを無効にします。
+    fn invalidate(&mut self, key: &Path) {
+        self.generation += 1;
+        let related = |path: &Path| path.starts_with(key) || key.starts_with(path);
+        self.metadata.retain(|path, _| !related(path));
+        self.queries.retain(|(path, _), _| !related(path));
+        let contents = self
+            .contents
+            .keys()
+            .filter(|path| related(path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in contents {
+            self.remove_content(&path);
+        }
+    }
+}
+
+/// ファイルシステムを包んで読み込みの結果をキャッシュします。
+#[derive(Debug)]
+pub struct CachedFileSystem<F> {
+    inner: F,
+    options: Options,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<F> CachedFileSystem<F> {
+    pub fn new(inner: F, options: Options) -> Self {
+        Self {
+            inner,
+            options,
+            cache: Arc::default(),
+        }
+    }
+
+    forward!(accessors: F);
+
+    /// `path`とその上階および下階のキャッシュを無効にします。
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) {
+        self.cache().invalidate(&key(path.as_ref()));
+    }
+
+    /// すべてのキャッシュを無効にします。
+    pub fn clear(&self) {
+        let mut cache = self.cache();
+        *cache = Cache {
+            generation: cache.generation + 1,
+            ..Cache::default()
+        };
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn fresh(&self) -> impl Fn(Instant) -> bool {
+        let ttl = self.options.ttl;
+        move |cached_at| ttl.is_none_or(|ttl| cached_at.elapsed() < ttl)
+    }
+
+    fn query<P: AsRef<Path>>(&self, path: P, query: Query, f: impl FnOnce(&F, &Path) -> bool) -> bool {
+        let path = path.as_ref();
+        let key = (key(path), query);
+        let fresh = self.fresh();
+        if let Some((answer, cached_at)) = self.cache().queries.get(&key) {
+            if fresh(*cached_at) {
+                return *answer;
+            }
+        }
+        let generation = self.cache().generation;
+        let answer = f(&self.inner, path);
+        let mut cache = self.cache();
+        if cache.generation == generation {
+            cache.queries.insert(key, (answer, Instant::now()));
+        }
+        answer
+    }
+
+    /// 書き込んだファイルを破棄した時に無効にするための[Writer]を作ります。
+    fn writer<W>(&self, path: &Path, inner: W) -> Writer<W> {
+        Writer {
+            inner,
+            guard: Invalidate {
+                key: key(path),
+                cache: self.cache.clone(),
+            },
+        }
+    }
+}
+
+/// [CachedFileSystem]の[api_ops::OpenFile]が返すファイルです。
+#[derive(Debug)]
+pub enum File<F> {
+    /// キャッシュした内容を読みます。
+    Cached(io::Cursor<Arc<[u8]>>),
+    /// 容量より大きいファイルを直接読みます。
+    Direct(F),
+}
+
+impl<F: io::Read> io::Read for File<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            File::Cached(cursor) => cursor.read(buf),
+            File::Direct(file) => file.read(buf),
+        }
+    }
+}
+
+impl<F: io::Seek> io::Seek for File<F> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match self {
+            File::Cached(cursor) => cursor.seek(pos),
+            File::Direct(file) => file.seek(pos),
+        }
+    }
+}
+
+impl<F: api_entity::File> api_entity::File for File<F> {
+    fn size(&self) -> u64 {
+        match self {
+            File::Cached(cursor) => cursor.get_ref().len() as u64,
+            File::Direct(file) => file.size(),
+        }
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+/// 書き込み用に開いたファイルです。破棄するか[api_entity::AtomicFile::commit]した時にキャッシュを無効にします。
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+    guard: Invalidate,
+}
+
+/// 破棄した時に`key`のキャッシュを無効にします。
+#[derive(Debug)]
+struct Invalidate {
+    key: PathBuf,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl Drop for Invalidate {
+    fn drop(&mut self) {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .invalidate(&self.key);
+    }
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: io::Seek> io::Seek for Writer<W> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: api_entity::File> api_entity::File for Writer<W> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+}
+
+impl<W: api_entity::AtomicFile> api_entity::AtomicFile for Writer<W> {
+    type E = W::E;
+
+    fn commit(self) -> Result<(), Self::E> {
+        let Writer { inner, guard } = self;
+        let result = inner.commit();
+        drop(guard);
+        result
+    }
+}
+
+impl<F: api_fs::Introspect> api_fs::Introspect for CachedFileSystem<F> {
+    forward!(introspect);
+}
+
+impl<F: api_fs::FileSystem> api_fs::FileSystem for CachedFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<api_entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        let sub = sub.as_ref();
+        let key = key(sub);
+        let fresh = self.fresh();
+        if let Some((metadata, cached_at)) = self.cache().metadata.get(&key) {
+            if fresh(*cached_at) {
+                return Ok(metadata.clone());
+            }
+        }
+        let generation = self.cache().generation;
+        let metadata = self.inner.metadata(sub)?;
+        let mut cache = self.cache();
+        if cache.generation == generation {
+            cache.metadata.insert(key, (metadata.clone(), Instant::now()));
+        }
+        Ok(metadata)
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(path, Query::Exists, |inner, path| inner.exists(path))
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(path, Query::IsFile, |inner, path| inner.is_file(path))
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.query(path, Query::IsDir, |inner, path| inner.is_dir(path))
+    }
+}
+
+impl<F> api_ops::OpenFile for CachedFileSystem<F>
+where
+    F: api_ops::OpenFile,
+    F::File: io::Read,
+    F::E: From<io::Error>,
+{
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let key = key(path);
+        if let Some(data) = self.cache().content(&key, self.fresh()) {
+            return Ok(File::Cached(io::Cursor::new(data)));
+        }
+
+        let generation = self.cache().generation;
+        let mut file = self.inner.open(path)?;
+        let size = api_entity::File::size(&file);
+        if size > self.options.capacity as u64 {
+            return Ok(File::Direct(file));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut data)?;
+        let data = Arc::<[u8]>::from(data);
+        let mut cache = self.cache();
+        if cache.generation == generation {
+            cache.insert_content(key, data.clone(), self.options.capacity);
+        }
+        Ok(File::Cached(io::Cursor::new(data)))
+    }
+}
+
+impl<F: api_ops::OpenDir> api_ops::OpenDir for CachedFileSystem<F> {
+    type Dir = PlainDir<F::Dir>;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inner.open(path).map(PlainDir::new)
+    }
+}
+
+impl<F: api_ops::CreateFile> api_ops::CreateFile for CachedFileSystem<F> {
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        self.invalidate(path);
+        self.inner.create(path).map(|file| self.writer(path, file))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        self.invalidate(path);
+        self.inner.create_new(path).map(|file| self.writer(path, file))
+    }
+}
+
+impl<F: api_ops::ReplaceFile> api_ops::ReplaceFile for CachedFileSystem<F> {
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        self.inner.replace(path).map(|file| self.writer(path, file))
+    }
+}
+
+impl<F: api_ops::CreateDir> api_ops::CreateDir for CachedFileSystem<F> {
+    type Dir = PlainDir<F::Dir>;
+    type E = F::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        let result = self.inner.create(path);
+        self.invalidate(path);
+        result.map(PlainDir::new)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        let result = self.inner.create_new(path);
+        self.invalidate(path);
+        result.map(PlainDir::new)
+    }
+}
+
+impl<F: api_ops::RemoveFile> api_ops::RemoveFile for CachedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        let path = path.as_ref();
+        let result = self.inner.remove(path);
+        self.invalidate(path);
+        result
+    }
+}
+
+impl<F: api_ops::RemoveDir> api_ops::RemoveDir for CachedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        let path = path.as_ref();
+        let result = self.inner.remove(path);
+        self.invalidate(path);
+        result
+    }
+}
+
+#[cfg(test)]
+mod cached {
+    use ::{
+        filesystem_provider_api::{
+            fs::{entity::AtomicFile as _, ops, FileSystem as _},
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::provider::Provider,
+        std::{
+            io::{Read as _, Write as _},
+            path::Path,
+            sync::Mutex,
+            time::Duration,
+        },
+    };
+
+    use crate::cache::{CachedFileSystem, Options};
+
+    /// ファイルを開いた後、返す前に`hook`を一度だけ呼び出すファイルシステムです。
+    struct Interleave<F> {
+        inner: F,
+        hook: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    }
+
+    impl<F: ops::OpenFile> ops::OpenFile for Interleave<F> {
+        type E = F::E;
+        type File = F::File;
+
+        fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+            let file = self.inner.open(path)?;
+            if let Some(hook) = self.hook.lock().unwrap().take() {
+                hook();
+            }
+            Ok(file)
+        }
+    }
+
+    fn read<F>(filesystem: &CachedFileSystem<F>, path: &str) -> Result<String, Box<dyn std::error::Error>>
+    where
+        F: ops::OpenFile,
+        F::File: std::io::Read,
+        F::E: From<std::io::Error> + std::error::Error + 'static,
+    {
+        let mut buf = String::new();
+        ops::OpenFile::open(filesystem, path)?.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn contents() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("a.txt"), b"first")?;
+        let filesystem = CachedFileSystem::new(Provider::make(temp.to_path_buf()), Options::default());
+
+        assert_eq!(read(&filesystem, "a.txt")?, "first");
+        std::fs::write(temp.join("a.txt"), b"behind")?;
+        assert_eq!(read(&filesystem, "./a.txt")?, "first");
+
+        ops::CreateFile::create(&filesystem, "a.txt")?.write_all(b"through")?;
+        assert_eq!(read(&filesystem, "a.txt")?, "through");
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "a.txt")?;
+        file.write_all(b"replaced")?;
+        file.commit()?;
+        assert_eq!(read(&filesystem, "a.txt")?, "replaced");
+
+        std::fs::write(temp.join("a.txt"), b"behind")?;
+        filesystem.invalidate("a.txt");
+        assert_eq!(read(&filesystem, "a.txt")?, "behind");
+        Ok(())
+    }
+
+    #[test]
+    fn lru() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("a.txt"), b"aaaaaa")?;
+        std::fs::write(temp.join("b.txt"), b"bbbbbb")?;
+        std::fs::write(temp.join("big.txt"), b"too large")?;
+        let options = Options {
+            capacity: 8,
+            ..Options::default()
+        };
+        let filesystem = CachedFileSystem::new(Provider::make(temp.to_path_buf()), options);
+
+        read(&filesystem, "a.txt")?;
+        read(&filesystem, "b.txt")?;
+        std::fs::write(temp.join("a.txt"), b"AAAAAA")?;
+        std::fs::write(temp.join("b.txt"), b"BBBBBB")?;
+        assert_eq!(read(&filesystem, "a.txt")?, "AAAAAA");
+        assert_eq!(read(&filesystem, "b.txt")?, "BBBBBB");
+
+        assert_eq!(read(&filesystem, "big.txt")?, "too large");
+        std::fs::write(temp.join("big.txt"), b"TOO LARGE")?;
+        assert_eq!(read(&filesystem, "big.txt")?, "TOO LARGE");
+        Ok(())
+    }
+
+    #[test]
+    fn queries() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = CachedFileSystem::new(Provider::make(temp.to_path_buf()), Options::default());
+
+        assert!(!filesystem.exists("d"));
+        std::fs::create_dir(temp.join("d"))?;
+        assert!(!filesystem.exists("d"));
+
+        ops::CreateDir::create(&filesystem, "d/e")?;
+        assert!(filesystem.exists("d"));
+        assert!(filesystem.is_dir("d/e"));
+        assert_eq!(
+            filesystem.metadata("d")?.size(),
+            std::fs::metadata(temp.join("d"))?.len()
+        );
+
+        ops::RemoveDir::remove(&filesystem, "d")?;
+        assert!(!filesystem.exists("d/e"));
+
+        let options = Options {
+            ttl: Some(Duration::ZERO),
+            ..Options::default()
+        };
+        let filesystem = CachedFileSystem::new(filesystem.into_inner(), options);
+        assert!(!filesystem.exists("x"));
+        std::fs::create_dir(temp.join("x"))?;
+        assert!(filesystem.exists("x"));
+        Ok(())
+    }
+
+    #[test]
+    fn parent_components() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("x"))?;
+        std::fs::write(temp.join("f"), b"old")?;
+        let filesystem = CachedFileSystem::new(Provider::make(temp.to_path_buf()), Options::default());
+
+        assert_eq!(read(&filesystem, "f")?, "old");
+        assert!(filesystem.exists("f"));
+        ops::CreateFile::create(&filesystem, "x/../f")?.write_all(b"new")?;
+        assert_eq!(read(&filesystem, "f")?, "new");
+        assert_eq!(read(&filesystem, "x/../f")?, "new");
+
+        ops::RemoveFile::remove(&filesystem, "x/../f")?;
+        assert!(!filesystem.exists("f"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalidated_while_reading() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("a.txt"), b"first")?;
+        let inner = Interleave {
+            inner: Provider::make(temp.to_path_buf()),
+            hook: Mutex::new(None),
+        };
+        let filesystem = CachedFileSystem::new(inner, Options::default());
+
+        // 開いたファイルを読み終える前に、別の書き込みがファイルを置き換えてキャッシュを無効にする。
+        let (dir, cache) = (temp.to_path_buf(), filesystem.cache.clone());
+        *filesystem.inner().hook.lock().unwrap() = Some(Box::new(move || {
+            std::fs::write(dir.join("b.txt"), b"second").unwrap();
+            std::fs::rename(dir.join("b.txt"), dir.join("a.txt")).unwrap();
+            cache.lock().unwrap().invalidate(Path::new("a.txt"));
+        }));
+
+        assert_eq!(read(&filesystem, "a.txt")?, "first");
+        assert_eq!(read(&filesystem, "a.txt")?, "second");
+        Ok(())
+    }
+}
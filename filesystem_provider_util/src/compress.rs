@@ -0,0 +1,446 @@
+//! ファイルを圧縮して保存するファイルシステムのラッパー。
+//!
+//! [CompressedFileSystem]は[ops::CreateFile]などで書き込む内容を圧縮し、[ops::OpenFile]で開いたファイルを伸長します。
+//! 開いたファイルは[io::Read]と[io::Seek]を実装し、[entity::File::size]と[FileSystem::metadata]は伸長後の大きさを返します。
+//! ディレクトリのエントリの大きさは保存された大きさのままです。
+//!
+//! # 形式
+//!
+//! 圧縮したファイルは以下からなります。数値はすべてリトルエンディアンです。
+//!
+//! - ヘッダー: マジック`FPZ1`と、伸長後のフレームの大きさ(`u32`)
+//! - フレームの列: 平文をフレームの大きさごとに区切り、それぞれ独立に圧縮したgzipメンバー
+//! - インデックス: 各フレームの圧縮後の大きさ(`u64`)
+//! - フッター: フレームの数(`u64`)、伸長後の大きさ(`u64`)、マジック`FPZ1`
+//!
+//! フッターとインデックスからフレームの位置が分かるので、シークしたフレームだけを伸長します。
+//! インデックスは書き込みを終えた時に書かれるので、書き込み中のファイルは読めません。
+
+use ::{
+    filesystem_provider_api::fs::{entity, ops, FileSystem, Introspect},
+    flate2::{read::GzDecoder, write::GzEncoder, Compression},
+    std::{
+        convert::TryInto,
+        io::{self, Read as _, Write as _},
+        path::Path,
+    },
+};
+
+use crate::frame;
+
+const MAGIC: &[u8; 4] = b"FPZ1";
+const HEADER_LEN: u64 = 8;
+const FOOTER_LEN: u64 = 20;
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// 伸長後のフレームの大きさの上限です。
+pub const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// 圧縮の設定です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Options {
+    /// 0から9までの圧縮レベルです。
+    pub level: u32,
+    /// 伸長後のフレームの大きさです。小さいほどシークが速く、大きいほど圧縮率が高くなります。
+    ///
+    /// 1以上[MAX_FRAME_LEN]以下でなければなりません。
+    pub frame_len: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            frame_len: 256 * 1024,
+        }
+    }
+}
+
+/// 圧縮したファイルを読むハンドルです。シークしたフレームだけを伸長します。
+pub type File<R> = frame::File<Frames<R>>;
+
+/// 圧縮して書き込むハンドルです。インデックスは最後のフレームと一緒に書き込まれます。
+pub type Writer<W> = frame::Writer<W, Deflate>;
+
+fn malformed() -> io::Error {
+    invalid_data("malformed compressed file")
+}
+
+/// 圧縮したファイルのフレームを伸長します。
+#[derive(Debug)]
+pub struct Frames<R> {
+    inner: R,
+    frame_len: u64,
+    /// 各フレームの開始位置です。最後の要素はインデックスの開始位置です。
+    offsets: Vec<u64>,
+}
+
+impl<R: io::Read + io::Seek> Frames<R> {
+    fn open(mut inner: R) -> io::Result<File<R>> {
+        let mut header = [0; HEADER_LEN as usize];
+        inner.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not a compressed file"));
+        }
+        let frame_len = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if frame_len == 0 || frame_len > MAX_FRAME_LEN {
+            return Err(malformed());
+        }
+
+        let end = inner.seek(io::SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        let mut footer = [0; FOOTER_LEN as usize];
+        inner.read_exact(&mut footer)?;
+        if &footer[16..] != MAGIC {
+            return Err(invalid_data("truncated compressed file"));
+        }
+        let (count, size) = (read_u64(&footer), read_u64(&footer[8..]));
+        let index_start = count
+            .checked_mul(8)
+            .and_then(|len| end.checked_sub(len))
+            .filter(|start| *start >= HEADER_LEN)
+            .ok_or_else(malformed)?;
+
+        inner.seek(io::SeekFrom::Start(index_start))?;
+        let mut index = vec![0; (count * 8) as usize];
+        inner.read_exact(&mut index)?;
+        let mut offsets = vec![HEADER_LEN];
+        for len in index.chunks(8).map(read_u64) {
+            let next = offsets.last().unwrap().checked_add(len).ok_or_else(malformed)?;
+            offsets.push(next);
+        }
+        let frame_len = frame_len as u64;
+        if *offsets.last().unwrap() != index_start || size > count.saturating_mul(frame_len) {
+            return Err(malformed());
+        }
+
+        let frames = Self {
+            inner,
+            frame_len,
+            offsets,
+        };
+        Ok(File::new(frames, size))
+    }
+}
+
+impl<R: io::Read + io::Seek> frame::Decode for Frames<R> {
+    fn frame_len(&self) -> u64 {
+        self.frame_len
+    }
+
+    /// 伸長するのはフレームの大きさより1バイト多いところまでです。それより長いフレームは読み出す時に拒否されます。
+    fn decode(&mut self, index: u64) -> io::Result<Vec<u8>> {
+        let index = index as usize;
+        let (start, end) = match self.offsets.get(index..=index + 1) {
+            Some(&[start, end]) => (start, end),
+            _ => return Err(invalid_data("frame is out of the index")),
+        };
+        self.inner.seek(io::SeekFrom::Start(start))?;
+        let compressed = (&mut self.inner).take(end - start);
+        let mut frame = Vec::with_capacity(self.frame_len as usize);
+        GzDecoder::new(compressed)
+            .take(self.frame_len + 1)
+            .read_to_end(&mut frame)?;
+        Ok(frame)
+    }
+}
+
+/// フレームを圧縮し、最後にインデックスとフッターを書き込みます。
+#[derive(Debug)]
+pub struct Deflate {
+    level: Compression,
+    index: Vec<u8>,
+    written: u64,
+}
+
+impl Deflate {
+    fn create<W: io::Write>(mut inner: W, options: Options) -> io::Result<Writer<W>> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&options.frame_len.to_le_bytes())?;
+        let deflate = Self {
+            level: Compression::new(options.level),
+            index: Vec::new(),
+            written: 0,
+        };
+        Ok(Writer::new(inner, deflate, options.frame_len as usize))
+    }
+}
+
+impl frame::Encode for Deflate {
+    fn encode<W: io::Write>(&mut self, inner: &mut W, frame: &[u8], last: bool) -> io::Result<()> {
+        if !frame.is_empty() {
+            let mut encoder = GzEncoder::new(Vec::new(), self.level);
+            encoder.write_all(frame)?;
+            let compressed = encoder.finish()?;
+            inner.write_all(&compressed)?;
+            self.index.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+            self.written += frame.len() as u64;
+        }
+        if last {
+            let count = (self.index.len() / 8) as u64;
+            inner.write_all(&self.index)?;
+            inner.write_all(&count.to_le_bytes())?;
+            inner.write_all(&self.written.to_le_bytes())?;
+            inner.write_all(MAGIC)?;
+        }
+        Ok(())
+    }
+}
+
+/// ファイルシステムを包んでファイルを圧縮します。
+#[derive(Debug)]
+pub struct CompressedFileSystem<F> {
+    inner: F,
+    options: Options,
+}
+
+impl<F> CompressedFileSystem<F> {
+    /// # Panics
+    ///
+    /// `options.frame_len`が0か[MAX_FRAME_LEN]より大きい場合。
+    pub fn new(inner: F, options: Options) -> Self {
+        assert!(
+            (1..=MAX_FRAME_LEN).contains(&options.frame_len),
+            "frame_len must be between 1 and {}",
+            MAX_FRAME_LEN
+        );
+        Self { inner, options }
+    }
+
+    forward!(accessors: F);
+}
+
+impl<F: Introspect> Introspect for CompressedFileSystem<F> {
+    forward!(introspect: is_readable, is_writable, is_truncatable, is_removable);
+
+    /// 追記にはインデックスを書き直す必要があるので、対応しません。
+    fn is_appendable(&self) -> bool {
+        false
+    }
+}
+
+/// ファイルの[entity::Metadata::size]を求めるためにファイルを開いてフッターを読みます。
+/// 圧縮したファイルとして読めない場合は失敗します。
+impl<F> FileSystem for CompressedFileSystem<F>
+where
+    F: FileSystem<MetadataE: From<io::Error>> + ops::OpenFile<File: io::Read + io::Seek>,
+    <F as ops::OpenFile>::E: Into<crate::BoxError>,
+{
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        let sub = sub.as_ref();
+        let stored = self.inner.metadata(sub)?;
+        if *stored.r#type() == entity::Type::Dir {
+            return Ok(stored);
+        }
+        let file = ops::OpenFile::open(&self.inner, sub).map_err(|err| io::Error::other(err.into()))?;
+        let size = entity::File::size(&Frames::open(file)?);
+        let metadata = entity::Metadata::new(sub.into(), entity::Type::File, size);
+        Ok(match stored.modified() {
+            Some(modified) => metadata.with_modified(modified),
+            None => metadata,
+        })
+    }
+
+    forward!(queries);
+}
+
+impl<F: ops::OpenFile<E: From<io::Error>, File: io::Read + io::Seek>> ops::OpenFile for CompressedFileSystem<F> {
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        Ok(Frames::open(self.inner.open(path)?)?)
+    }
+}
+
+impl<F: ops::OpenDir> ops::OpenDir for CompressedFileSystem<F> {
+    type Dir = F::Dir;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inner.open(path)
+    }
+}
+
+impl<F: ops::CreateFile<E: From<io::Error>, File: io::Write>> ops::CreateFile for CompressedFileSystem<F> {
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        Ok(Deflate::create(self.inner.create(path)?, self.options)?)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        Ok(Deflate::create(self.inner.create_new(path)?, self.options)?)
+    }
+}
+
+impl<F> ops::ReplaceFile for CompressedFileSystem<F>
+where
+    F: ops::ReplaceFile<E: From<io::Error>, File: io::Write + entity::AtomicFile<E: From<io::Error>>>,
+{
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        Ok(Deflate::create(self.inner.replace(path)?, self.options)?)
+    }
+}
+
+impl<F: ops::CreateDir> ops::CreateDir for CompressedFileSystem<F> {
+    type Dir = F::Dir;
+    type E = F::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inner.create(path)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inner.create_new(path)
+    }
+}
+
+impl<F: ops::RemoveFile> ops::RemoveFile for CompressedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.inner.remove(path)
+    }
+}
+
+impl<F: ops::RemoveDir> ops::RemoveDir for CompressedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.inner.remove(path)
+    }
+}
+
+#[cfg(test)]
+mod frames {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                entity::{AtomicFile as _, File as _},
+                ops, FileSystem as _,
+            },
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::{fs::OpenEntityError, provider::Provider},
+        flate2::{write::GzEncoder, Compression},
+        std::io::{self, Read as _, Seek as _, Write as _},
+    };
+
+    use crate::compress::{CompressedFileSystem, Options, MAGIC};
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let options = Options {
+            frame_len: 1000,
+            ..Options::default()
+        };
+        let filesystem = CompressedFileSystem::new(Provider::make(temp.to_path_buf()), options);
+
+        let log = (0..500)
+            .map(|i| format!("{:05} INFO request handled\n", i))
+            .collect::<String>();
+        let mut file = ops::CreateFile::create(&filesystem, "app.log")?;
+        file.write_all(log.as_bytes())?;
+        file.finish()?;
+        ops::CreateFile::create(&filesystem, "empty.log")?;
+
+        assert!(std::fs::metadata(temp.join("app.log"))?.len() < log.len() as u64 / 4);
+        assert_eq!(filesystem.metadata("app.log")?.size(), log.len() as u64);
+        assert_eq!(filesystem.metadata("empty.log")?.size(), 0);
+
+        let mut file = ops::OpenFile::open(&filesystem, "app.log")?;
+        assert_eq!(file.size(), log.len() as u64);
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        assert_eq!(text, log);
+
+        let mut buf = [0; 27];
+        file.seek(io::SeekFrom::Start(27 * 250))?;
+        file.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"00250 INFO request handled\n");
+        file.seek(io::SeekFrom::End(-3))?;
+        text.clear();
+        file.read_to_string(&mut text)?;
+        assert_eq!(text, "ed\n");
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "empty.log")?;
+        file.write_all(b"replaced")?;
+        file.commit()?;
+        text.clear();
+        ops::OpenFile::open(&filesystem, "empty.log")?.read_to_string(&mut text)?;
+        assert_eq!(text, "replaced");
+        Ok(())
+    }
+
+    #[test]
+    fn truncated() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = CompressedFileSystem::new(Provider::make(temp.to_path_buf()), Options::default());
+        ops::CreateFile::create(&filesystem, "a.log")?.write_all(b"hello")?;
+
+        let stored = std::fs::read(temp.join("a.log"))?;
+        std::fs::write(temp.join("a.log"), &stored[..stored.len() - 1])?;
+        assert!(ops::OpenFile::open(&filesystem, "a.log").is_err());
+        assert!(filesystem.metadata("a.log").is_err());
+        Ok(())
+    }
+
+    /// フレームとインデックスを並べて圧縮したファイルを組み立てます。
+    fn assemble(frame_len: u32, frames: &[&[u8]], index: &[u64], size: u64) -> Vec<u8> {
+        let mut stored = [&MAGIC[..], &frame_len.to_le_bytes()].concat();
+        for frame in frames {
+            stored.extend_from_slice(frame);
+        }
+        for len in index {
+            stored.extend_from_slice(&len.to_le_bytes());
+        }
+        stored.extend_from_slice(&(index.len() as u64).to_le_bytes());
+        stored.extend_from_slice(&size.to_le_bytes());
+        stored.extend_from_slice(MAGIC);
+        stored
+    }
+
+    #[test]
+    fn malformed() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = CompressedFileSystem::new(Provider::make(temp.to_path_buf()), Options::default());
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 5000])?;
+        let bomb = encoder.finish()?;
+        let len = bomb.len() as u64;
+        std::fs::write(temp.join("bomb"), assemble(1000, &[&bomb], &[len], 1000))?;
+        let err = ops::OpenFile::open(&filesystem, "bomb")?.read(&mut [0; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(temp.join("huge"), assemble(u32::MAX, &[&bomb], &[len], 1000))?;
+        std::fs::write(
+            temp.join("overflow"),
+            assemble(1000, &[&bomb], &[u64::MAX, len + 1], 1000),
+        )?;
+        for path in ["huge", "overflow"] {
+            assert!(matches!(
+                ops::OpenFile::open(&filesystem, path),
+                Err(OpenEntityError::IoError(err)) if err.kind() == io::ErrorKind::InvalidData
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,797 @@
+//! ファイルの内容と名前を暗号化して保存するファイルシステムのラッパー。
+//!
+//! [EncryptedFileSystem]は[ops::CreateFile]などで書き込む内容を暗号化し、[ops::OpenFile]で開いたファイルを復号します。
+//! 包むファイルシステムのバックエンドは問いません。鍵は[Provider]でファイルシステムを作る時に渡します。
+//!
+//! # 形式
+//!
+//! 暗号化したファイルは4バイトのマジック`FPE1`、16バイトのランダムなファイルID、チャンクの列からなります。
+//! 平文は[CHUNK_LEN]バイトごとのチャンクに分けられ、それぞれXChaCha20-Poly1305で暗号化されて16バイトのタグが付きます。
+//! ノンスはファイルID、チャンクの番号、最後のチャンクかどうかから作るので、チャンクの入れ替えや切り詰めは復号の失敗として検出されます。
+//! 空のファイルも空の最後のチャンクを一つ持ちます。
+//!
+//! チャンクごとに復号できるので、開いたファイルは[io::Seek]を実装します。平文の大きさは暗号文の大きさから計算できます。
+//!
+//! # 名前の暗号化
+//!
+//! [Options::encrypt_names]が`true`の場合、パスの各コンポーネントも暗号化されます。
+//! 同じ名前が常に同じ暗号文になるように、ノンスは名前の鍵付きハッシュから作ります。
+//! 暗号化した名前はノンスと暗号文をパディングの無いURL安全なBase64で表したもので、元の名前より長くなります。
+//! 名前はUTF-8でなければなりません。
+//!
+//! ディレクトリの一覧で復号できない名前はそのまま返します。
+
+use ::{
+    chacha20poly1305::{
+        aead::{rand_core::RngCore as _, Aead as _, KeyInit as _, OsRng},
+        XChaCha20Poly1305, XNonce,
+    },
+    filesystem_provider_api::{
+        fs::{
+            entity::{self, Type},
+            ops, FileSystem, Introspect,
+        },
+        provider::make,
+    },
+    std::{
+        ffi::OsString,
+        fmt,
+        io::{self, Read as _},
+        marker::PhantomData,
+        path::{Component, Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+use crate::frame;
+
+/// 平文のチャンクの大きさです。
+pub const CHUNK_LEN: usize = 64 * 1024;
+
+const MAGIC: &[u8; 4] = b"FPE1";
+const FILE_ID_LEN: usize = 16;
+const HEADER_LEN: u64 = (MAGIC.len() + FILE_ID_LEN) as u64;
+const TAG_LEN: usize = 16;
+const SEALED_LEN: u64 = (CHUNK_LEN + TAG_LEN) as u64;
+
+/// 暗号化の鍵です。ファイルの内容と名前の鍵はこの鍵から導出されます。
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+struct Keys {
+    content: XChaCha20Poly1305,
+    name: XChaCha20Poly1305,
+    name_nonce: [u8; 32],
+}
+
+impl Keys {
+    fn derive(key: &Key) -> Self {
+        let derive = |context| blake3::derive_key(context, &key.0);
+        Self {
+            content: XChaCha20Poly1305::new(&derive("filesystem_provider_util crypt content").into()),
+            name: XChaCha20Poly1305::new(&derive("filesystem_provider_util crypt name").into()),
+            name_nonce: derive("filesystem_provider_util crypt name nonce"),
+        }
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Keys(..)")
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 暗号化したファイルの大きさから平文の大きさを求めます。
+fn plain_size(stored: u64) -> u64 {
+    let data = stored.saturating_sub(HEADER_LEN);
+    data.saturating_sub(data.div_ceil(SEALED_LEN) * TAG_LEN as u64)
+}
+
+fn chunk_nonce(file_id: &[u8; FILE_ID_LEN], index: u64, last: bool) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..FILE_ID_LEN].copy_from_slice(file_id);
+    nonce[FILE_ID_LEN..].copy_from_slice(&(index | (last as u64) << 63).to_le_bytes());
+    nonce
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - i * 8));
+        for i in 0..=chunk.len() {
+            encoded.push(BASE64[(n >> (18 - i * 6)) as usize & 63] as char);
+        }
+    }
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            n |= (BASE64.iter().position(|b| b == c)? as u32) << (18 - i * 6);
+        }
+        bytes.extend((0..chunk.len() - 1).map(|i| (n >> (16 - i * 8)) as u8));
+    }
+    Some(bytes)
+}
+
+/// 暗号化の設定です。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Options {
+    /// `true`の場合、パスの各コンポーネントを暗号化します。
+    pub encrypt_names: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Cipher {
+    keys: Arc<Keys>,
+    options: Options,
+}
+
+impl Cipher {
+    fn encrypt_name(&self, name: &std::ffi::OsStr) -> io::Result<OsString> {
+        let name = name
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "name is not UTF-8"))?;
+        let mut nonce = XNonce::default();
+        nonce.copy_from_slice(&blake3::keyed_hash(&self.keys.name_nonce, name.as_bytes()).as_bytes()[..24]);
+        let sealed = self
+            .keys
+            .name
+            .encrypt(&nonce, name.as_bytes())
+            .map_err(|_| invalid_data("failed to encrypt name"))?;
+        Ok(encode_base64(&[nonce.as_slice(), &sealed].concat()).into())
+    }
+
+    fn decrypt_name(&self, name: &std::ffi::OsStr) -> Option<OsString> {
+        let bytes = decode_base64(name.to_str()?)?;
+        if bytes.len() < 24 {
+            return None;
+        }
+        let (nonce, sealed) = bytes.split_at(24);
+        let plain = self.keys.name.decrypt(XNonce::from_slice(nonce), sealed).ok()?;
+        String::from_utf8(plain).ok().map(Into::into)
+    }
+
+    /// パスの通常のコンポーネントを暗号化します。
+    fn encrypt_path(&self, path: &Path) -> io::Result<PathBuf> {
+        if !self.options.encrypt_names {
+            return Ok(path.to_path_buf());
+        }
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.encrypt_name(name),
+                component => Ok(component.as_os_str().to_owned()),
+            })
+            .collect()
+    }
+
+    /// パスの通常のコンポーネントを復号します。復号できないコンポーネントはそのままにします。
+    fn decrypt_path(&self, path: &Path) -> PathBuf {
+        if !self.options.encrypt_names {
+            return path.to_path_buf();
+        }
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.decrypt_name(name).unwrap_or_else(|| name.to_owned()),
+                component => component.as_os_str().to_owned(),
+            })
+            .collect()
+    }
+
+    fn reader<R: io::Read + entity::File>(&self, mut inner: R) -> io::Result<File<R>> {
+        let mut header = [0; HEADER_LEN as usize];
+        inner.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("not an encrypted file"));
+        }
+        let data = inner.size().saturating_sub(HEADER_LEN);
+        if data < TAG_LEN as u64 {
+            return Err(invalid_data("truncated encrypted file"));
+        }
+        let mut file_id = [0; FILE_ID_LEN];
+        file_id.copy_from_slice(&header[MAGIC.len()..]);
+        let chunks = Chunks {
+            inner,
+            keys: self.keys.clone(),
+            file_id,
+            count: data.div_ceil(SEALED_LEN),
+        };
+        Ok(File::new(chunks, plain_size(data + HEADER_LEN)))
+    }
+
+    fn writer<W: io::Write>(&self, mut inner: W) -> io::Result<Writer<W>> {
+        let mut file_id = [0; FILE_ID_LEN];
+        OsRng.fill_bytes(&mut file_id);
+        inner.write_all(MAGIC)?;
+        inner.write_all(&file_id)?;
+        let seal = Seal {
+            keys: self.keys.clone(),
+            file_id,
+            index: 0,
+        };
+        Ok(Writer::new(inner, seal, CHUNK_LEN))
+    }
+}
+
+/// 暗号化したファイルを読むハンドルです。チャンクごとに復号するので、シークできます。
+pub type File<R> = frame::File<Chunks<R>>;
+
+/// 暗号化して書き込むハンドルです。
+pub type Writer<W> = frame::Writer<W, Seal>;
+
+/// 暗号化したファイルのチャンクを復号します。
+#[derive(Debug)]
+pub struct Chunks<R> {
+    inner: R,
+    keys: Arc<Keys>,
+    file_id: [u8; FILE_ID_LEN],
+    count: u64,
+}
+
+impl<R: io::Read + io::Seek> frame::Decode for Chunks<R> {
+    fn frame_len(&self) -> u64 {
+        CHUNK_LEN as u64
+    }
+
+    fn decode(&mut self, index: u64) -> io::Result<Vec<u8>> {
+        self.inner.seek(io::SeekFrom::Start(HEADER_LEN + index * SEALED_LEN))?;
+        let mut sealed = Vec::with_capacity(SEALED_LEN as usize);
+        (&mut self.inner).take(SEALED_LEN).read_to_end(&mut sealed)?;
+        let nonce = chunk_nonce(&self.file_id, index, index + 1 == self.count);
+        self.keys
+            .content
+            .decrypt(&nonce, sealed.as_slice())
+            .map_err(|_| invalid_data("failed to decrypt chunk"))
+    }
+}
+
+/// チャンクを暗号化します。
+#[derive(Debug)]
+pub struct Seal {
+    keys: Arc<Keys>,
+    file_id: [u8; FILE_ID_LEN],
+    index: u64,
+}
+
+impl frame::Encode for Seal {
+    fn encode<W: io::Write>(&mut self, inner: &mut W, frame: &[u8], last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.file_id, self.index, last);
+        let sealed = self
+            .keys
+            .content
+            .encrypt(&nonce, frame)
+            .map_err(|_| invalid_data("failed to encrypt chunk"))?;
+        inner.write_all(&sealed)?;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+/// 名前を復号し、ファイルの大きさを平文の大きさにしたディレクトリです。
+#[derive(Debug)]
+pub struct Dir<D> {
+    inner: D,
+    cipher: Cipher,
+}
+
+impl<D: entity::Dir> entity::File for Dir<D> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_file(&self) -> bool {
+        false
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+}
+
+impl<D: entity::Dir> entity::Dir for Dir<D> {
+    type Entry = DirEntry<D::Entry>;
+    type IterE = D::IterE;
+    type Entries = DirEntries<D::Entries>;
+    type EntriesE = D::EntriesE;
+
+    fn total_size(&self) -> u64 {
+        self.entries()
+            .map(|entries| entries.flatten().map(|entry| entity::File::size(&entry)).sum())
+            .unwrap_or_default()
+    }
+
+    fn count(&self) -> usize {
+        self.inner.count()
+    }
+
+    fn entries(&self) -> Result<Self::Entries, Self::EntriesE> {
+        Ok(DirEntries {
+            inner: self.inner.entries()?,
+            cipher: self.cipher.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DirEntries<I> {
+    inner: I,
+    cipher: Cipher,
+}
+
+impl<I: Iterator<Item = Result<T, E>>, T, E> Iterator for DirEntries<I> {
+    type Item = Result<DirEntry<T>, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cipher = &self.cipher;
+        self.inner.next().map(|entry| {
+            entry.map(|inner| DirEntry {
+                inner,
+                cipher: cipher.clone(),
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DirEntry<T> {
+    inner: T,
+    cipher: Cipher,
+}
+
+impl<T: entity::DirEntry> entity::File for DirEntry<T> {
+    fn size(&self) -> u64 {
+        if self.inner.is_file() {
+            plain_size(self.inner.size())
+        } else {
+            self.inner.size()
+        }
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+}
+
+impl<T: entity::DirEntry> entity::DirEntry for DirEntry<T> {
+    fn path(&self) -> PathBuf {
+        self.cipher.decrypt_path(&self.inner.path())
+    }
+
+    fn file_name(&self) -> OsString {
+        let name = self.inner.file_name();
+        match self.cipher.options.encrypt_names {
+            true => self.cipher.decrypt_name(&name).unwrap_or(name),
+            false => name,
+        }
+    }
+
+    fn file_type(&self) -> Option<Type> {
+        self.inner.file_type()
+    }
+}
+
+/// ファイルシステムを包んで内容と名前を暗号化します。
+#[derive(Debug)]
+pub struct EncryptedFileSystem<F> {
+    inner: F,
+    cipher: Cipher,
+}
+
+impl<F> EncryptedFileSystem<F> {
+    pub fn new(inner: F, key: &Key, options: Options) -> Self {
+        Self {
+            inner,
+            cipher: Cipher {
+                keys: Arc::new(Keys::derive(key)),
+                options,
+            },
+        }
+    }
+
+    forward!(accessors: F);
+
+    /// `path`を包んだファイルシステムでのパスにします。
+    pub fn stored_path<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        self.cipher.encrypt_path(path.as_ref())
+    }
+
+    /// パスを暗号化してから`f`を呼び出します。
+    fn stored<T, E: From<io::Error>>(&self, path: &Path, f: impl FnOnce(&F, &Path) -> Result<T, E>) -> Result<T, E> {
+        f(&self.inner, &self.cipher.encrypt_path(path)?)
+    }
+}
+
+impl<F: Introspect> Introspect for EncryptedFileSystem<F> {
+    forward!(introspect: is_readable, is_writable, is_truncatable, is_removable);
+
+    /// 追記には最後のチャンクを復号し直す必要があるので、対応しません。
+    fn is_appendable(&self) -> bool {
+        false
+    }
+}
+
+impl<F: FileSystem<MetadataE: From<io::Error>>> FileSystem for EncryptedFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        let sub = sub.as_ref();
+        let stored = self.stored(sub, |inner, path| inner.metadata(path))?;
+        let size = match stored.r#type() {
+            Type::File => plain_size(stored.size()),
+            Type::Dir => stored.size(),
+        };
+        let metadata = entity::Metadata::new(sub.into(), stored.r#type().clone(), size);
+        Ok(match stored.modified() {
+            Some(modified) => metadata.with_modified(modified),
+            None => metadata,
+        })
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.cipher
+            .encrypt_path(path.as_ref())
+            .is_ok_and(|path| self.inner.exists(path))
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.cipher
+            .encrypt_path(path.as_ref())
+            .is_ok_and(|path| self.inner.is_file(path))
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.cipher
+            .encrypt_path(path.as_ref())
+            .is_ok_and(|path| self.inner.is_dir(path))
+    }
+}
+
+impl<F> ops::OpenFile for EncryptedFileSystem<F>
+where
+    F: ops::OpenFile<E: From<io::Error>, File: io::Read + io::Seek>,
+{
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let file = self.stored(path.as_ref(), |inner, path| inner.open(path))?;
+        Ok(self.cipher.reader(file)?)
+    }
+}
+
+impl<F: ops::OpenDir<E: From<io::Error>>> ops::OpenDir for EncryptedFileSystem<F> {
+    type Dir = Dir<F::Dir>;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let inner = self.stored(path.as_ref(), |inner, path| inner.open(path))?;
+        Ok(Dir {
+            inner,
+            cipher: self.cipher.clone(),
+        })
+    }
+}
+
+impl<F: ops::CreateFile<E: From<io::Error>, File: io::Write>> ops::CreateFile for EncryptedFileSystem<F> {
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let file = self.stored(path.as_ref(), |inner, path| inner.create(path))?;
+        Ok(self.cipher.writer(file)?)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let file = self.stored(path.as_ref(), |inner, path| inner.create_new(path))?;
+        Ok(self.cipher.writer(file)?)
+    }
+}
+
+impl<F> ops::ReplaceFile for EncryptedFileSystem<F>
+where
+    F: ops::ReplaceFile<E: From<io::Error>, File: io::Write + entity::AtomicFile<E: From<io::Error>>>,
+{
+    type E = F::E;
+    type File = Writer<F::File>;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let file = self.stored(path.as_ref(), |inner, path| inner.replace(path))?;
+        Ok(self.cipher.writer(file)?)
+    }
+}
+
+impl<F: ops::CreateDir<E: From<io::Error>>> ops::CreateDir for EncryptedFileSystem<F> {
+    type Dir = Dir<F::Dir>;
+    type E = F::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let inner = self.stored(path.as_ref(), |inner, path| inner.create(path))?;
+        Ok(Dir {
+            inner,
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let inner = self.stored(path.as_ref(), |inner, path| inner.create_new(path))?;
+        Ok(Dir {
+            inner,
+            cipher: self.cipher.clone(),
+        })
+    }
+}
+
+impl<F: ops::RemoveFile<E: From<io::Error>>> ops::RemoveFile for EncryptedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.stored(path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+impl<F: ops::RemoveDir<E: From<io::Error>>> ops::RemoveDir for EncryptedFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.stored(path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+/// 鍵を持ち、`P`が作るファイルシステムを[EncryptedFileSystem]で包んで返すプロバイダーです。
+#[derive(Debug)]
+pub struct Provider<P> {
+    key: Key,
+    options: Options,
+    provider: PhantomData<fn() -> P>,
+}
+
+impl<P> Provider<P> {
+    pub fn new(key: Key, options: Options) -> Self {
+        Self {
+            key,
+            options,
+            provider: PhantomData,
+        }
+    }
+}
+
+impl<P: make::Make> make::Readable for Provider<P> {
+    type Readable = EncryptedFileSystem<P::FS>;
+
+    fn make_readable(&mut self, root: PathBuf) -> make::Result<Self::Readable> {
+        Ok(EncryptedFileSystem::new(P::make(root), &self.key, self.options))
+    }
+}
+
+impl<P: make::Make> make::Writable for Provider<P> {
+    type Writable = EncryptedFileSystem<P::FS>;
+
+    fn make_writable(&mut self, root: PathBuf) -> make::Result<Self::Writable> {
+        Ok(EncryptedFileSystem::new(P::make(root), &self.key, self.options))
+    }
+}
+
+#[cfg(test)]
+mod at_rest {
+    use ::{
+        filesystem_provider_api::{
+            fs::{
+                entity::{AtomicFile as _, Dir as _, DirEntry as _, File as _},
+                ops, FileSystem as _,
+            },
+            provider::make::{Readable as _, Writable as _},
+        },
+        filesystem_provider_impl_disk::provider::Provider as Disk,
+        std::{
+            io::{self, Read as _, Seek as _, Write as _},
+            sync::Arc,
+        },
+    };
+
+    use crate::crypt::{decode_base64, encode_base64, Cipher, Key, Keys, Options, Provider, CHUNK_LEN};
+
+    #[test]
+    fn base64() {
+        for len in 0..8 {
+            let bytes = (0..len)
+                .map(|i: u8| i.wrapping_mul(37).wrapping_add(200))
+                .collect::<Vec<u8>>();
+            assert_eq!(decode_base64(&encode_base64(&bytes)), Some(bytes));
+        }
+        assert_eq!(encode_base64(b"\xfb\xff"), "-_8");
+    }
+
+    #[test]
+    fn contents() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let mut provider = Provider::<Disk>::new(Key::new([7; 32]), Options::default());
+        let filesystem = provider.make_writable(temp.to_path_buf())?;
+
+        let plain = (0..CHUNK_LEN * 2 + 100).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut file = ops::CreateFile::create(&filesystem, "a.bin")?;
+        file.write_all(&plain)?;
+        file.finish()?;
+        ops::CreateFile::create(&filesystem, "empty")?;
+
+        let stored = std::fs::read(temp.join("a.bin"))?;
+        assert!(!stored.windows(64).any(|window| window == &plain[..64]));
+        assert_eq!(filesystem.metadata("a.bin")?.size(), plain.len() as u64);
+        assert_eq!(filesystem.metadata("empty")?.size(), 0);
+
+        let mut file = ops::OpenFile::open(&filesystem, "a.bin")?;
+        assert_eq!(file.size(), plain.len() as u64);
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        assert_eq!(buf, plain);
+
+        let mut buf = [0; 10];
+        file.seek(io::SeekFrom::Start(CHUNK_LEN as u64 - 5))?;
+        file.read_exact(&mut buf)?;
+        assert_eq!(buf, plain[CHUNK_LEN - 5..CHUNK_LEN + 5]);
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "empty")?;
+        file.write_all(b"replaced")?;
+        file.commit()?;
+        let mut buf = String::new();
+        ops::OpenFile::open(&filesystem, "empty")?.read_to_string(&mut buf)?;
+        assert_eq!(buf, "replaced");
+        Ok(())
+    }
+
+    #[test]
+    fn tampering() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let mut provider = Provider::<Disk>::new(Key::new([7; 32]), Options::default());
+        let filesystem = provider.make_writable(temp.to_path_buf())?;
+        let plain = vec![1; CHUNK_LEN + 1];
+        ops::CreateFile::create(&filesystem, "a.bin")?.write_all(&plain)?;
+        let stored = std::fs::read(temp.join("a.bin"))?;
+
+        let read = |bytes: &[u8]| -> io::Result<Vec<u8>> {
+            std::fs::write(temp.join("a.bin"), bytes)?;
+            let mut buf = Vec::new();
+            ops::OpenFile::open(&filesystem, "a.bin")
+                .map_err(io::Error::other)?
+                .read_to_end(&mut buf)?;
+            Ok(buf)
+        };
+        assert_eq!(read(&stored)?, plain);
+
+        let mut flipped = stored.clone();
+        flipped[100] ^= 1;
+        assert_eq!(read(&flipped).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let truncated = &stored[..stored.len() - 17];
+        assert_eq!(read(truncated).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(temp.join("a.bin"), &stored)?;
+        let mut other = Provider::<Disk>::new(Key::new([8; 32]), Options::default());
+        let filesystem = other.make_readable(temp.to_path_buf())?;
+        let mut buf = Vec::new();
+        let err = ops::OpenFile::open(&filesystem, "a.bin")?
+            .read_to_end(&mut buf)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn names() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let options = Options { encrypt_names: true };
+        let mut provider = Provider::<Disk>::new(Key::new([7; 32]), options);
+        let filesystem = provider.make_writable(temp.to_path_buf())?;
+
+        ops::CreateDir::create(&filesystem, "secret")?;
+        ops::CreateFile::create(&filesystem, "secret/plans.txt")?.write_all(b"plans")?;
+        assert!(filesystem.is_file("./secret/plans.txt"));
+
+        let raw = std::fs::read_dir(&temp)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(raw.len(), 1);
+        assert_ne!(raw[0], "secret");
+        assert_eq!(raw[0], filesystem.stored_path("secret")?.into_os_string());
+
+        let dir = ops::OpenDir::open(&filesystem, "secret")?;
+        let entries = dir.entries()?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_name(), "plans.txt");
+        assert_eq!(entries[0].size(), 5);
+        assert!(entries[0].path().ends_with("secret/plans.txt"));
+        assert_eq!(dir.total_size(), 5);
+
+        ops::RemoveDir::remove(&filesystem, "secret")?;
+        assert!(!filesystem.exists("secret"));
+        Ok(())
+    }
+
+    /// `fail_at`バイトまで書き込んだ後に一度だけ失敗するライターです。
+    struct Flaky {
+        written: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl io::Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.fail_at {
+                Some(fail_at) if self.written + buf.len() > fail_at => {
+                    let n = fail_at - self.written;
+                    if n == 0 {
+                        self.fail_at = None;
+                        return Err(io::Error::other("flaky"));
+                    }
+                    self.written += n;
+                    Ok(n)
+                },
+                _ => {
+                    self.written += buf.len();
+                    Ok(buf.len())
+                },
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_write() -> Result<(), Box<dyn std::error::Error>> {
+        let cipher = Cipher {
+            keys: Arc::new(Keys::derive(&Key::new([7; 32]))),
+            options: Options::default(),
+        };
+        let mut writer = cipher.writer(Flaky {
+            written: 0,
+            fail_at: Some(100),
+        })?;
+        assert!(writer.write_all(&[0; CHUNK_LEN + 1]).is_err());
+        assert!(writer.write(b"more").is_err());
+        assert!(writer.flush().is_err());
+        assert!(writer.finish().is_err());
+        Ok(())
+    }
+}
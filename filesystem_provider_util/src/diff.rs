@@ -0,0 +1,340 @@
+//! 二つのディレクトリツリーの差分を調べるモジュール。
+//!
+//! 比較する二つのファイルシステムのバックエンドは異なっていても構いません。
+//! 例えば、アーカイブの中のリリースとディスク上のリリースを比較できます。
+//!
+//! ファイルの内容は[entity::Metadata::size]に加えて、[Compare]に従って最終更新日時かハッシュで比較します。
+//! ファイルでもディレクトリでもないエンティティは無視されます。
+
+use ::{
+    filesystem_provider_api::fs::{
+        entity::{self, Dir as _, DirEntry as _, Type},
+        ops,
+    },
+    std::{
+        collections::BTreeMap,
+        ffi::OsString,
+        path::{Path, PathBuf},
+    },
+};
+
+use crate::{
+    hash::{self, Algorithm},
+    BoxError, Source,
+};
+
+/// 差分の各エントリ。パスは比較したツリーのルートを基準とした相対パスです。
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Change {
+    /// 新しいツリーにだけある。
+    Added(PathBuf, Type),
+    /// 古いツリーにだけある。
+    Removed(PathBuf, Type),
+    /// ファイルとディレクトリが入れ替わった。
+    TypeChanged { path: PathBuf, from: Type, to: Type },
+    /// ファイルの内容が変わった。
+    Modified(PathBuf),
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added(path, _) | Change::Removed(path, _) | Change::Modified(path) => path,
+            Change::TypeChanged { path, .. } => path,
+        }
+    }
+}
+
+/// ファイルの内容の比較方法です。いずれも大きさが異なるファイルは変更されたと見なします。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Compare {
+    /// 大きさだけを比較します。
+    #[default]
+    Size,
+    /// 最終更新日時が異なる場合に変更されたと見なします。
+    /// バックアップから戻したファイルのように、新しいツリーの方が古い日時を持つ場合も変更されたと見なします。
+    /// どちらかのファイルシステムが最終更新日時を持たない場合は変更されたと見なします。
+    ///
+    /// コピーしても最終更新日時は揃わないので、[crate::sync::sync]では使えません。
+    Mtime,
+    /// 内容をこのハッシュ関数で比較します。
+    Content(Algorithm),
+}
+
+/// 差分の調べ方です。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Options {
+    pub compare: Compare,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to open {0:?}: {1}")]
+    OpenError(PathBuf, #[source] BoxError),
+    #[error("failed to read entries of {0:?}: {1}")]
+    EntriesError(PathBuf, #[source] BoxError),
+    #[error("failed to read metadata of {0:?}: {1}")]
+    MetadataError(PathBuf, #[source] BoxError),
+    #[error(transparent)]
+    HashError(#[from] hash::Error),
+}
+
+pub type Result<T> = std::result::Result<T, self::Error>;
+
+/// `old`の`old_path`から`new`の`new_path`への差分を返します。
+/// 引数`old_path`と`new_path`はそれぞれのファイルシステムの基底パスを基準としたサブパスと見なされます。
+///
+/// 差分はパスの順に並びます。ディレクトリが追加または削除された場合、その下階のエンティティもすべて報告されます。
+pub fn diff<A, B, P, Q>(old: &A, old_path: P, new: &B, new_path: Q, options: &Options) -> Result<Vec<Change>>
+where
+    A: Source,
+    B: Source,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut changes = Vec::new();
+    let old = Side::new(old, old_path.as_ref());
+    let new = Side::new(new, new_path.as_ref());
+    diff_dir(&old, &new, Path::new(""), options, &mut changes)?;
+    Ok(changes)
+}
+
+/// 比較する一方のツリーです。
+struct Side<'a, F> {
+    filesystem: &'a F,
+    root: &'a Path,
+}
+
+impl<'a, F: Source> Side<'a, F> {
+    fn new(filesystem: &'a F, root: &'a Path) -> Self {
+        Self { filesystem, root }
+    }
+
+    fn path(&self, relative: &Path) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    /// `relative`のディレクトリの子エンティティを名前の順に返します。
+    fn children(&self, relative: &Path) -> Result<BTreeMap<OsString, Type>> {
+        let path = self.path(relative);
+        let dir =
+            ops::OpenDir::open(self.filesystem, &path).map_err(|err| Error::OpenError(path.clone(), err.into()))?;
+        let entries_error = |err: BoxError| Error::EntriesError(path.clone(), err);
+
+        let mut children = BTreeMap::new();
+        for entry in dir.entries().map_err(|err| entries_error(err.into()))? {
+            let entry = entry.map_err(|err| entries_error(err.into()))?;
+            if let Some(r#type) = entry.file_type() {
+                children.insert(entry.file_name(), r#type);
+            }
+        }
+        Ok(children)
+    }
+
+    fn metadata(&self, relative: &Path) -> Result<entity::Metadata> {
+        let path = self.path(relative);
+        self.filesystem
+            .metadata(&path)
+            .map_err(|err| Error::MetadataError(path, err.into()))
+    }
+
+    fn hash(&self, relative: &Path, algorithm: Algorithm) -> Result<hash::Digest> {
+        hash::hash_file(self.filesystem, self.path(relative), algorithm).map_err(Into::into)
+    }
+
+    /// `relative`のディレクトリの下階のエンティティをすべて`change`で報告します。
+    fn list(&self, relative: &Path, change: fn(PathBuf, Type) -> Change, changes: &mut Vec<Change>) -> Result<()> {
+        for (name, r#type) in self.children(relative)? {
+            let relative = relative.join(name);
+            changes.push(change(relative.clone(), r#type.clone()));
+            if r#type == Type::Dir {
+                self.list(&relative, change, changes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn modified<A: Source, B: Source>(old: &Side<A>, new: &Side<B>, relative: &Path, options: &Options) -> Result<bool> {
+    let (old_metadata, new_metadata) = (old.metadata(relative)?, new.metadata(relative)?);
+    if old_metadata.size() != new_metadata.size() {
+        return Ok(true);
+    }
+    match options.compare {
+        Compare::Size => Ok(false),
+        Compare::Mtime => match (old_metadata.modified(), new_metadata.modified()) {
+            (Some(old), Some(new)) => Ok(old != new),
+            _ => Ok(true),
+        },
+        Compare::Content(algorithm) => Ok(old.hash(relative, algorithm)? != new.hash(relative, algorithm)?),
+    }
+}
+
+fn diff_dir<A: Source, B: Source>(
+    old: &Side<A>,
+    new: &Side<B>,
+    relative: &Path,
+    options: &Options,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let mut old_children = old.children(relative)?;
+    let new_children = new.children(relative)?;
+
+    let mut names = old_children.keys().cloned().collect::<Vec<_>>();
+    names.extend(
+        new_children
+            .keys()
+            .filter(|name| !old_children.contains_key(*name))
+            .cloned(),
+    );
+    names.sort();
+
+    for name in names {
+        let relative = relative.join(&name);
+        match (old_children.remove(&name), new_children.get(&name).cloned()) {
+            (Some(from), None) => {
+                changes.push(Change::Removed(relative.clone(), from.clone()));
+                if from == Type::Dir {
+                    old.list(&relative, Change::Removed, changes)?;
+                }
+            },
+            (None, Some(to)) => {
+                changes.push(Change::Added(relative.clone(), to.clone()));
+                if to == Type::Dir {
+                    new.list(&relative, Change::Added, changes)?;
+                }
+            },
+            (Some(Type::File), Some(Type::File)) => {
+                if modified(old, new, &relative, options)? {
+                    changes.push(Change::Modified(relative));
+                }
+            },
+            (Some(Type::Dir), Some(Type::Dir)) => diff_dir(old, new, &relative, options, changes)?,
+            (Some(from), Some(to)) => {
+                changes.push(Change::TypeChanged {
+                    path: relative.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+                if from == Type::Dir {
+                    old.list(&relative, Change::Removed, changes)?;
+                } else {
+                    new.list(&relative, Change::Added, changes)?;
+                }
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tree_diff {
+    use ::{
+        filesystem_provider_api::{
+            fs::{entity::Type, ops},
+            provider::make::Make as _,
+        },
+        std::{io::Write as _, path::PathBuf},
+    };
+
+    use crate::{
+        diff::{self, Change, Compare, Options},
+        hash::Algorithm,
+    };
+
+    #[test]
+    fn disk_to_cas() -> Result<(), Box<dyn std::error::Error>> {
+        let old = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(old.join("gone").join("deep"))?;
+        std::fs::create_dir(old.join("kept"))?;
+        std::fs::write(old.join("gone").join("deep").join("x.txt"), b"x")?;
+        std::fs::write(old.join("kept").join("same.txt"), b"same")?;
+        std::fs::write(old.join("kept").join("resized.txt"), b"short")?;
+        std::fs::write(old.join("kept").join("edited.txt"), b"aaaa")?;
+        std::fs::write(old.join("swap"), b"file")?;
+        let old = filesystem_provider_impl_disk::provider::Provider::make(old.to_path_buf());
+
+        let temp = mktemp::Temp::new_dir()?;
+        let new = filesystem_provider_impl_cas::provider::Provider::make(temp.to_path_buf());
+        ops::CreateDir::create(&new, "kept")?;
+        ops::CreateDir::create(&new, "swap")?;
+        ops::CreateDir::create(&new, "added")?;
+        let write = |path: &str, contents: &[u8]| -> Result<(), Box<dyn std::error::Error>> {
+            let mut file = ops::CreateFile::create(&new, path)?;
+            file.write_all(contents)?;
+            file.flush().map_err(Into::into)
+        };
+        write("kept/same.txt", b"same")?;
+        write("kept/resized.txt", b"longer")?;
+        write("kept/edited.txt", b"bbbb")?;
+        write("swap/inner.txt", b"inner")?;
+        write("added/y.txt", b"y")?;
+
+        let path = |p: &str| p.split('/').collect::<PathBuf>();
+        let mut expected = vec![
+            Change::Added(path("added"), Type::Dir),
+            Change::Added(path("added/y.txt"), Type::File),
+            Change::Removed(path("gone"), Type::Dir),
+            Change::Removed(path("gone/deep"), Type::Dir),
+            Change::Removed(path("gone/deep/x.txt"), Type::File),
+            Change::Modified(path("kept/resized.txt")),
+            Change::TypeChanged {
+                path: path("swap"),
+                from: Type::File,
+                to: Type::Dir,
+            },
+            Change::Added(path("swap/inner.txt"), Type::File),
+        ];
+        assert_eq!(diff::diff(&old, ".", &new, ".", &Options::default())?, expected);
+
+        let options = Options {
+            compare: Compare::Content(Algorithm::Blake3),
+        };
+        expected.insert(5, Change::Modified(path("kept/edited.txt")));
+        assert_eq!(diff::diff(&old, ".", &new, ".", &options)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn identical() -> Result<(), Box<dyn std::error::Error>> {
+        let filesystem = filesystem_provider_impl_disk::provider::Provider::make(PathBuf::from("."));
+        let options = Options {
+            compare: Compare::Content(Algorithm::Sha256),
+        };
+        assert!(diff::diff(&filesystem, "src", &filesystem, "src", &options)?.is_empty());
+
+        let options = Options {
+            compare: Compare::Mtime,
+        };
+        assert!(diff::diff(&filesystem, "src", &filesystem, "src", &options)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn older_mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let (old, new) = (mktemp::Temp::new_dir()?, mktemp::Temp::new_dir()?);
+        std::fs::write(old.join("a.txt"), b"a")?;
+        std::fs::write(new.join("a.txt"), b"b")?;
+        let past = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(new.join("a.txt"))?
+            .set_modified(past)?;
+
+        let options = Options {
+            compare: Compare::Mtime,
+        };
+        assert_eq!(
+            diff::diff(
+                &filesystem_provider_impl_disk::provider::Provider::make(old.to_path_buf()),
+                ".",
+                &filesystem_provider_impl_disk::provider::Provider::make(new.to_path_buf()),
+                ".",
+                &options
+            )?,
+            vec![Change::Modified(PathBuf::from("a.txt"))]
+        );
+        Ok(())
+    }
+}
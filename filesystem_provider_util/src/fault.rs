@@ -0,0 +1,520 @@
+//! 操作を意図的に失敗させるファイルシステムのラッパー。エラー処理のテストに使います。
+//!
+//! [FaultyFileSystem]は呼び出しごとに[Rule]を順に調べ、最初に発動したルールの[Fault]を注入します。
+//! 注入されたエラーは[io::Error]から包んだファイルシステムのエラーに変換されるので、
+//! 例えばディスクのバックエンドでは`IoError`の列挙子として現れます。
+//!
+//! 確率で発動するルールはシードから作った疑似乱数を使うので、同じシードと同じ呼び出しの列に対して常に同じ結果になります。
+//!
+//! ```
+//! use ::{
+//!     filesystem_provider_api::{fs::ops, provider::make::Make as _},
+//!     filesystem_provider_impl_disk::provider::Provider,
+//!     filesystem_provider_util::fault::{Fault, FaultyFileSystem, Operation, Rule},
+//!     std::io::{self, Write as _},
+//! };
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let temp = mktemp::Temp::new_dir()?;
+//! let filesystem = FaultyFileSystem::new(Provider::make(temp.to_path_buf()), 0)
+//!     .rule(Rule::new(Fault::Error(io::ErrorKind::StorageFull)).operation(Operation::Write).nth(2));
+//!
+//! let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+//! file.write_all(b"first")?;
+//! assert_eq!(file.write_all(b"second").unwrap_err().kind(), io::ErrorKind::StorageFull);
+//! # Ok(())
+//! # }
+//! ```
+
+use ::{
+    filesystem_provider_api::fs::{entity, ops, FileSystem, Introspect},
+    std::{
+        io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, MutexGuard, PoisonError},
+        time::Duration,
+    },
+};
+
+use crate::normalize;
+
+/// 障害を注入できる操作です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Operation {
+    Metadata,
+    OpenFile,
+    OpenDir,
+    CreateFile,
+    ReplaceFile,
+    CreateDir,
+    RemoveFile,
+    RemoveDir,
+    /// 返されたファイルからの読み込みです。
+    Read,
+    /// 返されたファイルへの書き込みです。
+    Write,
+    /// 返されたファイルの[entity::AtomicFile::commit]です。
+    Commit,
+}
+
+/// 注入する障害です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Fault {
+    /// この種類のエラーで失敗させます。
+    Error(io::ErrorKind),
+    /// このOSのエラー番号（`ENOSPC`など）で失敗させます。
+    Os(i32),
+    /// 読み書きを最大でこのバイト数に切り詰めます。読み書き以外の操作には影響しません。
+    Partial(usize),
+    /// 操作の前にこの時間だけ待ちます。
+    Delay(Duration),
+}
+
+impl Fault {
+    /// 待つ障害は待ち、失敗させる障害は返すべきエラーを返します。
+    fn apply(&self) -> Option<io::Error> {
+        match *self {
+            Fault::Error(kind) => Some(io::Error::new(kind, "injected fault")),
+            Fault::Os(code) => Some(io::Error::from_raw_os_error(code)),
+            Fault::Partial(_) => None,
+            Fault::Delay(duration) => {
+                std::thread::sleep(duration);
+                None
+            },
+        }
+    }
+}
+
+/// ルールが発動する条件です。
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Trigger {
+    /// 一致するたびに発動します。
+    #[default]
+    Always,
+    /// 一致したn回目（1から数えます）だけ発動します。
+    Nth(usize),
+    /// 一致したn回目以降のすべてで発動します。
+    After(usize),
+    /// 一致するたびにこの確率で発動します。
+    Probability(f64),
+}
+
+/// 操作とパスが一致した時に障害を注入するルールです。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    fault: Fault,
+    operation: Option<Operation>,
+    path: Option<PathBuf>,
+    trigger: Trigger,
+}
+
+impl Rule {
+    /// すべての操作とパスに一致し、常に発動するルールを作ります。
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            fault,
+            operation: None,
+            path: None,
+            trigger: Trigger::Always,
+        }
+    }
+
+    /// `operation`だけに一致させます。
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// `path`とその下階だけに一致させます。`path`は基底パスからの相対パスです。
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(normalize(path.as_ref()));
+        self
+    }
+
+    pub fn trigger(mut self, trigger: Trigger) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    /// `self.trigger(Trigger::Nth(n))`と同じです。
+    pub fn nth(self, n: usize) -> Self {
+        self.trigger(Trigger::Nth(n))
+    }
+
+    fn matches(&self, operation: Operation, path: &Path) -> bool {
+        self.operation.is_none_or(|op| op == operation) && self.path.as_ref().is_none_or(|p| path.starts_with(p))
+    }
+}
+
+/// シードから決まる疑似乱数（xorshift64*）です。
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // 0は不動点なので、splitmix64で散らしてから使う。
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    /// `[0, 1)`の一様な値を返します。
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug)]
+struct Script {
+    rules: Vec<(Rule, usize)>,
+    rng: Rng,
+}
+
+impl Script {
+    /// 一致するルールの回数を数え、最初に発動したルールの障害を返します。
+    fn check(&mut self, operation: Operation, path: &Path) -> Option<Fault> {
+        let mut fired = None;
+        for (rule, count) in &mut self.rules {
+            if !rule.matches(operation, path) {
+                continue;
+            }
+            *count += 1;
+            let fire = match rule.trigger {
+                Trigger::Always => true,
+                Trigger::Nth(n) => *count == n,
+                Trigger::After(n) => *count >= n,
+                Trigger::Probability(p) => self.rng.next_f64() < p,
+            };
+            if fire && fired.is_none() {
+                fired = Some(rule.fault);
+            }
+        }
+        fired
+    }
+}
+
+type Shared = Arc<Mutex<Script>>;
+
+fn check(script: &Shared, operation: Operation, path: &Path) -> Option<Fault> {
+    script
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .check(operation, path)
+}
+
+/// ファイルシステムを包んで障害を注入します。
+#[derive(Debug)]
+pub struct FaultyFileSystem<F> {
+    inner: F,
+    script: Shared,
+}
+
+impl<F> FaultyFileSystem<F> {
+    /// ルールを持たないラッパーを作ります。`seed`は[Trigger::Probability]の判定に使います。
+    pub fn new(inner: F, seed: u64) -> Self {
+        Self {
+            inner,
+            script: Arc::new(Mutex::new(Script {
+                rules: Vec::new(),
+                rng: Rng::new(seed),
+            })),
+        }
+    }
+
+    /// ルールを最後に加えます。
+    pub fn rule(self, rule: Rule) -> Self {
+        self.push(rule);
+        self
+    }
+
+    /// ルールを最後に加えます。既に返されたファイルにも適用されます。
+    pub fn push(&self, rule: Rule) {
+        self.script().rules.push((rule, 0));
+    }
+
+    /// すべてのルールを取り除きます。
+    pub fn clear(&self) {
+        self.script().rules.clear();
+    }
+
+    forward!(accessors: F);
+
+    fn script(&self) -> MutexGuard<'_, Script> {
+        self.script.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 障害を注入してから`f`を呼び出します。
+    fn inject<T, E: From<io::Error>>(
+        &self,
+        operation: Operation,
+        path: &Path,
+        f: impl FnOnce(&F, &Path) -> Result<T, E>,
+    ) -> Result<T, E> {
+        if let Some(err) = check(&self.script, operation, &normalize(path)).and_then(|fault| fault.apply()) {
+            return Err(err.into());
+        }
+        f(&self.inner, path)
+    }
+
+    fn file<T>(&self, inner: T, path: &Path) -> File<T> {
+        File {
+            inner,
+            path: normalize(path),
+            script: self.script.clone(),
+        }
+    }
+}
+
+/// [FaultyFileSystem]が返すファイルです。読み書きとコミットに障害を注入します。
+#[derive(Debug)]
+pub struct File<T> {
+    inner: T,
+    path: PathBuf,
+    script: Shared,
+}
+
+impl<T> File<T> {
+    /// 障害を注入し、読み書きできるバイト数を返します。
+    fn inject(&self, operation: Operation, len: usize) -> io::Result<usize> {
+        match check(&self.script, operation, &self.path) {
+            Some(Fault::Partial(n)) => Ok(len.min(n)),
+            Some(fault) => fault.apply().map_or(Ok(len), Err),
+            None => Ok(len),
+        }
+    }
+}
+
+impl<T: io::Read> io::Read for File<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inject(Operation::Read, buf.len())?;
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl<T: io::Write> io::Write for File<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inject(Operation::Write, buf.len())?;
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: io::Seek> io::Seek for File<T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<T: entity::File> entity::File for File<T> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+}
+
+impl<T: entity::AtomicFile<E: From<io::Error>>> entity::AtomicFile for File<T> {
+    type E = T::E;
+
+    fn commit(self) -> Result<(), Self::E> {
+        self.inject(Operation::Commit, 0)?;
+        self.inner.commit()
+    }
+}
+
+impl<F: Introspect> Introspect for FaultyFileSystem<F> {
+    forward!(introspect);
+}
+
+impl<F: FileSystem<MetadataE: From<io::Error>>> FileSystem for FaultyFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.inject(Operation::Metadata, sub.as_ref(), |inner, sub| inner.metadata(sub))
+    }
+
+    forward!(queries);
+}
+
+impl<F: ops::OpenFile<E: From<io::Error>>> ops::OpenFile for FaultyFileSystem<F> {
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let file = self.inject(Operation::OpenFile, path, |inner, path| inner.open(path))?;
+        Ok(self.file(file, path))
+    }
+}
+
+impl<F: ops::OpenDir<E: From<io::Error>>> ops::OpenDir for FaultyFileSystem<F> {
+    type Dir = F::Dir;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inject(Operation::OpenDir, path.as_ref(), |inner, path| inner.open(path))
+    }
+}
+
+impl<F: ops::CreateFile<E: From<io::Error>>> ops::CreateFile for FaultyFileSystem<F> {
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let file = self.inject(Operation::CreateFile, path, |inner, path| inner.create(path))?;
+        Ok(self.file(file, path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let file = self.inject(Operation::CreateFile, path, |inner, path| inner.create_new(path))?;
+        Ok(self.file(file, path))
+    }
+}
+
+impl<F> ops::ReplaceFile for FaultyFileSystem<F>
+where
+    F: ops::ReplaceFile<E: From<io::Error>, File: entity::AtomicFile<E: From<io::Error>>>,
+{
+    type E = F::E;
+    type File = File<F::File>;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let file = self.inject(Operation::ReplaceFile, path, |inner, path| inner.replace(path))?;
+        Ok(self.file(file, path))
+    }
+}
+
+impl<F: ops::CreateDir<E: From<io::Error>>> ops::CreateDir for FaultyFileSystem<F> {
+    type Dir = F::Dir;
+    type E = F::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inject(Operation::CreateDir, path.as_ref(), |inner, path| inner.create(path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inject(Operation::CreateDir, path.as_ref(), |inner, path| {
+            inner.create_new(path)
+        })
+    }
+}
+
+impl<F: ops::RemoveFile<E: From<io::Error>>> ops::RemoveFile for FaultyFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.inject(Operation::RemoveFile, path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+impl<F: ops::RemoveDir<E: From<io::Error>>> ops::RemoveDir for FaultyFileSystem<F> {
+    type E = F::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.inject(Operation::RemoveDir, path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+#[cfg(test)]
+mod injection {
+    use ::{
+        filesystem_provider_api::{fs::ops, provider::make::Make as _},
+        filesystem_provider_impl_disk::{
+            fs::{CreateEntityError, OpenEntityError},
+            provider::Provider,
+        },
+        std::io::{self, Read as _, Write as _},
+    };
+
+    use crate::fault::{Fault, FaultyFileSystem, Operation, Rule, Trigger};
+
+    #[test]
+    fn operations() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("a.txt"), b"hello")?;
+        let filesystem = FaultyFileSystem::new(Provider::make(temp.to_path_buf()), 0)
+            .rule(
+                Rule::new(Fault::Error(io::ErrorKind::StorageFull))
+                    .operation(Operation::CreateFile)
+                    .path("full"),
+            )
+            .rule(
+                Rule::new(Fault::Error(io::ErrorKind::Other))
+                    .operation(Operation::Read)
+                    .nth(2),
+            );
+
+        assert!(ops::CreateFile::create(&filesystem, "b.txt").is_ok());
+        match ops::CreateFile::create(&filesystem, "./full/b.txt") {
+            Err(CreateEntityError::IoError(err)) => assert_eq!(err.kind(), io::ErrorKind::StorageFull),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let mut file = ops::OpenFile::open(&filesystem, "a.txt")?;
+        let mut buf = [0; 2];
+        file.read_exact(&mut buf)?;
+        assert!(file.read_exact(&mut buf).is_err());
+        file.read_exact(&mut buf)?;
+
+        filesystem.clear();
+        filesystem.push(Rule::new(Fault::Error(io::ErrorKind::NotFound)).operation(Operation::OpenFile));
+        assert!(matches!(
+            ops::OpenFile::open(&filesystem, "a.txt"),
+            Err(OpenEntityError::IoError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_writes() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let filesystem = FaultyFileSystem::new(Provider::make(temp.to_path_buf()), 0)
+            .rule(Rule::new(Fault::Partial(3)).operation(Operation::Write));
+
+        let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+        assert_eq!(file.write(b"abcdefgh")?, 3);
+        file.write_all(b"defgh")?;
+        drop(file);
+        assert_eq!(std::fs::read(temp.join("a.txt"))?, b"abcdefgh");
+        Ok(())
+    }
+
+    #[test]
+    fn seeded() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let pattern = |seed| -> Result<Vec<bool>, Box<dyn std::error::Error>> {
+            let filesystem = FaultyFileSystem::new(Provider::make(temp.to_path_buf()), seed).rule(
+                Rule::new(Fault::Error(io::ErrorKind::Other))
+                    .operation(Operation::Write)
+                    .trigger(Trigger::Probability(0.5)),
+            );
+            let mut file = ops::CreateFile::create(&filesystem, "a.txt")?;
+            Ok((0..64).map(|_| file.write(b"x").is_ok()).collect())
+        };
+
+        let (a, b) = (pattern(42)?, pattern(42)?);
+        assert_eq!(a, b);
+        assert!(a.contains(&true) && a.contains(&false));
+        assert_ne!(a, pattern(7)?);
+        Ok(())
+    }
+}
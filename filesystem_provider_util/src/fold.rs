@@ -0,0 +1,317 @@
+//! 大文字と小文字の違いやUnicodeの正規化の違いを無視してパスを解決するファイルシステムのラッパー。
+//!
+//! macOSやWindowsで作られたアセットは、大文字と小文字を区別しないファイルシステムや、
+//! NFDで名前を保存するファイルシステムを前提にしている場合があります。
+//! [FoldingFileSystem]はパスの各コンポーネントを[Options]に従って畳み込み、親ディレクトリの中で畳み込んだ名前が一致するエントリに解決します。
+//!
+//! - 名前が完全に一致するエントリがあれば、それを使います。
+//! - 無ければ親ディレクトリを列挙し、畳み込んだ名前が一致するエントリを使います。複数ある場合は名前の順で最初のものです。
+//! - それも無ければ、[Options::nfc]が`true`の場合はNFCにした名前を、そうでなければ与えられた名前を使います。
+//!
+//! [ops::CreateFile::create_new]と[ops::CreateDir::create_new]は、畳み込んだ名前が一致するエントリがある場合に
+//! [io::ErrorKind::AlreadyExists]で失敗します。
+//!
+//! 大文字と小文字の畳み込みは[str::to_lowercase]で行います。UTF-8でない名前は畳み込みません。
+
+use ::{
+    filesystem_provider_api::fs::{
+        entity::{self, Dir as _, DirEntry as _},
+        ops, FileSystem, Introspect,
+    },
+    std::{
+        ffi::{OsStr, OsString},
+        io,
+        path::{Component, Path, PathBuf},
+    },
+    unicode_normalization::UnicodeNormalization as _,
+};
+
+/// パスの解決の方法です。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Options {
+    /// `true`の場合、大文字と小文字を区別しません。
+    pub case_insensitive: bool,
+    /// `true`の場合、名前をNFCにしてから比較します。
+    pub nfc: bool,
+}
+
+impl Options {
+    /// 比較に使う名前です。
+    fn key(&self, name: &OsStr) -> OsString {
+        match name.to_str() {
+            Some(name) => {
+                let name = match self.nfc {
+                    true => name.nfc().collect::<String>(),
+                    false => name.to_owned(),
+                };
+                match self.case_insensitive {
+                    true => name.to_lowercase().into(),
+                    false => name.into(),
+                }
+            },
+            None => name.to_owned(),
+        }
+    }
+
+    /// 新しく作るエントリの名前です。
+    fn stored(&self, name: &OsStr) -> OsString {
+        match (self.nfc, name.to_str()) {
+            (true, Some(name)) => name.nfc().collect::<String>().into(),
+            _ => name.to_owned(),
+        }
+    }
+}
+
+/// ファイルシステムを包んでパスを畳み込んで解決します。
+#[derive(Debug)]
+pub struct FoldingFileSystem<F> {
+    inner: F,
+    options: Options,
+}
+
+impl<F> FoldingFileSystem<F> {
+    pub fn new(inner: F, options: Options) -> Self {
+        Self { inner, options }
+    }
+
+    forward!(accessors: F);
+}
+
+impl<F: FileSystem + ops::OpenDir> FoldingFileSystem<F> {
+    /// `dir`の中で畳み込んだ名前が`name`と一致するエントリの名前を返します。
+    fn find(&self, dir: &Path, name: &OsStr) -> Option<OsString> {
+        let key = self.options.key(name);
+        let dir = ops::OpenDir::open(
+            &self.inner,
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            },
+        )
+        .ok()?;
+        dir.entries()
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name())
+            .filter(|candidate| self.options.key(candidate) == key)
+            .min()
+    }
+
+    /// `path`を包んだファイルシステムでのパスに解決します。
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut resolved = PathBuf::new();
+        // 基底パスの外に出た後は包んだファイルシステムに任せる。
+        let mut outside = false;
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) if !outside => {
+                    let exact = resolved.join(name);
+                    if self.inner.exists(&exact) {
+                        resolved = exact;
+                    } else {
+                        let name = self.find(&resolved, name).unwrap_or_else(|| self.options.stored(name));
+                        resolved.push(name);
+                    }
+                },
+                Component::CurDir => (),
+                Component::ParentDir if !outside && resolved.pop() => (),
+                component => {
+                    outside = true;
+                    resolved.push(component);
+                },
+            }
+        }
+        if resolved.as_os_str().is_empty() {
+            resolved.push(Component::CurDir);
+        }
+        resolved
+    }
+
+    /// `path`に新しいエントリを作れるか調べ、作るパスを返します。
+    fn resolve_new<E: From<io::Error>>(&self, path: &Path) -> Result<PathBuf, E> {
+        let resolved = self.resolve(path);
+        if let (Some(parent), Some(name)) = (resolved.parent(), resolved.file_name()) {
+            if self.find(parent, name).is_some() {
+                let message = format!("{:?} collides with an existing entry", path);
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+impl<F: Introspect> Introspect for FoldingFileSystem<F> {
+    forward!(introspect);
+}
+
+impl<F: FileSystem + ops::OpenDir> FileSystem for FoldingFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.inner.metadata(self.resolve(sub))
+    }
+
+    fn exists<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.inner.exists(self.resolve(path))
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.inner.is_file(self.resolve(path))
+    }
+
+    fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool
+    where
+        Self: Sized,
+    {
+        self.inner.is_dir(self.resolve(path))
+    }
+}
+
+impl<F: FileSystem + ops::OpenDir + ops::OpenFile> ops::OpenFile for FoldingFileSystem<F> {
+    type E = <F as ops::OpenFile>::E;
+    type File = <F as ops::OpenFile>::File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        ops::OpenFile::open(&self.inner, self.resolve(path))
+    }
+}
+
+impl<F: FileSystem + ops::OpenDir> ops::OpenDir for FoldingFileSystem<F> {
+    type Dir = F::Dir;
+    type E = <F as ops::OpenDir>::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        ops::OpenDir::open(&self.inner, self.resolve(path))
+    }
+}
+
+impl<F> ops::CreateFile for FoldingFileSystem<F>
+where
+    F: FileSystem + ops::OpenDir + ops::CreateFile<E: From<io::Error>>,
+{
+    type E = <F as ops::CreateFile>::E;
+    type File = <F as ops::CreateFile>::File;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        ops::CreateFile::create(&self.inner, self.resolve(path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        ops::CreateFile::create_new(&self.inner, self.resolve_new::<Self::E>(path.as_ref())?)
+    }
+}
+
+impl<F: FileSystem + ops::OpenDir + ops::ReplaceFile> ops::ReplaceFile for FoldingFileSystem<F> {
+    type E = <F as ops::ReplaceFile>::E;
+    type File = <F as ops::ReplaceFile>::File;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        ops::ReplaceFile::replace(&self.inner, self.resolve(path))
+    }
+}
+
+impl<F> ops::CreateDir for FoldingFileSystem<F>
+where
+    F: FileSystem + ops::OpenDir + ops::CreateDir<E: From<io::Error>>,
+{
+    type Dir = <F as ops::CreateDir>::Dir;
+    type E = <F as ops::CreateDir>::E;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        ops::CreateDir::create(&self.inner, self.resolve(path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        ops::CreateDir::create_new(&self.inner, self.resolve_new::<Self::E>(path.as_ref())?)
+    }
+}
+
+impl<F: FileSystem + ops::OpenDir + ops::RemoveFile> ops::RemoveFile for FoldingFileSystem<F> {
+    type E = <F as ops::RemoveFile>::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        ops::RemoveFile::remove(&self.inner, self.resolve(path))
+    }
+}
+
+impl<F: FileSystem + ops::OpenDir + ops::RemoveDir> ops::RemoveDir for FoldingFileSystem<F> {
+    type E = <F as ops::RemoveDir>::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        ops::RemoveDir::remove(&self.inner, self.resolve(path))
+    }
+}
+
+#[cfg(test)]
+mod folding {
+    use ::{
+        filesystem_provider_api::{
+            fs::{ops, FileSystem as _},
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::{fs::CreateEntityError, provider::Provider},
+        std::{io, path::Path},
+    };
+
+    use crate::fold::{FoldingFileSystem, Options};
+
+    #[test]
+    fn case_insensitive() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("Assets"))?;
+        std::fs::write(temp.join("Assets").join("Logo.PNG"), b"png")?;
+        let options = Options {
+            case_insensitive: true,
+            nfc: false,
+        };
+        let filesystem = FoldingFileSystem::new(Provider::make(temp.to_path_buf()), options);
+
+        assert!(filesystem.is_file("assets/logo.png"));
+        assert!(filesystem.is_dir("./ASSETS/../assets"));
+        assert_eq!(filesystem.resolve("assets/logo.png"), Path::new("Assets/Logo.PNG"));
+        assert!(ops::OpenFile::open(&filesystem, "ASSETS/logo.png").is_ok());
+
+        match ops::CreateFile::create_new(&filesystem, "assets/LOGO.png") {
+            Err(CreateEntityError::IoError(err)) => assert_eq!(err.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(ops::CreateDir::create_new(&filesystem, "ASSETS").is_err());
+
+        ops::CreateFile::create_new(&filesystem, "assets/Icon.png")?;
+        assert!(temp.join("Assets").join("Icon.png").is_file());
+        ops::RemoveFile::remove(&filesystem, "ASSETS/ICON.PNG")?;
+        assert!(!temp.join("Assets").join("Icon.png").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn nfc() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let (nfc, nfd) = ("caf\u{e9}.txt", "cafe\u{301}.txt");
+        std::fs::write(temp.join(nfd), b"decomposed")?;
+        let options = Options {
+            case_insensitive: false,
+            nfc: true,
+        };
+        let filesystem = FoldingFileSystem::new(Provider::make(temp.to_path_buf()), options);
+
+        assert!(filesystem.is_file(nfc));
+        assert_eq!(filesystem.resolve(nfc), Path::new(nfd));
+        assert!(ops::CreateFile::create_new(&filesystem, nfc).is_err());
+        assert!(!filesystem.exists("CAF\u{c9}.txt"));
+
+        ops::CreateFile::create_new(&filesystem, "nai\u{308}ve.txt")?;
+        assert!(temp.join("na\u{ef}ve.txt").is_file());
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! 内容を固定長のフレームに分けて独立に変換する形式の、読み書きのハンドル。
+//!
+//! [crate::crypt]と[crate::compress]はフレームの変換だけを[Decode]と[Encode]で実装し、
+//! シークやフレームのキャッシュ、書き込みのバッファリングと終了はここで共通に扱います。
+
+use ::{filesystem_provider_api::fs::entity, std::io};
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 保存された形式からフレームを読んで元に戻します。
+pub trait Decode {
+    /// 元に戻したフレームの大きさです。最後のフレームだけはこれより短い場合があります。
+    fn frame_len(&self) -> u64;
+
+    /// `index`番目のフレームを読んで元に戻します。
+    fn decode(&mut self, index: u64) -> io::Result<Vec<u8>>;
+}
+
+/// フレームを変換して書き込みます。
+pub trait Encode {
+    /// `frame`を変換して`inner`に書き込みます。
+    ///
+    /// `last`の場合は形式の終わりまで書き込みます。最後のフレームは空の場合があります。
+    fn encode<W: io::Write>(&mut self, inner: &mut W, frame: &[u8], last: bool) -> io::Result<()>;
+}
+
+/// フレームに分けて保存されたファイルを読むハンドルです。
+#[derive(Debug)]
+pub struct File<D> {
+    frames: D,
+    size: u64,
+    position: u64,
+    /// 最後に元に戻したフレームの番号と内容です。
+    frame: Option<(u64, Vec<u8>)>,
+}
+
+impl<D> File<D> {
+    /// `size`は元に戻した内容の大きさです。
+    pub(crate) fn new(frames: D, size: u64) -> Self {
+        Self {
+            frames,
+            size,
+            position: 0,
+            frame: None,
+        }
+    }
+}
+
+impl<D: Decode> File<D> {
+    fn load(&mut self, index: u64) -> io::Result<&[u8]> {
+        if self.frame.as_ref().is_none_or(|(loaded, _)| *loaded != index) {
+            let frame = self.frames.decode(index)?;
+            if frame.len() as u64 > self.frames.frame_len() {
+                return Err(invalid_data("frame is longer than expected"));
+            }
+            self.frame = Some((index, frame));
+        }
+        Ok(&self.frame.as_ref().unwrap().1)
+    }
+}
+
+impl<D: Decode> io::Read for File<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let frame_len = self.frames.frame_len();
+        let index = self.position / frame_len;
+        let offset = (self.position % frame_len) as usize;
+        let frame = self.load(index)?;
+        let n = buf.len().min(frame.len().saturating_sub(offset));
+        if n == 0 {
+            return Err(invalid_data("frame is shorter than expected"));
+        }
+        buf[..n].copy_from_slice(&frame[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<D> io::Seek for File<D> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
+
+impl<D> entity::File for File<D> {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+/// フレームに分けて変換しながら書き込むハンドルです。書き込みは先頭から順に行います。
+///
+/// 最後のフレームと形式の終わりは破棄するか[Writer::finish]を呼び出した時に書き込まれます。
+/// [io::Write::flush]は完成したフレームだけを書き込みます。
+///
+/// フレームの書き込みに失敗すると包んだファイルには途中までの内容が残るので、以降の書き込みはすべて失敗します。
+#[derive(Debug)]
+pub struct Writer<W: io::Write, C: Encode> {
+    inner: Option<W>,
+    frames: C,
+    frame_len: usize,
+    buffer: Vec<u8>,
+    written: u64,
+    /// フレームの書き込みに失敗したかどうかです。
+    failed: bool,
+}
+
+fn failed() -> io::Error {
+    io::Error::other("file is broken by a previous failed write")
+}
+
+impl<W: io::Write, C: Encode> Writer<W, C> {
+    /// `inner`にはヘッダーを書き込み済みでなければなりません。
+    pub(crate) fn new(inner: W, frames: C, frame_len: usize) -> Self {
+        Self {
+            inner: Some(inner),
+            frames,
+            frame_len,
+            buffer: Vec::with_capacity(frame_len),
+            written: 0,
+            failed: false,
+        }
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        if self.failed {
+            return Err(failed());
+        }
+        let inner = self.inner.as_mut().unwrap();
+        let mut result = self.frames.encode(inner, &self.buffer, last);
+        if last && result.is_ok() {
+            result = inner.flush();
+        }
+        self.failed = result.is_err();
+        result?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// 最後のフレームを書き込んで、包んだファイルを返します。
+    fn close(mut self) -> io::Result<W> {
+        let result = self.seal(true);
+        let inner = self.inner.take().unwrap();
+        result.map(|_| inner)
+    }
+
+    /// 最後のフレームと形式の終わりを書き込みます。破棄した時とは異なり、失敗を報告します。
+    pub fn finish(self) -> io::Result<()> {
+        self.close().map(|_| ())
+    }
+}
+
+impl<W: io::Write, C: Encode> Drop for Writer<W, C> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && !self.failed {
+            let _ = self.seal(true);
+        }
+    }
+}
+
+impl<W: io::Write, C: Encode> io::Write for Writer<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(failed());
+        }
+        let mut written = 0;
+        while written < buf.len() {
+            // 満杯のフレームが最後とは限らないので、次の書き込みが来てから変換する。
+            if self.buffer.len() == self.frame_len {
+                self.seal(false)?;
+            }
+            let n = (self.frame_len - self.buffer.len()).min(buf.len() - written);
+            self.buffer.extend_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.failed {
+            return Err(failed());
+        }
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: io::Write, C: Encode> entity::File for Writer<W, C> {
+    fn size(&self) -> u64 {
+        self.written
+    }
+
+    fn is_file(&self) -> bool {
+        true
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+impl<W: io::Write + entity::AtomicFile<E: From<io::Error>>, C: Encode> entity::AtomicFile for Writer<W, C> {
+    type E = W::E;
+
+    fn commit(self) -> Result<(), Self::E> {
+        self.close()?.commit()
+    }
+}
@@ -0,0 +1,516 @@
+//! ファイルやディレクトリツリーの内容のハッシュを計算し、マニフェストと照合するモジュール。
+//!
+//! ディレクトリツリーのハッシュはMerkle木として計算されます。
+//! ディレクトリのハッシュは子エンティティをファイル名のバイト列の順に並べ、それぞれの種類、ファイル名、ハッシュを連結したもののハッシュです。
+//! したがって、ディレクトリを読む順序に依らず同じ内容のツリーは同じハッシュになります。
+//!
+//! ファイル名はプラットフォームのエンコーディングのままのバイト列でハッシュに含めます。
+//!
+//! ファイルでもディレクトリでもないエンティティは無視されます。
+//! シンボリックリンクの循環を辿り続けないように、[MAX_DEPTH]より深いディレクトリは[Error::TooDeepError]で失敗します。
+
+use ::{
+    filesystem_provider_api::fs::{
+        entity::{Dir as _, DirEntry as _, Type},
+        ops,
+    },
+    sha2::Digest as _,
+    std::{
+        collections::BTreeMap,
+        ffi::{OsStr, OsString},
+        fmt, io,
+        path::{Path, PathBuf},
+    },
+};
+
+use crate::{BoxError, Source};
+
+/// 辿るディレクトリの深さの上限です。
+pub const MAX_DEPTH: usize = 256;
+
+/// ハッシュ関数。
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            },
+        }
+    }
+
+    fn finalize(self) -> Digest {
+        match self {
+            Hasher::Sha256(hasher) => Digest(hasher.finalize().to_vec()),
+            Hasher::Blake3(hasher) => Digest(hasher.finalize().as_bytes().to_vec()),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// ハッシュ値。[fmt::Display]は小文字の16進数です。
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Digest(Vec<u8>);
+
+impl Digest {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// 16進数の文字列からハッシュ値を作ります。
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Digest)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to open {0:?}: {1}")]
+    OpenError(PathBuf, #[source] BoxError),
+    #[error("failed to read {0:?}: {1}")]
+    ReadError(PathBuf, #[source] io::Error),
+    #[error("failed to read entries of {0:?}: {1}")]
+    EntriesError(PathBuf, #[source] BoxError),
+    #[error("too deep, possibly a symlink cycle {0:?}")]
+    TooDeepError(PathBuf),
+}
+
+pub type Result<T> = std::result::Result<T, self::Error>;
+
+/// `path`のファイルのハッシュを計算します。
+/// 引数`path`はファイルシステムの基底パスを基準としたサブパスと見なされます。
+pub fn hash_file<F, P>(filesystem: &F, path: P, algorithm: Algorithm) -> Result<Digest>
+where
+    F: ops::OpenFile,
+    F::File: io::Read,
+    F::E: Into<BoxError>,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut file = filesystem
+        .open(path)
+        .map_err(|err| Error::OpenError(path.to_path_buf(), err.into()))?;
+
+    let mut hasher = Hasher::new(algorithm);
+    io::copy(&mut file, &mut hasher).map_err(|err| Error::ReadError(path.to_path_buf(), err))?;
+    Ok(hasher.finalize())
+}
+
+/// `path`のディレクトリツリーのハッシュを計算します。
+/// 引数`path`はファイルシステムの基底パスを基準としたサブパスと見なされます。
+pub fn hash_tree<F, P>(filesystem: &F, path: P, algorithm: Algorithm) -> Result<Digest>
+where
+    F: Source,
+    P: AsRef<Path>,
+{
+    walk(filesystem, path.as_ref(), Path::new(""), algorithm, &mut |_, _| ())
+}
+
+/// ディレクトリツリーを辿り、各ファイルのハッシュを`visit`に渡しながらディレクトリのハッシュを計算します。
+/// `visit`には`path`を基準とした相対パスが渡されます。
+pub(crate) fn walk<F, V>(
+    filesystem: &F,
+    path: &Path,
+    relative: &Path,
+    algorithm: Algorithm,
+    visit: &mut V,
+) -> Result<Digest>
+where
+    F: Source,
+    V: FnMut(&Path, &Digest),
+{
+    if relative.components().count() > MAX_DEPTH {
+        return Err(Error::TooDeepError(path.to_path_buf()));
+    }
+    let dir = ops::OpenDir::open(filesystem, path).map_err(|err| Error::OpenError(path.to_path_buf(), err.into()))?;
+    let entries_error = |err: BoxError| Error::EntriesError(path.to_path_buf(), err);
+
+    let mut children = BTreeMap::new();
+    for entry in dir.entries().map_err(|err| entries_error(err.into()))? {
+        let entry = entry.map_err(|err| entries_error(err.into()))?;
+        if let Some(r#type) = entry.file_type() {
+            children.insert(entry.file_name(), (entry.path(), r#type));
+        }
+    }
+
+    let mut hasher = Hasher::new(algorithm);
+    for (name, (path, r#type)) in children {
+        let relative = relative.join(&name);
+        let (tag, digest) = match r#type {
+            Type::File => {
+                let digest = hash_file(filesystem, &path, algorithm)?;
+                visit(&relative, &digest);
+                (b'f', digest)
+            },
+            Type::Dir => (b'd', walk(filesystem, &path, &relative, algorithm, visit)?),
+        };
+        let name = name.as_encoded_bytes();
+        hasher.update(&[tag]);
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(digest.as_bytes());
+    }
+    Ok(hasher.finalize())
+}
+
+/// ディレクトリツリー内の各ファイルのハッシュの一覧です。パスはツリーのルートを基準とした相対パスです。
+///
+/// [fmt::Display]は`sha256sum`などと同じ`<ハッシュ>  <パス>`形式の行です。パスの区切りは常に`/`です。
+/// ファイル名のバイト列を失わないように、`\`は`\\`に、改行文字とUTF-8として不正なバイトは`\xNN`に置き換えます。
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Manifest {
+    algorithm: Algorithm,
+    entries: BTreeMap<PathBuf, Digest>,
+}
+
+impl Manifest {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// `path`のディレクトリツリーを辿ってマニフェストを作ります。
+    /// 引数`path`はファイルシステムの基底パスを基準としたサブパスと見なされます。
+    pub fn build<F, P>(filesystem: &F, path: P, algorithm: Algorithm) -> Result<Self>
+    where
+        F: Source,
+        P: AsRef<Path>,
+    {
+        let mut manifest = Self::new(algorithm);
+        walk(
+            filesystem,
+            path.as_ref(),
+            Path::new(""),
+            algorithm,
+            &mut |path, digest| {
+                manifest.entries.insert(path.to_path_buf(), digest.clone());
+            },
+        )?;
+        Ok(manifest)
+    }
+
+    /// `<ハッシュ>  <パス>`形式の行からマニフェストを作ります。空行は無視されます。
+    pub fn parse(algorithm: Algorithm, text: &str) -> std::result::Result<Self, ParseError> {
+        let mut manifest = Self::new(algorithm);
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+            let error = || ParseError(number + 1);
+            let (hex, path) = line.split_once("  ").ok_or_else(error)?;
+            let digest = Digest::from_hex(hex).ok_or_else(error)?;
+            let path = path
+                .split('/')
+                .map(unescape)
+                .collect::<Option<PathBuf>>()
+                .ok_or_else(error)?;
+            manifest.entries.insert(path, digest);
+        }
+        Ok(manifest)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn insert(&mut self, path: PathBuf, digest: Digest) -> Option<Digest> {
+        self.entries.insert(path, digest)
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Digest> {
+        self.entries.get(path.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Digest)> {
+        self.entries.iter().map(|(path, digest)| (path.as_path(), digest))
+    }
+
+    /// `path`のディレクトリツリーをこのマニフェストと照合します。
+    /// 引数`path`はファイルシステムの基底パスを基準としたサブパスと見なされます。
+    pub fn verify<F, P>(&self, filesystem: &F, path: P) -> Result<Report>
+    where
+        F: Source,
+        P: AsRef<Path>,
+    {
+        let actual = Self::build(filesystem, path, self.algorithm)?;
+
+        let mut report = Report::default();
+        for (path, digest) in &self.entries {
+            match actual.entries.get(path) {
+                None => report.missing.push(path.clone()),
+                Some(actual) if actual != digest => report.modified.push(path.clone()),
+                Some(_) => (),
+            }
+        }
+        report.extra = actual
+            .entries
+            .into_keys()
+            .filter(|path| !self.entries.contains_key(path))
+            .collect();
+        Ok(report)
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, digest) in &self.entries {
+            let path = path.iter().map(escape).collect::<Vec<_>>();
+            writeln!(f, "{}  {}", digest, path.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// [Manifest]の行に書くためにファイル名をエスケープします。
+fn escape(name: &OsStr) -> String {
+    use fmt::Write as _;
+
+    let mut escaped = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' | '\r' => write!(escaped, "\\x{:02x}", c as u8).unwrap(),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            write!(escaped, "\\x{:02x}", byte).unwrap();
+        }
+    }
+    escaped
+}
+
+/// [escape]を元に戻します。不正なエスケープの場合は`None`を返します。
+fn unescape(name: &str) -> Option<OsString> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut iter = name.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match iter.next()? {
+            b'\\' => bytes.push(b'\\'),
+            b'x' => {
+                let hex = [iter.next()?, iter.next()?];
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            _ => return None,
+        }
+    }
+    match String::from_utf8(bytes) {
+        Ok(name) => Some(name.into()),
+        #[cfg(unix)]
+        Err(err) => Some(std::os::unix::ffi::OsStringExt::from_vec(err.into_bytes())),
+        #[cfg(not(unix))]
+        Err(_) => None,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("malformed manifest line {0}")]
+pub struct ParseError(usize);
+
+/// [Manifest::verify]の結果。各パスはツリーのルートを基準とした相対パスで、辞書順に並んでいます。
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Report {
+    /// マニフェストにあるがツリーに無いファイル。
+    pub missing: Vec<PathBuf>,
+    /// ツリーにあるがマニフェストに無いファイル。
+    pub extra: Vec<PathBuf>,
+    /// ハッシュが一致しないファイル。
+    pub modified: Vec<PathBuf>,
+}
+
+impl Report {
+    /// ツリーがマニフェストと完全に一致する場合は`true`を返します。
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.modified.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod digest {
+    use ::{
+        filesystem_provider_api::provider::make::Make as _, filesystem_provider_impl_disk::provider::Provider,
+        std::path::PathBuf,
+    };
+
+    use crate::hash::{self, Algorithm, Manifest};
+
+    fn tree() -> Result<mktemp::Temp, Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(temp.join("a").join("b"))?;
+        std::fs::write(temp.join("abc.txt"), b"abc")?;
+        std::fs::write(temp.join("a").join("x.txt"), b"x")?;
+        std::fs::write(temp.join("a").join("b").join("y.txt"), b"y")?;
+        Ok(temp)
+    }
+
+    #[test]
+    fn hash_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tree()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        assert_eq!(
+            hash::hash_file(&filesystem, "abc.txt", Algorithm::Sha256)?.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash::hash_file(&filesystem, "abc.txt", Algorithm::Blake3)?.to_string(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hash_tree() -> Result<(), Box<dyn std::error::Error>> {
+        let first = tree()?;
+        let second = tree()?;
+
+        let digest = hash::hash_tree(&Provider::make(first.to_path_buf()), ".", Algorithm::Blake3)?;
+        assert_eq!(
+            digest,
+            hash::hash_tree(&Provider::make(second.to_path_buf()), ".", Algorithm::Blake3)?
+        );
+
+        std::fs::write(second.join("a").join("b").join("y.txt"), b"z")?;
+        assert_ne!(
+            digest,
+            hash::hash_tree(&Provider::make(second.to_path_buf()), ".", Algorithm::Blake3)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tree()?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let manifest = Manifest::build(&filesystem, ".", Algorithm::Sha256)?;
+        assert!(manifest.verify(&filesystem, ".")?.is_ok());
+        assert_eq!(Manifest::parse(Algorithm::Sha256, &manifest.to_string())?, manifest);
+
+        std::fs::remove_file(temp.join("abc.txt"))?;
+        std::fs::write(temp.join("a").join("x.txt"), b"changed")?;
+        std::fs::write(temp.join("a").join("b").join("new.txt"), b"new")?;
+
+        let report = manifest.verify(&filesystem, ".")?;
+        assert_eq!(report.missing, vec![PathBuf::from("abc.txt")]);
+        assert_eq!(report.modified, vec![PathBuf::from("a").join("x.txt")]);
+        assert_eq!(report.extra, vec![PathBuf::from("a").join("b").join("new.txt")]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_unicode_name() -> Result<(), Box<dyn std::error::Error>> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+        // 置換文字に置き換えると同じ名前になる二つのツリー。
+        let (first, second) = (mktemp::Temp::new_dir()?, mktemp::Temp::new_dir()?);
+        std::fs::write(first.join(OsStr::from_bytes(b"\xfe")), b"x")?;
+        std::fs::write(second.join(OsStr::from_bytes(b"\xff")), b"x")?;
+
+        assert_ne!(
+            hash::hash_tree(&Provider::make(first.to_path_buf()), ".", Algorithm::Sha256)?,
+            hash::hash_tree(&Provider::make(second.to_path_buf()), ".", Algorithm::Sha256)?
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_unicode_manifest() -> Result<(), Box<dyn std::error::Error>> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join(OsStr::from_bytes(b"d\xfe")))?;
+        std::fs::write(temp.join(OsStr::from_bytes(b"d\xfe")).join("a\\x"), b"x")?;
+        std::fs::write(temp.join("new\nline"), b"y")?;
+        let filesystem = Provider::make(temp.to_path_buf());
+
+        let manifest = Manifest::build(&filesystem, ".", Algorithm::Sha256)?;
+        let text = manifest.to_string();
+        assert!(text.contains("d\\xfe/a\\\\x"));
+        assert!(text.contains("new\\x0aline"));
+        assert_eq!(Manifest::parse(Algorithm::Sha256, &text)?, manifest);
+        assert!(Manifest::parse(Algorithm::Sha256, &text)?
+            .verify(&filesystem, ".")?
+            .is_ok());
+
+        let digest = hash::hash_file(&filesystem, "new\nline", Algorithm::Sha256)?;
+        assert!(Manifest::parse(Algorithm::Sha256, &format!("{}  a\\q", digest)).is_err());
+        assert!(Manifest::parse(Algorithm::Sha256, &format!("{}  a\\x4", digest)).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycle() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tree()?;
+        std::os::unix::fs::symlink("..", temp.join("a").join("up"))?;
+
+        assert!(hash::hash_tree(&Provider::make(temp.to_path_buf()), ".", Algorithm::Sha256).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn too_deep() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let deep = (0..=hash::MAX_DEPTH).fold(temp.to_path_buf(), |path, _| path.join("d"));
+        std::fs::create_dir_all(&deep)?;
+
+        assert!(matches!(
+            hash::hash_tree(&Provider::make(temp.to_path_buf()), ".", Algorithm::Sha256),
+            Err(hash::Error::TooDeepError(_))
+        ));
+        std::fs::remove_dir(deep)?;
+        assert!(hash::hash_tree(&Provider::make(temp.to_path_buf()), ".", Algorithm::Sha256).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! [filesystem_provider_api]のトレイトだけを使って実装された、バックエンドに依存しない機能を提供するクレート。
+//!
+//! ファイルシステムがディスク上にあってもアーカイブであっても同じ様に利用できます。
+//!
+//! - [hash] ファイルやディレクトリツリーのハッシュとマニフェストによる照合
+//! - [diff] 二つのディレクトリツリーの差分
+//! - [sync] ディレクトリツリーの同期
+//!
+//! 任意のファイルシステムを包んで振る舞いを加えるラッパーもあります。
+//! ラッパーは包んだファイルシステムが実装するトレイトだけを実装します。
+//!
+//! - [audit] 操作の記録
+//! - [cache] 読み込みのキャッシュ
+//! - [compress] ファイルの圧縮
+//! - [crypt] 内容と名前の暗号化
+//! - [fault] テストのための障害の注入
+//! - [fold] 大文字と小文字やUnicodeの正規化の違いを無視したパスの解決
+//! - [protect] 書き込みを許すサブツリー以外の保護
+//! - [quota] 容量とエンティティの数の制限
+
+/// ラッパーのメソッドを`inner`フィールドの同じメソッドへ転送する実装を生成します。
+///
+/// - `forward!(introspect)`は[Introspect](filesystem_provider_api::fs::Introspect)のすべての能力を、
+///   `forward!(introspect: is_readable, ...)`は指定した能力だけを転送します。
+/// - `forward!(queries)`は[FileSystem::exists]、[FileSystem::is_file]、[FileSystem::is_dir]を転送します。
+/// - `forward!(accessors: F)`は`inner`と`into_inner`を、`forward!(inner: F)`は`inner`だけを生成します。
+macro_rules! forward {
+    (introspect) => {
+        forward!(introspect: is_readable, is_writable, is_appendable, is_truncatable, is_removable);
+    };
+    (introspect: $($method:ident),+ $(,)?) => {
+        $(
+            fn $method(&self) -> bool {
+                self.inner.$method()
+            }
+        )+
+    };
+    (queries) => {
+        forward!(query: exists, is_file, is_dir);
+    };
+    (query: $($method:ident),+) => {
+        $(
+            fn $method<P: AsRef<::std::path::Path>>(&self, path: P) -> bool
+            where
+                Self: Sized,
+            {
+                self.inner.$method(path)
+            }
+        )+
+    };
+    (accessors: $inner:ty) => {
+        forward!(inner: $inner);
+
+        pub fn into_inner(self) -> $inner {
+            self.inner
+        }
+    };
+    (inner: $inner:ty) => {
+        pub fn inner(&self) -> &$inner {
+            &self.inner
+        }
+    };
+}
+
+pub mod audit;
+pub mod cache;
+pub mod compress;
+pub mod crypt;
+pub mod diff;
+pub mod fault;
+pub mod fold;
+mod frame;
+pub mod hash;
+pub mod protect;
+pub mod quota;
+pub mod sync;
+
+use ::{
+    filesystem_provider_api::fs::{entity, ops, FileSystem},
+    std::{
+        io,
+        path::{Component, Path, PathBuf},
+    },
+};
+
+/// バックエンドごとに異なるエラーを保持するための型です。
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// ツリーを読むのに必要なトレイトをまとめたものです。エラーはすべて[BoxError]に変換できなければなりません。
+///
+/// これらを実装するファイルシステムには自動的に実装されます。
+pub trait Source:
+    FileSystem<MetadataE: Into<BoxError>>
+    + ops::OpenFile<File: io::Read, E: Into<BoxError>>
+    + ops::OpenDir<E: Into<BoxError>, Dir: entity::Dir<EntriesE: Into<BoxError>, IterE: Into<BoxError>>>
+{
+}
+
+impl<F> Source for F where
+    F: FileSystem<MetadataE: Into<BoxError>>
+        + ops::OpenFile<File: io::Read, E: Into<BoxError>>
+        + ops::OpenDir<E: Into<BoxError>, Dir: entity::Dir<EntriesE: Into<BoxError>, IterE: Into<BoxError>>>
+{
+}
+
+/// ツリーを変更するのに必要なトレイトをまとめたものです。エラーはすべて[BoxError]に変換できなければなりません。
+///
+/// これらを実装するファイルシステムには自動的に実装されます。
+pub trait Sink:
+    ops::CreateFile<File: io::Write, E: Into<BoxError>>
+    + ops::CreateDir<E: Into<BoxError>>
+    + ops::RemoveFile<E: Into<BoxError>>
+    + ops::RemoveDir<E: Into<BoxError>>
+{
+}
+
+impl<F> Sink for F where
+    F: ops::CreateFile<File: io::Write, E: Into<BoxError>>
+        + ops::CreateDir<E: Into<BoxError>>
+        + ops::RemoveFile<E: Into<BoxError>>
+        + ops::RemoveDir<E: Into<BoxError>>
+{
+}
+
+/// [entity::DirAt]を実装せずにディレクトリを包みます。
+///
+/// ラッパーは変更をファイルシステムのメソッドで受け取って処理するので、ハンドルを基準にした変更でそれを迂回されないように使います。
+#[derive(Debug)]
+pub struct PlainDir<D>(D);
+
+impl<D> PlainDir<D> {
+    pub(crate) fn new(inner: D) -> Self {
+        Self(inner)
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+impl<D: entity::File> entity::File for PlainDir<D> {
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+}
+
+impl<D: entity::Dir> entity::Dir for PlainDir<D> {
+    type Entry = D::Entry;
+    type IterE = D::IterE;
+    type Entries = D::Entries;
+    type EntriesE = D::EntriesE;
+
+    fn total_size(&self) -> u64 {
+        self.0.total_size()
+    }
+
+    fn count(&self) -> usize {
+        self.0.count()
+    }
+
+    fn entries(&self) -> Result<Self::Entries, Self::EntriesE> {
+        self.0.entries()
+    }
+}
+
+impl<D: entity::Durable> entity::Durable for PlainDir<D> {
+    type E = D::E;
+
+    fn sync_all(&self) -> Result<(), Self::E> {
+        self.0.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<(), Self::E> {
+        self.0.sync_data()
+    }
+}
+
+/// パスから`.`を取り除きます。`..`は解決しません。
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|comp| *comp != Component::CurDir).collect()
+}
+
+/// `path`の`.`と`..`を字句的に解決します。基底パスの外を指す場合は`None`を返します。
+pub(crate) fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            },
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
@@ -0,0 +1,390 @@
+//! ファイルシステムを読み込み専用にするラッパー。書き込みを許すサブツリーを指定できます。
+//!
+//! プロバイダーの能力とは異なり、既にあるファイルシステムを実行時に保護します。
+//! [ProtectedFileSystem]は読み込みをそのまま通し、許されたサブツリーの外への変更を[Error::PermissionError]で拒否します。
+//!
+//! パスは`..`を字句的に解決してから判定するので、`target/../src`は`src`と見なされます。
+//! シンボリックリンクは解決しないので、許されたサブツリーの中から外を指すリンクを通した変更は防げません。
+//! 返されたディレクトリの[entity::DirAt]による変更も、ディレクトリのサブパスに名前を連結したパスで判定します。
+
+use ::{
+    filesystem_provider_api::fs::{entity, ops, FileSystem, Introspect},
+    std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+use crate::resolve;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+    #[error("write protected {0:?}")]
+    PermissionError(PathBuf),
+    #[error(transparent)]
+    InnerError(E),
+}
+
+/// `path`が`writable`のいずれかのサブツリーに含まれるか調べます。
+fn is_writable(writable: &[PathBuf], path: &Path) -> bool {
+    resolve(path).is_some_and(|path| writable.iter().any(|allowed| path.starts_with(allowed)))
+}
+
+/// `path`への変更が許されている場合だけ`f`を呼び出します。
+fn guard<T, E>(writable: &[PathBuf], path: &Path, f: impl FnOnce() -> Result<T, E>) -> Result<T, Error<E>> {
+    if !is_writable(writable, path) {
+        return Err(Error::PermissionError(path.to_path_buf()));
+    }
+    f().map_err(Error::InnerError)
+}
+
+/// ファイルシステムを包んで変更を制限します。
+#[derive(Debug)]
+pub struct ProtectedFileSystem<F> {
+    inner: F,
+    writable: Arc<Vec<PathBuf>>,
+}
+
+impl<F> ProtectedFileSystem<F> {
+    /// すべての変更を拒否するラッパーを作ります。
+    pub fn read_only(inner: F) -> Self {
+        Self {
+            inner,
+            writable: Arc::new(Vec::new()),
+        }
+    }
+
+    /// `path`とその下階への変更を許します。`path`は基底パスからの相対パスです。
+    ///
+    /// # Panics
+    ///
+    /// `path`が基底パスの外を指す場合。
+    pub fn allow<P: AsRef<Path>>(mut self, path: P) -> Self {
+        let path = path.as_ref();
+        let resolved = resolve(path).unwrap_or_else(|| panic!("{:?} is out of the root", path));
+        Arc::make_mut(&mut self.writable).push(resolved);
+        self
+    }
+
+    /// `path`への変更が許されているか調べます。
+    pub fn is_writable_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        is_writable(&self.writable, path.as_ref())
+    }
+
+    forward!(accessors: F);
+
+    fn guard<T, E>(&self, path: &Path, f: impl FnOnce(&F, &Path) -> Result<T, E>) -> Result<T, Error<E>> {
+        guard(&self.writable, path, || f(&self.inner, path))
+    }
+
+    fn dir<D>(&self, inner: D, path: &Path) -> Dir<D> {
+        Dir {
+            inner,
+            sub: path.to_path_buf(),
+            writable: self.writable.clone(),
+        }
+    }
+}
+
+/// 子エンティティへの変更を[ProtectedFileSystem]と同じ様に制限するディレクトリです。
+#[derive(Debug)]
+pub struct Dir<D> {
+    inner: D,
+    /// 基底パスからのこのディレクトリのパスです。
+    sub: PathBuf,
+    writable: Arc<Vec<PathBuf>>,
+}
+
+impl<D> Dir<D> {
+    forward!(accessors: D);
+
+    fn guard<T, E>(&self, name: &Path, f: impl FnOnce(&D) -> Result<T, E>) -> Result<T, Error<E>> {
+        guard(&self.writable, &self.sub.join(name), || f(&self.inner))
+    }
+
+    fn child<C>(&self, inner: C, name: &Path) -> Dir<C> {
+        Dir {
+            inner,
+            sub: self.sub.join(name),
+            writable: self.writable.clone(),
+        }
+    }
+}
+
+impl<D: entity::File> entity::File for Dir<D> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+}
+
+impl<D: entity::Dir> entity::Dir for Dir<D> {
+    type Entry = D::Entry;
+    type IterE = D::IterE;
+    type Entries = D::Entries;
+    type EntriesE = D::EntriesE;
+
+    fn total_size(&self) -> u64 {
+        self.inner.total_size()
+    }
+
+    fn count(&self) -> usize {
+        self.inner.count()
+    }
+
+    fn entries(&self) -> Result<Self::Entries, Self::EntriesE> {
+        self.inner.entries()
+    }
+}
+
+impl<D: entity::Durable> entity::Durable for Dir<D> {
+    type E = D::E;
+
+    fn sync_all(&self) -> Result<(), Self::E> {
+        self.inner.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<(), Self::E> {
+        self.inner.sync_data()
+    }
+}
+
+impl<D: entity::DirAt> entity::DirAt for Dir<D> {
+    type File = D::File;
+    type E = Error<D::E>;
+
+    fn open_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        self.inner.open_file(name).map_err(Error::InnerError)
+    }
+
+    fn open_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E> {
+        let name = name.as_ref();
+        let inner = self.inner.open_dir(name).map_err(Error::InnerError)?;
+        Ok(self.child(inner, name))
+    }
+
+    fn create_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        let name = name.as_ref();
+        self.guard(name, |inner| inner.create_file(name))
+    }
+
+    fn create_new_file<P: AsRef<Path>>(&self, name: P) -> Result<Self::File, Self::E> {
+        let name = name.as_ref();
+        self.guard(name, |inner| inner.create_new_file(name))
+    }
+
+    fn create_dir<P: AsRef<Path>>(&self, name: P) -> Result<Self, Self::E> {
+        let name = name.as_ref();
+        let inner = self.guard(name, |inner| inner.create_dir(name))?;
+        Ok(self.child(inner, name))
+    }
+
+    fn remove_file<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E> {
+        let name = name.as_ref();
+        self.guard(name, |inner| inner.remove_file(name))
+    }
+
+    fn remove_dir<P: AsRef<Path>>(&self, name: P) -> Result<(), Self::E> {
+        let name = name.as_ref();
+        self.guard(name, |inner| inner.remove_dir(name))
+    }
+
+    fn metadata<P: AsRef<Path>>(&self, name: P) -> Result<entity::Metadata, Self::E> {
+        self.inner.metadata(name).map_err(Error::InnerError)
+    }
+}
+
+/// 書き込みに関する能力は、包んだファイルシステムが持ち、かつ書き込みを許すサブツリーが一つ以上ある場合に`true`です。
+/// パスごとの判定には[ProtectedFileSystem::is_writable_path]を使ってください。
+impl<F: Introspect> Introspect for ProtectedFileSystem<F> {
+    forward!(introspect: is_readable);
+
+    fn is_writable(&self) -> bool {
+        !self.writable.is_empty() && self.inner.is_writable()
+    }
+
+    fn is_appendable(&self) -> bool {
+        !self.writable.is_empty() && self.inner.is_appendable()
+    }
+
+    fn is_truncatable(&self) -> bool {
+        !self.writable.is_empty() && self.inner.is_truncatable()
+    }
+
+    fn is_removable(&self) -> bool {
+        !self.writable.is_empty() && self.inner.is_removable()
+    }
+}
+
+impl<F: FileSystem> FileSystem for ProtectedFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.inner.metadata(sub)
+    }
+
+    forward!(queries);
+}
+
+impl<F: ops::OpenFile> ops::OpenFile for ProtectedFileSystem<F> {
+    type E = F::E;
+    type File = F::File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.inner.open(path)
+    }
+}
+
+impl<F: ops::OpenDir> ops::OpenDir for ProtectedFileSystem<F> {
+    type Dir = Dir<F::Dir>;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        Ok(self.dir(self.inner.open(path)?, path))
+    }
+}
+
+impl<F: ops::CreateFile> ops::CreateFile for ProtectedFileSystem<F> {
+    type E = Error<F::E>;
+    type File = F::File;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.guard(path.as_ref(), |inner, path| inner.create(path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.guard(path.as_ref(), |inner, path| inner.create_new(path))
+    }
+}
+
+impl<F: ops::ReplaceFile> ops::ReplaceFile for ProtectedFileSystem<F> {
+    type E = Error<F::E>;
+    type File = F::File;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.guard(path.as_ref(), |inner, path| inner.replace(path))
+    }
+}
+
+impl<F: ops::CreateDir> ops::CreateDir for ProtectedFileSystem<F> {
+    type Dir = Dir<F::Dir>;
+    type E = Error<F::E>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        let dir = self.guard(path, |inner, path| inner.create(path))?;
+        Ok(self.dir(dir, path))
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        let dir = self.guard(path, |inner, path| inner.create_new(path))?;
+        Ok(self.dir(dir, path))
+    }
+}
+
+impl<F: ops::RemoveFile> ops::RemoveFile for ProtectedFileSystem<F> {
+    type E = Error<F::E>;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.guard(path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+impl<F: ops::RemoveDir> ops::RemoveDir for ProtectedFileSystem<F> {
+    type E = Error<F::E>;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        self.guard(path.as_ref(), |inner, path| inner.remove(path))
+    }
+}
+
+#[cfg(test)]
+mod allowlist {
+    use ::{
+        filesystem_provider_api::{
+            fs::{entity::DirAt as _, ops, FileSystem as _, Introspect as _},
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::provider::Provider,
+        std::io::Write as _,
+    };
+
+    use crate::protect::{Error, ProtectedFileSystem};
+
+    #[test]
+    fn writable_subtrees() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir(temp.join("src"))?;
+        std::fs::create_dir(temp.join("target"))?;
+        std::fs::write(temp.join("src").join("lib.rs"), b"")?;
+        let filesystem = ProtectedFileSystem::read_only(Provider::make(temp.to_path_buf()))
+            .allow("target")
+            .allow("./.cache");
+        assert!(filesystem.is_readable() && filesystem.is_writable());
+
+        ops::CreateFile::create(&filesystem, "target/out")?.write_all(b"built")?;
+        ops::CreateDir::create(&filesystem, ".cache")?;
+        ops::RemoveDir::remove(&filesystem, "target")?;
+
+        for path in ["src/lib.rs", "Cargo.toml", "target/../src/lib.rs", "targets/x"] {
+            assert!(matches!(
+                ops::CreateFile::create(&filesystem, path),
+                Err(Error::PermissionError(_))
+            ));
+        }
+        assert!(matches!(
+            ops::RemoveFile::remove(&filesystem, "src/lib.rs"),
+            Err(Error::PermissionError(_))
+        ));
+        assert!(filesystem.exists("src/lib.rs"));
+        assert!(ops::OpenFile::open(&filesystem, "src/lib.rs").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn dir_handle() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(temp.join("src").join("target"))?;
+        std::fs::write(temp.join("src").join("lib.rs"), b"")?;
+        let filesystem = ProtectedFileSystem::read_only(Provider::make(temp.to_path_buf())).allow("src/target");
+
+        let src = ops::OpenDir::open(&filesystem, "./src")?;
+        for result in [
+            src.create_file("main.rs").map(drop),
+            src.create_dir("bin").map(drop),
+            src.remove_file("lib.rs"),
+            src.create_file("target/../lib.rs").map(drop),
+        ] {
+            assert!(matches!(result, Err(Error::PermissionError(_))));
+        }
+        assert!(src.open_file("lib.rs").is_ok());
+        assert!(filesystem.exists("src/lib.rs") && !filesystem.exists("src/main.rs"));
+
+        src.create_file("target/out")?.write_all(b"built")?;
+        let target = src.open_dir("target")?;
+        target.create_dir("debug")?.create_new_file("out")?;
+        target.remove_file("out")?;
+        assert!(filesystem.exists("src/target/debug/out") && !filesystem.exists("src/target/out"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_only() {
+        let filesystem = ProtectedFileSystem::read_only(Provider::make(".".into()));
+        assert!(filesystem.is_readable());
+        assert!(!filesystem.is_writable() && !filesystem.is_removable());
+        assert!(!filesystem.is_writable_path("src"));
+    }
+}
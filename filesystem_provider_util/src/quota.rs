@@ -0,0 +1,526 @@
+//! 容量とエンティティの数を制限するファイルシステムのラッパー。
+//!
+//! [QuotaFileSystem]は作った時に基底パスの下を[entity::Dir::total_size]と[entity::Dir::count]で走査して使用量を求め、
+//! その後はラッパーを通した操作で使用量を更新します。
+//! 上限を超えるエンティティの作成と、返されたファイルへの書き込みは[Exceeded]で失敗します。
+//! ファイルへの書き込みが失敗する場合、[io::Error]の種類は[io::ErrorKind::QuotaExceeded]です。
+//!
+//! ディレクトリの容量は作った時点の大きさで数えます。上限の判定にはファイルの内容の大きさだけを使います。
+//! ラッパーを通さない変更は使用量に反映されないので、その場合は[QuotaFileSystem::rescan]を呼び出してください。
+//! 返されるディレクトリは[entity::DirAt]を実装しないので、ディレクトリの中の変更もラッパーのメソッドで行います。
+
+use ::{
+    filesystem_provider_api::fs::{
+        entity::{self, Dir as _, DirEntry as _, Type},
+        ops, FileSystem, Introspect,
+    },
+    std::{
+        io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, MutexGuard, PoisonError},
+    },
+};
+
+use crate::{BoxError, PlainDir, Source};
+
+/// 使用量の上限です。`None`の場合は制限しません。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// エンティティのドライブに占める容量の合計の上限（バイト）です。
+    pub bytes: Option<u64>,
+    /// 基底パスを含まないエンティティの数の上限です。
+    pub entities: Option<usize>,
+}
+
+/// 基底パスの下の使用量です。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub entities: usize,
+}
+
+/// 上限を超えたことを表すエラーです。値は超えた上限です。
+#[derive(Debug, Clone, Copy, Eq, PartialEq, thiserror::Error)]
+pub enum Exceeded {
+    #[error("byte quota of {0} exceeded")]
+    Bytes(u64),
+    #[error("entity quota of {0} exceeded")]
+    Entities(usize),
+}
+
+impl From<Exceeded> for io::Error {
+    fn from(exceeded: Exceeded) -> Self {
+        io::Error::new(io::ErrorKind::QuotaExceeded, exceeded)
+    }
+}
+
+/// [QuotaFileSystem]の操作のエラーです。
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+    #[error(transparent)]
+    QuotaError(#[from] Exceeded),
+    #[error(transparent)]
+    InnerError(E),
+}
+
+/// 使用量を求める走査が失敗した場合のエラーです。
+#[derive(Debug, thiserror::Error)]
+#[error("failed to scan {0:?}: {1}")]
+pub struct ScanError(PathBuf, #[source] BoxError);
+
+#[derive(Debug)]
+struct Account {
+    limits: Limits,
+    usage: Usage,
+}
+
+impl Account {
+    /// `bytes`と`entities`を使用量に加えます。上限を超える場合は何もせずに失敗します。
+    fn reserve(&mut self, bytes: u64, entities: usize) -> Result<(), Exceeded> {
+        let usage = Usage {
+            bytes: self.usage.bytes + bytes,
+            entities: self.usage.entities + entities,
+        };
+        match self.limits {
+            Limits { bytes: Some(limit), .. } if bytes > 0 && usage.bytes > limit => Err(Exceeded::Bytes(limit)),
+            Limits {
+                entities: Some(limit), ..
+            } if entities > 0 && usage.entities > limit => Err(Exceeded::Entities(limit)),
+            _ => {
+                self.usage = usage;
+                Ok(())
+            },
+        }
+    }
+
+    fn release(&mut self, bytes: u64, entities: usize) {
+        self.usage.bytes = self.usage.bytes.saturating_sub(bytes);
+        self.usage.entities = self.usage.entities.saturating_sub(entities);
+    }
+}
+
+type Shared = Arc<Mutex<Account>>;
+
+fn lock(account: &Shared) -> MutexGuard<'_, Account> {
+    account.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `path`のディレクトリの下階の使用量を求めます。`path`自身は含みません。
+fn scan<F: Source>(filesystem: &F, path: &Path) -> Result<Usage, ScanError> {
+    let error = |err: BoxError| ScanError(path.to_path_buf(), err);
+    let dir = ops::OpenDir::open(filesystem, path).map_err(|err| error(err.into()))?;
+    let mut usage = Usage {
+        bytes: dir.total_size(),
+        entities: dir.count(),
+    };
+    for entry in dir.entries().map_err(|err| error(err.into()))? {
+        let entry = entry.map_err(|err| error(err.into()))?;
+        if entry.file_type() == Some(Type::Dir) {
+            let sub = scan(filesystem, &path.join(entry.file_name()))?;
+            usage.bytes += sub.bytes;
+            usage.entities += sub.entities;
+        }
+    }
+    Ok(usage)
+}
+
+/// ファイルシステムを包んで使用量を制限します。
+#[derive(Debug)]
+pub struct QuotaFileSystem<F> {
+    inner: F,
+    account: Shared,
+}
+
+impl<F: Source> QuotaFileSystem<F> {
+    /// 基底パスの下を走査して使用量を求めます。既に上限を超えていても失敗しません。
+    pub fn new(inner: F, limits: Limits) -> Result<Self, ScanError> {
+        let usage = scan(&inner, Path::new("."))?;
+        Ok(Self {
+            inner,
+            account: Arc::new(Mutex::new(Account { limits, usage })),
+        })
+    }
+
+    /// 基底パスの下を走査し直して使用量を求めます。
+    pub fn rescan(&self) -> Result<Usage, ScanError> {
+        let usage = scan(&self.inner, Path::new("."))?;
+        lock(&self.account).usage = usage;
+        Ok(usage)
+    }
+}
+
+impl<F> QuotaFileSystem<F> {
+    forward!(accessors: F);
+
+    pub fn limits(&self) -> Limits {
+        lock(&self.account).limits
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        lock(&self.account).limits = limits;
+    }
+
+    pub fn usage(&self) -> Usage {
+        lock(&self.account).usage
+    }
+
+    fn reserve<E>(&self, bytes: u64, entities: usize) -> Result<(), Error<E>> {
+        lock(&self.account).reserve(bytes, entities).map_err(Error::QuotaError)
+    }
+
+    fn release(&self, bytes: u64, entities: usize) {
+        lock(&self.account).release(bytes, entities)
+    }
+
+    fn writer<W>(&self, inner: W, pending: Option<Pending>) -> Writer<W> {
+        Writer {
+            inner,
+            charge: Charge {
+                account: self.account.clone(),
+                position: 0,
+                len: 0,
+                pending,
+            },
+        }
+    }
+}
+
+impl<F: FileSystem> QuotaFileSystem<F> {
+    /// `path`にあるエンティティの種類と大きさです。
+    fn existing(&self, path: &Path) -> Option<(Type, u64)> {
+        self.inner
+            .metadata(path)
+            .ok()
+            .map(|metadata| (metadata.r#type().clone(), metadata.size()))
+    }
+}
+
+/// [ops::ReplaceFile]で置き換える前のエンティティの使用量です。
+#[derive(Debug)]
+struct Pending {
+    /// 置き換えられるファイルの大きさです。
+    replaced: u64,
+    /// 置き換えるファイルが無かったために予約したエンティティの数です。
+    entities: usize,
+}
+
+/// 書き込まれたファイルの大きさを使用量に計上します。
+#[derive(Debug)]
+struct Charge {
+    account: Shared,
+    position: u64,
+    len: u64,
+    /// コミットされていない置き換えです。コミットせずに破棄された場合、書き込んだ分を使用量から除きます。
+    pending: Option<Pending>,
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            lock(&self.account).release(self.len, pending.entities);
+        }
+    }
+}
+
+/// 書き込み用に開いたファイルです。ファイルを大きくする書き込みは上限を超える場合に失敗します。
+///
+/// 書き込みはこのハンドルの位置から行われると見なします。追記モードのファイルは包めません。
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+    charge: Charge,
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let charge = &mut self.charge;
+        let growth = (charge.position + buf.len() as u64).saturating_sub(charge.len);
+        lock(&charge.account).reserve(growth, 0)?;
+
+        let written = match self.inner.write(buf) {
+            Ok(written) => written,
+            Err(err) => {
+                lock(&charge.account).release(growth, 0);
+                return Err(err);
+            },
+        };
+        charge.position += written as u64;
+        let len = charge.len.max(charge.position);
+        lock(&charge.account).release(growth - (len - charge.len), 0);
+        charge.len = len;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: io::Seek> io::Seek for Writer<W> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.charge.position = self.inner.seek(pos)?;
+        Ok(self.charge.position)
+    }
+}
+
+impl<W: entity::File> entity::File for Writer<W> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+}
+
+impl<W: entity::AtomicFile> entity::AtomicFile for Writer<W> {
+    type E = W::E;
+
+    fn commit(self) -> Result<(), Self::E> {
+        let Writer { inner, mut charge } = self;
+        inner.commit()?;
+        if let Some(pending) = charge.pending.take() {
+            lock(&charge.account).release(pending.replaced, 0);
+        }
+        Ok(())
+    }
+}
+
+impl<F: Introspect> Introspect for QuotaFileSystem<F> {
+    forward!(introspect);
+}
+
+impl<F: FileSystem> FileSystem for QuotaFileSystem<F> {
+    type MetadataE = F::MetadataE;
+
+    fn metadata<P: AsRef<Path>>(&self, sub: P) -> Result<entity::Metadata, Self::MetadataE>
+    where
+        Self: Sized,
+    {
+        self.inner.metadata(sub)
+    }
+
+    forward!(queries);
+}
+
+impl<F: ops::OpenFile> ops::OpenFile for QuotaFileSystem<F> {
+    type E = F::E;
+    type File = F::File;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.inner.open(path)
+    }
+}
+
+impl<F: ops::OpenDir> ops::OpenDir for QuotaFileSystem<F> {
+    type Dir = PlainDir<F::Dir>;
+    type E = F::E;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        self.inner.open(path).map(PlainDir::new)
+    }
+}
+
+impl<F: FileSystem + ops::CreateFile> ops::CreateFile for QuotaFileSystem<F> {
+    type E = Error<<F as ops::CreateFile>::E>;
+    type File = Writer<<F as ops::CreateFile>::File>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let existing = self.existing(path);
+        let entities = if existing.is_some() { 0 } else { 1 };
+        self.reserve(0, entities)?;
+        match self.inner.create(path) {
+            Ok(file) => {
+                // 既存のファイルは切り詰められる。
+                self.release(existing.map_or(0, |(_, size)| size), 0);
+                Ok(self.writer(file, None))
+            },
+            Err(err) => {
+                self.release(0, entities);
+                Err(Error::InnerError(err))
+            },
+        }
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        self.reserve(0, 1)?;
+        match self.inner.create_new(path) {
+            Ok(file) => Ok(self.writer(file, None)),
+            Err(err) => {
+                self.release(0, 1);
+                Err(Error::InnerError(err))
+            },
+        }
+    }
+}
+
+impl<F: FileSystem + ops::ReplaceFile> ops::ReplaceFile for QuotaFileSystem<F> {
+    type E = Error<<F as ops::ReplaceFile>::E>;
+    type File = Writer<<F as ops::ReplaceFile>::File>;
+
+    fn replace<P: AsRef<Path>>(&self, path: P) -> Result<Self::File, Self::E> {
+        let path = path.as_ref();
+        let pending = match self.existing(path) {
+            Some((_, replaced)) => Pending { replaced, entities: 0 },
+            None => Pending {
+                replaced: 0,
+                entities: 1,
+            },
+        };
+        self.reserve(0, pending.entities)?;
+        match self.inner.replace(path) {
+            Ok(file) => Ok(self.writer(file, Some(pending))),
+            Err(err) => {
+                self.release(0, pending.entities);
+                Err(Error::InnerError(err))
+            },
+        }
+    }
+}
+
+impl<F: FileSystem + ops::CreateDir> ops::CreateDir for QuotaFileSystem<F> {
+    type Dir = PlainDir<<F as ops::CreateDir>::Dir>;
+    type E = Error<<F as ops::CreateDir>::E>;
+
+    fn create<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        if let Some((Type::Dir, _)) = self.existing(path) {
+            return self.inner.create(path).map(PlainDir::new).map_err(Error::InnerError);
+        }
+        self.create_new(path)
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<Self::Dir, Self::E> {
+        let path = path.as_ref();
+        self.reserve(0, 1)?;
+        match self.inner.create_new(path) {
+            Ok(dir) => {
+                let size = self.existing(path).map_or(0, |(_, size)| size);
+                lock(&self.account).usage.bytes += size;
+                Ok(PlainDir::new(dir))
+            },
+            Err(err) => {
+                self.release(0, 1);
+                Err(Error::InnerError(err))
+            },
+        }
+    }
+}
+
+impl<F: FileSystem + ops::RemoveFile> ops::RemoveFile for QuotaFileSystem<F> {
+    type E = <F as ops::RemoveFile>::E;
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        let path = path.as_ref();
+        let existing = self.existing(path);
+        self.inner.remove(path)?;
+        if let Some((_, size)) = existing {
+            self.release(size, 1);
+        }
+        Ok(())
+    }
+}
+
+impl<F: Source + ops::RemoveDir> ops::RemoveDir for QuotaFileSystem<F> {
+    type E = <F as ops::RemoveDir>::E;
+
+    /// 削除するディレクトリの下階の使用量は削除する前に走査して求めます。走査に失敗した場合は`path`自身の分だけを除きます。
+    fn remove<P: AsRef<Path>>(&self, path: P) -> Result<(), Self::E> {
+        let path = path.as_ref();
+        let existing = self.existing(path);
+        let contents = scan(&self.inner, path).unwrap_or_default();
+        self.inner.remove(path)?;
+        if let Some((_, size)) = existing {
+            self.release(size + contents.bytes, 1 + contents.entities);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod limits {
+    use ::{
+        filesystem_provider_api::{
+            fs::{entity::AtomicFile as _, ops, FileSystem as _},
+            provider::make::Make as _,
+        },
+        filesystem_provider_impl_disk::provider::Provider,
+        std::io::{self, Seek as _, Write as _},
+    };
+
+    use crate::quota::{Error, Exceeded, Limits, QuotaFileSystem, Usage};
+
+    #[test]
+    fn bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::write(temp.join("a.txt"), b"0123456789")?;
+        let limits = Limits {
+            bytes: Some(16),
+            entities: None,
+        };
+        let filesystem = QuotaFileSystem::new(Provider::make(temp.to_path_buf()), limits)?;
+        assert_eq!(filesystem.usage(), Usage { bytes: 10, entities: 1 });
+
+        let mut file = ops::CreateFile::create(&filesystem, "b.txt")?;
+        file.write_all(b"abcdef")?;
+        let err = file.write_all(b"g").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::QuotaExceeded);
+        file.seek(io::SeekFrom::Start(0))?;
+        file.write_all(b"ABCDEF")?;
+        drop(file);
+        assert_eq!(filesystem.usage(), Usage { bytes: 16, entities: 2 });
+        assert_eq!(std::fs::read(temp.join("b.txt"))?, b"ABCDEF");
+
+        ops::CreateFile::create(&filesystem, "a.txt")?.write_all(b"0123")?;
+        assert_eq!(filesystem.usage(), Usage { bytes: 10, entities: 2 });
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "b.txt")?;
+        file.write_all(b"xyz")?;
+        file.commit()?;
+        assert_eq!(filesystem.usage(), Usage { bytes: 7, entities: 2 });
+
+        let mut file = ops::ReplaceFile::replace(&filesystem, "c.txt")?;
+        file.write_all(b"temporary")?;
+        drop(file);
+        assert_eq!(filesystem.usage(), Usage { bytes: 7, entities: 2 });
+
+        ops::RemoveFile::remove(&filesystem, "a.txt")?;
+        assert_eq!(filesystem.usage(), Usage { bytes: 3, entities: 1 });
+        Ok(())
+    }
+
+    #[test]
+    fn entities() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        let limits = Limits {
+            bytes: None,
+            entities: Some(3),
+        };
+        let filesystem = QuotaFileSystem::new(Provider::make(temp.to_path_buf()), limits)?;
+
+        ops::CreateDir::create(&filesystem, "d")?;
+        ops::CreateDir::create(&filesystem, "d")?;
+        ops::CreateFile::create_new(&filesystem, "d/a.txt")?;
+        ops::CreateFile::create(&filesystem, "d/b.txt")?;
+        assert!(matches!(
+            ops::CreateFile::create_new(&filesystem, "d/c.txt"),
+            Err(Error::QuotaError(Exceeded::Entities(3)))
+        ));
+        assert!(matches!(
+            ops::CreateDir::create(&filesystem, "e"),
+            Err(Error::QuotaError(Exceeded::Entities(3)))
+        ));
+        assert!(!filesystem.exists("d/c.txt"));
+        assert_eq!(filesystem.usage().entities, 3);
+
+        ops::RemoveDir::remove(&filesystem, "d")?;
+        assert_eq!(filesystem.usage(), Usage::default());
+        assert_eq!(filesystem.rescan()?, Usage::default());
+        Ok(())
+    }
+}
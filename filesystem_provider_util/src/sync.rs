@@ -0,0 +1,287 @@
+//! 宛先のディレクトリツリーを送り元と同じ内容にするモジュール。
+//!
+//! 同期は[crate::diff]で宛先から送り元への差分を調べ、それを埋める[Action]を宛先に適用することで行います。
+//! 送り元と宛先のバックエンドは異なっていても構いません。例えば、アーカイブの中のリリースをディスクに展開できます。
+//!
+//! 宛先のファイルは[ops::CreateFile::create]で作り直されます。
+//! 最終更新日時を設定するトレイトは無く、コピーしたファイルは送り元と同じ最終更新日時にならないので、
+//! [Compare::Mtime]で比較する同期は[Error::CompareError]で失敗します。
+
+use ::{
+    filesystem_provider_api::fs::{entity::Type, ops},
+    std::{
+        io::{self, Write as _},
+        path::{Path, PathBuf},
+    },
+};
+
+pub use crate::diff::Compare;
+use crate::{
+    diff::{self, Change},
+    BoxError, Sink, Source,
+};
+
+/// 宛先に対する操作。パスは同期するツリーのルートを基準とした相対パスです。
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Action {
+    CreateDir(PathBuf),
+    /// 送り元のファイルを宛先にコピーします。宛先にファイルがある場合は置き換えます。
+    CopyFile(PathBuf),
+    RemoveFile(PathBuf),
+    /// ディレクトリをその中身ごと削除します。
+    RemoveDir(PathBuf),
+}
+
+impl Action {
+    pub fn path(&self) -> &Path {
+        match self {
+            Action::CreateDir(path) | Action::CopyFile(path) | Action::RemoveFile(path) | Action::RemoveDir(path) => {
+                path
+            },
+        }
+    }
+}
+
+/// 同期の方法です。
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Options {
+    pub compare: Compare,
+    /// `true`の場合、宛先を変更せずに行うはずの操作だけを返します。
+    pub dry_run: bool,
+    /// `true`の場合、送り元に無いエンティティを宛先から削除します。
+    ///
+    /// `false`でも、ファイルとディレクトリが入れ替わったエンティティは置き換えるために削除されます。
+    pub delete: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unsupported comparison {0:?}")]
+    CompareError(Compare),
+    #[error(transparent)]
+    DiffError(#[from] diff::Error),
+    #[error("failed to read {0:?}: {1}")]
+    ReadError(PathBuf, #[source] BoxError),
+    #[error("failed to write {0:?}: {1}")]
+    WriteError(PathBuf, #[source] BoxError),
+    #[error("failed to copy {0:?}: {1}")]
+    CopyError(PathBuf, #[source] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, self::Error>;
+
+/// `destination`の`destination_path`を`source`の`source_path`と同じ内容にし、行った操作を返します。
+/// 引数`source_path`と`destination_path`はそれぞれのファイルシステムの基底パスを基準としたサブパスと見なされます。
+///
+/// 操作は順に適用され、失敗した時点で中断します。
+/// `options.compare`が[Compare::Mtime]の場合は何もせずに失敗します。
+pub fn sync<S, D, P, Q>(
+    source: &S,
+    source_path: P,
+    destination: &D,
+    destination_path: Q,
+    options: &Options,
+) -> Result<Vec<Action>>
+where
+    S: Source,
+    D: Source + Sink,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    if options.compare == Compare::Mtime {
+        return Err(Error::CompareError(options.compare));
+    }
+    let (source_path, destination_path) = (source_path.as_ref(), destination_path.as_ref());
+    let diff_options = diff::Options {
+        compare: options.compare,
+    };
+    let changes = diff::diff(destination, destination_path, source, source_path, &diff_options)?;
+
+    let actions = plan(changes, options.delete);
+    if !options.dry_run {
+        for action in &actions {
+            apply(source, source_path, destination, destination_path, action)?;
+        }
+    }
+    Ok(actions)
+}
+
+/// 差分を埋める操作を返します。削除するディレクトリの下階の削除は含みません。
+fn plan(changes: Vec<Change>, delete: bool) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut removed: Option<PathBuf> = None;
+    for change in changes {
+        if let (Change::Removed(path, _), Some(dir)) = (&change, &removed) {
+            if path.starts_with(dir) {
+                continue;
+            }
+        }
+
+        match change {
+            Change::Added(path, Type::Dir) => actions.push(Action::CreateDir(path)),
+            Change::Added(path, Type::File) | Change::Modified(path) => actions.push(Action::CopyFile(path)),
+            Change::Removed(path, Type::Dir) if delete => {
+                removed = Some(path.clone());
+                actions.push(Action::RemoveDir(path));
+            },
+            Change::Removed(path, Type::File) if delete => actions.push(Action::RemoveFile(path)),
+            Change::Removed(..) => (),
+            Change::TypeChanged {
+                path, from: Type::Dir, ..
+            } => {
+                removed = Some(path.clone());
+                actions.push(Action::RemoveDir(path.clone()));
+                actions.push(Action::CopyFile(path));
+            },
+            Change::TypeChanged { path, .. } => {
+                actions.push(Action::RemoveFile(path.clone()));
+                actions.push(Action::CreateDir(path));
+            },
+        }
+    }
+    actions
+}
+
+fn apply<S, D>(source: &S, source_path: &Path, destination: &D, destination_path: &Path, action: &Action) -> Result<()>
+where
+    S: Source,
+    D: Source + Sink,
+{
+    let path = destination_path.join(action.path());
+    let write_error = |err: BoxError| Error::WriteError(path.clone(), err);
+    match action {
+        Action::CreateDir(_) => ops::CreateDir::create(destination, &path)
+            .map(drop)
+            .map_err(|err| write_error(err.into())),
+        Action::CopyFile(relative) => {
+            let from = source_path.join(relative);
+            let mut reader =
+                ops::OpenFile::open(source, &from).map_err(|err| Error::ReadError(from.clone(), err.into()))?;
+            let mut writer = ops::CreateFile::create(destination, &path).map_err(|err| write_error(err.into()))?;
+            io::copy(&mut reader, &mut writer)
+                .and_then(|_| writer.flush())
+                .map(drop)
+                .map_err(|err| Error::CopyError(path.clone(), err))
+        },
+        Action::RemoveFile(_) => ops::RemoveFile::remove(destination, &path).map_err(|err| write_error(err.into())),
+        Action::RemoveDir(_) => ops::RemoveDir::remove(destination, &path).map_err(|err| write_error(err.into())),
+    }
+}
+
+#[cfg(test)]
+mod mirror {
+    use ::{
+        filesystem_provider_api::{fs::FileSystem as _, provider::make::Make as _},
+        std::path::PathBuf,
+    };
+
+    use crate::{
+        diff::{self, Compare},
+        hash::Algorithm,
+        sync::{self, Action, Options},
+    };
+
+    fn source() -> Result<mktemp::Temp, Box<dyn std::error::Error>> {
+        let temp = mktemp::Temp::new_dir()?;
+        std::fs::create_dir_all(temp.join("a").join("b"))?;
+        std::fs::write(temp.join("a").join("x.txt"), b"x")?;
+        std::fs::write(temp.join("a").join("b").join("y.txt"), b"y")?;
+        std::fs::write(temp.join("swap"), b"file")?;
+        Ok(temp)
+    }
+
+    #[test]
+    fn disk_to_cas() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = source()?;
+        let source = filesystem_provider_impl_disk::provider::Provider::make(temp.to_path_buf());
+        let temp = mktemp::Temp::new_dir()?;
+        let destination = filesystem_provider_impl_cas::provider::Provider::make(temp.to_path_buf());
+
+        let options = Options {
+            compare: Compare::Content(Algorithm::Sha256),
+            dry_run: true,
+            delete: true,
+        };
+        let actions = sync::sync(&source, ".", &destination, ".", &options)?;
+        assert_eq!(actions.len(), 5);
+        assert!(!destination.exists("a"));
+
+        let options = Options {
+            dry_run: false,
+            ..options
+        };
+        assert_eq!(sync::sync(&source, ".", &destination, ".", &options)?, actions);
+        let diff_options = diff::Options {
+            compare: options.compare,
+        };
+        assert!(diff::diff(&source, ".", &destination, ".", &diff_options)?.is_empty());
+        assert!(sync::sync(&source, ".", &destination, ".", &options)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn delete_extraneous() -> Result<(), Box<dyn std::error::Error>> {
+        let from = source()?;
+        let source = filesystem_provider_impl_disk::provider::Provider::make(from.to_path_buf());
+        let to = mktemp::Temp::new_dir()?;
+        let destination = filesystem_provider_impl_disk::provider::Provider::make(to.to_path_buf());
+
+        std::fs::create_dir_all(to.join("extra").join("deep"))?;
+        std::fs::create_dir_all(to.join("swap").join("inner"))?;
+        std::fs::write(to.join("extra").join("deep").join("z.txt"), b"z")?;
+
+        let path = |p: &str| p.split('/').collect::<PathBuf>();
+        let options = Options::default();
+        let actions = sync::sync(&source, ".", &destination, ".", &options)?;
+        assert_eq!(
+            actions,
+            vec![
+                Action::CreateDir(path("a")),
+                Action::CreateDir(path("a/b")),
+                Action::CopyFile(path("a/b/y.txt")),
+                Action::CopyFile(path("a/x.txt")),
+                Action::RemoveDir(path("swap")),
+                Action::CopyFile(path("swap")),
+            ]
+        );
+        assert!(to.join("extra").join("deep").join("z.txt").is_file());
+        assert!(to.join("swap").is_file());
+
+        let options = Options {
+            delete: true,
+            ..options
+        };
+        let actions = sync::sync(&source, ".", &destination, ".", &options)?;
+        assert_eq!(actions, vec![Action::RemoveDir(path("extra"))]);
+        assert!(!to.join("extra").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn converge() -> Result<(), Box<dyn std::error::Error>> {
+        let from = source()?;
+        let source = filesystem_provider_impl_disk::provider::Provider::make(from.to_path_buf());
+        let to = mktemp::Temp::new_dir()?;
+        let destination = filesystem_provider_impl_disk::provider::Provider::make(to.to_path_buf());
+
+        let options = Options {
+            compare: Compare::Mtime,
+            ..Options::default()
+        };
+        assert!(matches!(
+            sync::sync(&source, ".", &destination, ".", &options),
+            Err(sync::Error::CompareError(Compare::Mtime))
+        ));
+        assert!(!to.join("a").exists());
+
+        for compare in [Compare::Size, Compare::Content(Algorithm::Blake3)] {
+            let options = Options {
+                compare,
+                ..Options::default()
+            };
+            sync::sync(&source, ".", &destination, ".", &options)?;
+            assert!(sync::sync(&source, ".", &destination, ".", &options)?.is_empty());
+        }
+        Ok(())
+    }
+}